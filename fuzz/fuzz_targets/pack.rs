@@ -0,0 +1,18 @@
+#![no_main]
+use std::io::{Cursor, Read};
+
+use libfuzzer_sys::fuzz_target;
+use rqs::fs::pack::Pack;
+
+fuzz_target!(|data: &[u8]| {
+    let pack = match Pack::from_reader(Cursor::new(data), "fuzz.pak".into()) {
+        Ok(pack) => pack,
+        Err(_) => return,
+    };
+    // Every file that the pack lists must be within the data.
+    for info in pack.file_infos() {
+        let start = info.offset as usize;
+        let mut contents = Vec::new();
+        (&data[start..start + info.size]).read_to_end(&mut contents).unwrap();
+    }
+});
@@ -0,0 +1,20 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use rqs::wad::Wad;
+
+fuzz_target!(|data: &[u8]| {
+    let wad = match Wad::from_bytes(data.to_vec()) {
+        Ok(wad) => wad,
+        Err(_) => return,
+    };
+    for (i, lump) in wad.lump_infos().iter().enumerate() {
+        wad.data_for_lump_num(i).unwrap();
+        let name = lump.name();
+        let _ = wad.decompressed_lump(name);
+        let _ = wad.qpic(name);
+        let _ = wad.palette(name);
+        let _ = wad.miptex(name);
+    }
+    let _ = wad.data_for_lump_num(wad.lump_infos().len()).unwrap_err();
+    let _ = wad.conchars();
+});
@@ -0,0 +1,427 @@
+// Copyright (C) 1996-1997 Id Software, Inc.
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+//
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 59 Temple Place - Suite 330, Boston, MA  02111-1307, USA.
+
+// Modified by Adrian Chan, March 2018
+// The on-disk structures are from bspfile.h
+
+//! Typed versions of the data stored in BSP lumps.
+//!
+//! The indices and bounds are stored with the widest types used by any of the
+//! BSP formats, so that every format loads into the same structures.
+
+use byteorder::{ByteOrder, LittleEndian};
+
+/// The number of collision hulls in each model.
+pub const MAX_MAP_HULLS: usize = 4;
+/// The number of light styles that each face can have.
+pub const MAX_LIGHTMAPS: usize = 4;
+/// The number of ambient sound levels in each leaf.
+pub const NUM_AMBIENTS: usize = 4;
+
+
+/// A plane, used to split space in the BSP tree.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Plane {
+    /// The normal of the plane.
+    pub normal: [f32; 3],
+    /// The distance of the plane from the origin, along the normal.
+    pub dist: f32,
+    /// Which axis the plane is aligned to, if any.  0-2 are the X, Y and Z
+    /// axes, and 3-5 are planes that are closest to those axes.
+    pub kind: i32,
+}
+
+/// A node in the BSP tree that's used for drawing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Node {
+    /// The index of the plane that splits this node.
+    pub plane: u32,
+    /// The front and back children.  A child `n >= 0` is a node, and a
+    /// negative child is the leaf `-1 - n`.
+    pub children: [i32; 2],
+    /// The minimum corner of the bounding box.
+    pub mins: [f32; 3],
+    /// The maximum corner of the bounding box.
+    pub maxs: [f32; 3],
+    /// The index of the first face in the node.
+    pub first_face: u32,
+    /// The number of faces in the node.
+    pub num_faces: u32,
+}
+
+/// A node in one of the BSP trees that's used for collision.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClipNode {
+    /// The index of the plane that splits this node.
+    pub plane: u32,
+    /// The front and back children.  A child `n >= 0` is a clip node, and a
+    /// negative child is the contents of that side, such as `CONTENTS_SOLID`.
+    pub children: [i32; 2],
+}
+
+/// How a texture is mapped onto a face.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TexInfo {
+    /// The S and T texture axes.  Each is `[x, y, z, offset]`.
+    pub vecs: [[f32; 4]; 2],
+    /// The index of the texture.
+    pub miptex: u32,
+    /// `TEX_SPECIAL` is set for sky and liquid textures, which aren't
+    /// lightmapped.
+    pub flags: u32,
+}
+
+/// `TexInfo::flags` bit for textures that aren't lightmapped.
+pub const TEX_SPECIAL: u32 = 1;
+
+/// A polygon on the surface of the map.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Face {
+    /// The index of the plane that the face lies on.
+    pub plane: u32,
+    /// Is the face on the back side of the plane?
+    pub side: bool,
+    /// The index of the first surfedge of the face.
+    pub first_edge: u32,
+    /// The number of surfedges (and vertexes) in the face.
+    pub num_edges: u32,
+    /// The index of the face's `TexInfo`.
+    pub texinfo: u32,
+    /// The light styles of each lightmap.  255 means there is no lightmap.
+    pub styles: [u8; MAX_LIGHTMAPS],
+    /// The offset of the face's lightmaps in the lighting lump, if it has
+    /// any.
+    pub light_offset: Option<usize>,
+}
+
+/// A leaf of the BSP tree: a convex region of space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Leaf {
+    /// What the leaf contains, such as `CONTENTS_EMPTY` or `CONTENTS_WATER`.
+    pub contents: i32,
+    /// The offset of the leaf's visibility data in the visibility lump, if it
+    /// has any.
+    pub vis_offset: Option<usize>,
+    /// The minimum corner of the bounding box.
+    pub mins: [f32; 3],
+    /// The maximum corner of the bounding box.
+    pub maxs: [f32; 3],
+    /// The index of the first marksurface of the leaf.
+    pub first_mark_surface: u32,
+    /// The number of marksurfaces in the leaf.
+    pub num_mark_surfaces: u32,
+    /// The volume of each ambient sound in the leaf.
+    pub ambient_level: [u8; NUM_AMBIENTS],
+}
+
+/// A model made from part of the BSP.  Model 0 is the world, and the others
+/// are brush entities such as doors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Model {
+    /// The minimum corner of the bounding box.
+    pub mins: [f32; 3],
+    /// The maximum corner of the bounding box.
+    pub maxs: [f32; 3],
+    /// The origin of the model.
+    pub origin: [f32; 3],
+    /// The head node of each hull.  Hull 0 is a `Node` tree, and the others
+    /// are `ClipNode` trees.
+    pub head_nodes: [i32; MAX_MAP_HULLS],
+    /// The number of visible leafs in the model, not counting leaf 0.
+    pub vis_leafs: u32,
+    /// The index of the first face of the model.
+    pub first_face: u32,
+    /// The number of faces in the model.
+    pub num_faces: u32,
+}
+
+/// Leaf contents, and clip node children, for empty space.
+pub const CONTENTS_EMPTY: i32 = -1;
+/// Leaf contents, and clip node children, for solid space.
+pub const CONTENTS_SOLID: i32 = -2;
+/// Leaf contents for water.
+pub const CONTENTS_WATER: i32 = -3;
+/// Leaf contents for slime.
+pub const CONTENTS_SLIME: i32 = -4;
+/// Leaf contents for lava.
+pub const CONTENTS_LAVA: i32 = -5;
+/// Leaf contents for the sky.
+pub const CONTENTS_SKY: i32 = -6;
+
+
+fn read_vec3(data: &[u8]) -> [f32; 3] {
+    [LittleEndian::read_f32(&data[0..4]),
+     LittleEndian::read_f32(&data[4..8]),
+     LittleEndian::read_f32(&data[8..12])]
+}
+
+fn read_short_vec3(data: &[u8]) -> [f32; 3] {
+    [f32::from(LittleEndian::read_i16(&data[0..2])),
+     f32::from(LittleEndian::read_i16(&data[2..4])),
+     f32::from(LittleEndian::read_i16(&data[4..6]))]
+}
+
+/// Read an offset where -1 means "none".  Other negative offsets are also
+/// treated as none.
+fn read_offset(data: &[u8]) -> Option<usize> {
+    let offset = LittleEndian::read_i32(data);
+    if offset < 0 { None } else { Some(offset as usize) }
+}
+
+/// Parsing the items in each lump of a version 29 BSP.
+///
+/// Each function is given exactly one item's worth of data.
+pub(super) mod v29 {
+    use super::*;
+
+    /// Size of a `dplane_t`.
+    pub const PLANE_SIZE: usize = 20;
+    /// Size of a `dvertex_t`.
+    pub const VERTEX_SIZE: usize = 12;
+    /// Size of a `dnode_t`.
+    pub const NODE_SIZE: usize = 24;
+    /// Size of a `texinfo_t`.
+    pub const TEXINFO_SIZE: usize = 40;
+    /// Size of a `dface_t`.
+    pub const FACE_SIZE: usize = 20;
+    /// Size of a `dclipnode_t`.
+    pub const CLIPNODE_SIZE: usize = 8;
+    /// Size of a `dleaf_t`.
+    pub const LEAF_SIZE: usize = 28;
+    /// Size of a marksurface.
+    pub const MARKSURFACE_SIZE: usize = 2;
+    /// Size of a `dedge_t`.
+    pub const EDGE_SIZE: usize = 4;
+    /// Size of a surfedge.
+    pub const SURFEDGE_SIZE: usize = 4;
+    /// Size of a `dmodel_t`.
+    pub const MODEL_SIZE: usize = 64;
+
+    pub fn plane(data: &[u8]) -> Plane {
+        Plane {
+            normal: read_vec3(&data[0..12]),
+            dist: LittleEndian::read_f32(&data[12..16]),
+            kind: LittleEndian::read_i32(&data[16..20]),
+        }
+    }
+
+    pub fn vertex(data: &[u8]) -> [f32; 3] {
+        read_vec3(data)
+    }
+
+    pub fn node(data: &[u8]) -> Node {
+        Node {
+            plane: LittleEndian::read_i32(&data[0..4]) as u32,
+            children: [i32::from(LittleEndian::read_i16(&data[4..6])),
+                       i32::from(LittleEndian::read_i16(&data[6..8]))],
+            mins: read_short_vec3(&data[8..14]),
+            maxs: read_short_vec3(&data[14..20]),
+            first_face: u32::from(LittleEndian::read_u16(&data[20..22])),
+            num_faces: u32::from(LittleEndian::read_u16(&data[22..24])),
+        }
+    }
+
+    pub fn texinfo(data: &[u8]) -> TexInfo {
+        let mut vecs = [[0.0; 4]; 2];
+        for (i, v) in vecs.iter_mut().flat_map(|v| v.iter_mut()).enumerate() {
+            *v = LittleEndian::read_f32(&data[i*4..i*4+4]);
+        }
+        TexInfo {
+            vecs,
+            miptex: LittleEndian::read_i32(&data[32..36]) as u32,
+            flags: LittleEndian::read_i32(&data[36..40]) as u32,
+        }
+    }
+
+    pub fn face(data: &[u8]) -> Face {
+        let mut styles = [0; MAX_LIGHTMAPS];
+        styles.copy_from_slice(&data[12..16]);
+        Face {
+            plane: u32::from(LittleEndian::read_u16(&data[0..2])),
+            side: LittleEndian::read_u16(&data[2..4]) != 0,
+            first_edge: LittleEndian::read_i32(&data[4..8]) as u32,
+            num_edges: u32::from(LittleEndian::read_u16(&data[8..10])),
+            texinfo: u32::from(LittleEndian::read_u16(&data[10..12])),
+            styles,
+            light_offset: read_offset(&data[16..20]),
+        }
+    }
+
+    pub fn clipnode(data: &[u8]) -> ClipNode {
+        ClipNode {
+            plane: LittleEndian::read_i32(&data[0..4]) as u32,
+            children: [i32::from(LittleEndian::read_i16(&data[4..6])),
+                       i32::from(LittleEndian::read_i16(&data[6..8]))],
+        }
+    }
+
+    pub fn leaf(data: &[u8]) -> Leaf {
+        let mut ambient_level = [0; NUM_AMBIENTS];
+        ambient_level.copy_from_slice(&data[24..28]);
+        Leaf {
+            contents: LittleEndian::read_i32(&data[0..4]),
+            vis_offset: read_offset(&data[4..8]),
+            mins: read_short_vec3(&data[8..14]),
+            maxs: read_short_vec3(&data[14..20]),
+            first_mark_surface:
+                u32::from(LittleEndian::read_u16(&data[20..22])),
+            num_mark_surfaces:
+                u32::from(LittleEndian::read_u16(&data[22..24])),
+            ambient_level,
+        }
+    }
+
+    pub fn mark_surface(data: &[u8]) -> u32 {
+        u32::from(LittleEndian::read_u16(data))
+    }
+
+    pub fn edge(data: &[u8]) -> [u32; 2] {
+        [u32::from(LittleEndian::read_u16(&data[0..2])),
+         u32::from(LittleEndian::read_u16(&data[2..4]))]
+    }
+
+    pub fn surf_edge(data: &[u8]) -> i32 {
+        LittleEndian::read_i32(data)
+    }
+
+    pub fn model(data: &[u8]) -> Model {
+        let mut head_nodes = [0; MAX_MAP_HULLS];
+        LittleEndian::read_i32_into(&data[36..52], &mut head_nodes);
+        Model {
+            mins: read_vec3(&data[0..12]),
+            maxs: read_vec3(&data[12..24]),
+            origin: read_vec3(&data[24..36]),
+            head_nodes,
+            vis_leafs: LittleEndian::read_i32(&data[52..56]) as u32,
+            first_face: LittleEndian::read_i32(&data[56..60]) as u32,
+            num_faces: LittleEndian::read_i32(&data[60..64]) as u32,
+        }
+    }
+}
+
+/// Parsing the items in the lumps of a BSP2 or 2PSB file, where they differ
+/// from a version 29 BSP.
+///
+/// Both formats widen the indices in nodes, faces, clip nodes, leafs,
+/// marksurfaces and edges to 32 bits.  BSP2 also stores the bounds of nodes
+/// and leafs as floats, whereas 2PSB keeps them as shorts.
+pub(super) mod bsp2 {
+    use super::*;
+
+    /// Size of a BSP2 `dnode_t`.
+    pub const NODE_SIZE: usize = 44;
+    /// Size of a 2PSB `dnode_t`.
+    pub const RMQ_NODE_SIZE: usize = 32;
+    /// Size of a BSP2 `dface_t`.
+    pub const FACE_SIZE: usize = 28;
+    /// Size of a BSP2 `dclipnode_t`.
+    pub const CLIPNODE_SIZE: usize = 12;
+    /// Size of a BSP2 `dleaf_t`.
+    pub const LEAF_SIZE: usize = 44;
+    /// Size of a 2PSB `dleaf_t`.
+    pub const RMQ_LEAF_SIZE: usize = 32;
+    /// Size of a BSP2 marksurface.
+    pub const MARKSURFACE_SIZE: usize = 4;
+    /// Size of a BSP2 `dedge_t`.
+    pub const EDGE_SIZE: usize = 8;
+
+    fn children(data: &[u8]) -> [i32; 2] {
+        [LittleEndian::read_i32(&data[0..4]),
+         LittleEndian::read_i32(&data[4..8])]
+    }
+
+    pub fn node(data: &[u8]) -> Node {
+        Node {
+            plane: LittleEndian::read_u32(&data[0..4]),
+            children: children(&data[4..12]),
+            mins: read_vec3(&data[12..24]),
+            maxs: read_vec3(&data[24..36]),
+            first_face: LittleEndian::read_u32(&data[36..40]),
+            num_faces: LittleEndian::read_u32(&data[40..44]),
+        }
+    }
+
+    pub fn rmq_node(data: &[u8]) -> Node {
+        Node {
+            plane: LittleEndian::read_u32(&data[0..4]),
+            children: children(&data[4..12]),
+            mins: read_short_vec3(&data[12..18]),
+            maxs: read_short_vec3(&data[18..24]),
+            first_face: LittleEndian::read_u32(&data[24..28]),
+            num_faces: LittleEndian::read_u32(&data[28..32]),
+        }
+    }
+
+    pub fn face(data: &[u8]) -> Face {
+        let mut styles = [0; MAX_LIGHTMAPS];
+        styles.copy_from_slice(&data[20..24]);
+        Face {
+            plane: LittleEndian::read_u32(&data[0..4]),
+            side: LittleEndian::read_u32(&data[4..8]) != 0,
+            first_edge: LittleEndian::read_u32(&data[8..12]),
+            num_edges: LittleEndian::read_u32(&data[12..16]),
+            texinfo: LittleEndian::read_u32(&data[16..20]),
+            styles,
+            light_offset: read_offset(&data[24..28]),
+        }
+    }
+
+    pub fn clipnode(data: &[u8]) -> ClipNode {
+        ClipNode {
+            plane: LittleEndian::read_u32(&data[0..4]),
+            children: children(&data[4..12]),
+        }
+    }
+
+    fn leaf_with_bounds(data: &[u8], mins: [f32; 3], maxs: [f32; 3],
+                        marks_at: usize)
+        -> Leaf
+    {
+        let mut ambient_level = [0; NUM_AMBIENTS];
+        ambient_level.copy_from_slice(&data[marks_at+8..marks_at+12]);
+        Leaf {
+            contents: LittleEndian::read_i32(&data[0..4]),
+            vis_offset: read_offset(&data[4..8]),
+            mins,
+            maxs,
+            first_mark_surface:
+                LittleEndian::read_u32(&data[marks_at..marks_at+4]),
+            num_mark_surfaces:
+                LittleEndian::read_u32(&data[marks_at+4..marks_at+8]),
+            ambient_level,
+        }
+    }
+
+    pub fn leaf(data: &[u8]) -> Leaf {
+        leaf_with_bounds(data, read_vec3(&data[8..20]),
+                         read_vec3(&data[20..32]), 32)
+    }
+
+    pub fn rmq_leaf(data: &[u8]) -> Leaf {
+        leaf_with_bounds(data, read_short_vec3(&data[8..14]),
+                         read_short_vec3(&data[14..20]), 20)
+    }
+
+    pub fn mark_surface(data: &[u8]) -> u32 {
+        LittleEndian::read_u32(data)
+    }
+
+    pub fn edge(data: &[u8]) -> [u32; 2] {
+        [LittleEndian::read_u32(&data[0..4]),
+         LittleEndian::read_u32(&data[4..8])]
+    }
+}
@@ -0,0 +1,961 @@
+// Copyright (C) 1996-1997 Id Software, Inc.
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+//
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 59 Temple Place - Suite 330, Boston, MA  02111-1307, USA.
+
+// Modified by Adrian Chan, March 2018
+// Based on bspfile.h and the Mod_LoadBrushModel parts of model.c
+
+//! Loading maps from BSP files.
+//!
+//! A BSP file has a header that gives the position of 15 lumps, each holding
+//! one kind of map data.  Loading checks that every lump is within the file,
+//! that it holds a whole number of items, and that the indices stored in each
+//! item refer to items that exist.
+//!
+//! As well as Quake's version 29 BSPs, the BSP2 and 2PSB formats used by
+//! large community maps are supported; see `Format`.
+
+use byteorder::{ByteOrder, LittleEndian};
+use failure::Error;
+
+use std::error;
+use std::fmt;
+use std::ops::Range;
+
+use fs::FileSys;
+use try_from_temp::TryFromTemp;
+use wad::MipTex;
+
+pub mod lumps;
+pub use self::lumps::{ClipNode, Face, Leaf, Model, Node, Plane, TexInfo};
+use self::lumps::{bsp2, v29};
+
+/// The BSP version used by Quake.
+pub const BSP_VERSION: i32 = 29;
+/// The version field of a BSP2 file, which holds the text `BSP2`.
+pub const BSP2_VERSION: i32 =
+    b'B' as i32 | (b'S' as i32) << 8 | (b'P' as i32) << 16
+    | (b'2' as i32) << 24;
+/// The version field of a 2PSB file, which holds the text `2PSB`.
+pub const BSP2_RMQ_VERSION: i32 =
+    b'2' as i32 | (b'P' as i32) << 8 | (b'S' as i32) << 16
+    | (b'B' as i32) << 24;
+/// The number of lumps in a BSP file.
+pub const NUM_LUMPS: usize = 15;
+/// Size of the BSP header: a version followed by an offset and size for each
+/// lump.
+pub const HEADER_SIZE: usize = 4 + NUM_LUMPS*8;
+
+
+/// The formats of BSP file that can be loaded.
+///
+/// Every format has the same header and lumps, but BSP2 and 2PSB use wider
+/// types for some of the items, so that maps can be larger.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Version 29, as used by Quake.
+    Bsp29,
+    /// `2PSB`, the first extended format, from the RMQ project.  Indices are
+    /// 32 bits, but node and leaf bounds are still shorts.
+    Bsp2Rmq,
+    /// `BSP2`.  Indices are 32 bits, and node and leaf bounds are floats.
+    Bsp2,
+}
+
+impl Format {
+    /// The format with the given version field, if there is one.
+    pub fn from_version(version: i32) -> Option<Self> {
+        match version {
+            BSP_VERSION => Some(Format::Bsp29),
+            BSP2_RMQ_VERSION => Some(Format::Bsp2Rmq),
+            BSP2_VERSION => Some(Format::Bsp2),
+            _ => None,
+        }
+    }
+}
+
+/// The lumps in a BSP file, in the order they appear in the header.
+#[allow(missing_docs)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Lump {
+    Entities,
+    Planes,
+    Textures,
+    Vertexes,
+    Visibility,
+    Nodes,
+    TexInfo,
+    Faces,
+    Lighting,
+    ClipNodes,
+    Leafs,
+    MarkSurfaces,
+    Edges,
+    SurfEdges,
+    Models,
+}
+
+impl Lump {
+    /// All of the lumps, in header order.
+    pub const ALL: [Lump; NUM_LUMPS] = [
+        Lump::Entities, Lump::Planes, Lump::Textures, Lump::Vertexes,
+        Lump::Visibility, Lump::Nodes, Lump::TexInfo, Lump::Faces,
+        Lump::Lighting, Lump::ClipNodes, Lump::Leafs, Lump::MarkSurfaces,
+        Lump::Edges, Lump::SurfEdges, Lump::Models,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Lump::Entities => "entities",
+            Lump::Planes => "planes",
+            Lump::Textures => "textures",
+            Lump::Vertexes => "vertexes",
+            Lump::Visibility => "visibility",
+            Lump::Nodes => "nodes",
+            Lump::TexInfo => "texinfo",
+            Lump::Faces => "faces",
+            Lump::Lighting => "lighting",
+            Lump::ClipNodes => "clipnodes",
+            Lump::Leafs => "leafs",
+            Lump::MarkSurfaces => "marksurfaces",
+            Lump::Edges => "edges",
+            Lump::SurfEdges => "surfedges",
+            Lump::Models => "models",
+        }
+    }
+}
+
+impl fmt::Display for Lump {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A map, loaded from a BSP file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bsp {
+    /// The format of the file that the map was loaded from.
+    pub format: Format,
+    /// The entity definitions, as text.
+    pub entities: String,
+    /// The planes used by nodes, clip nodes and faces.
+    pub planes: Vec<Plane>,
+    /// The textures.  A texture may be missing from the file, in which case
+    /// it's `None`.
+    pub textures: Vec<Option<MipTex>>,
+    /// The vertex positions.
+    pub vertexes: Vec<[f32; 3]>,
+    /// The compressed potentially visible sets of the leafs.
+    pub visibility: Vec<u8>,
+    /// The drawing BSP tree.
+    pub nodes: Vec<Node>,
+    /// How textures are mapped onto faces.
+    pub texinfo: Vec<TexInfo>,
+    /// The faces.
+    pub faces: Vec<Face>,
+    /// The lightmaps of the faces.
+    pub lighting: Vec<u8>,
+    /// The collision BSP trees.
+    pub clip_nodes: Vec<ClipNode>,
+    /// The leafs of the drawing BSP tree.
+    pub leafs: Vec<Leaf>,
+    /// Indices of faces, used by leafs to list the faces inside them.
+    pub mark_surfaces: Vec<u32>,
+    /// Pairs of vertex indices.
+    pub edges: Vec<[u32; 2]>,
+    /// Indices of edges, used by faces to list their edges.  A negative
+    /// surfedge `-e` means edge `e` is used in reverse.
+    pub surf_edges: Vec<i32>,
+    /// The models.  Model 0 is the world.
+    pub models: Vec<Model>,
+}
+
+impl Bsp {
+    /// Load a map, such as `maps/e1m1.bsp`, from the file system.
+    ///
+    /// Returns `None` if the file doesn't exist.
+    pub fn load(fs: &FileSys, name: &str) -> Result<Option<Self>, Error> {
+        fs.load_parsed(name, Self::from_bytes)
+    }
+
+    /// Parse and validate a map from the contents of a BSP file.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, BspError> {
+        if data.len() < HEADER_SIZE {
+            return Err(BspError::OutOfBounds { at: 0, len: HEADER_SIZE });
+        }
+        let version = LittleEndian::read_i32(&data[0..4]);
+        let format = Format::from_version(version)
+            .ok_or(BspError::BadVersion(version))?;
+
+        let mut ranges = Vec::with_capacity(NUM_LUMPS);
+        for &lump in &Lump::ALL {
+            ranges.push(lump_range(data, lump)?);
+        }
+        let lump_data = |lump: Lump| &data[ranges[lump as usize].clone()];
+        let items = |lump: Lump, size: usize| {
+            Items { data: lump_data(lump), at: ranges[lump as usize].start,
+                    lump, size }
+        };
+
+        let (nodes, leafs) = match format {
+            Format::Bsp29 => (
+                items(Lump::Nodes, v29::NODE_SIZE).parse(v29::node)?,
+                items(Lump::Leafs, v29::LEAF_SIZE).parse(v29::leaf)?),
+            Format::Bsp2Rmq => (
+                items(Lump::Nodes, bsp2::RMQ_NODE_SIZE).parse(bsp2::rmq_node)?,
+                items(Lump::Leafs, bsp2::RMQ_LEAF_SIZE).parse(bsp2::rmq_leaf)?),
+            Format::Bsp2 => (
+                items(Lump::Nodes, bsp2::NODE_SIZE).parse(bsp2::node)?,
+                items(Lump::Leafs, bsp2::LEAF_SIZE).parse(bsp2::leaf)?),
+        };
+        let (faces, clip_nodes, mark_surfaces, edges) = match format {
+            Format::Bsp29 => (
+                items(Lump::Faces, v29::FACE_SIZE).parse(v29::face)?,
+                items(Lump::ClipNodes, v29::CLIPNODE_SIZE)
+                    .parse(v29::clipnode)?,
+                items(Lump::MarkSurfaces, v29::MARKSURFACE_SIZE)
+                    .parse(v29::mark_surface)?,
+                items(Lump::Edges, v29::EDGE_SIZE).parse(v29::edge)?),
+            Format::Bsp2Rmq | Format::Bsp2 => (
+                items(Lump::Faces, bsp2::FACE_SIZE).parse(bsp2::face)?,
+                items(Lump::ClipNodes, bsp2::CLIPNODE_SIZE)
+                    .parse(bsp2::clipnode)?,
+                items(Lump::MarkSurfaces, bsp2::MARKSURFACE_SIZE)
+                    .parse(bsp2::mark_surface)?,
+                items(Lump::Edges, bsp2::EDGE_SIZE).parse(bsp2::edge)?),
+        };
+
+        let bsp = Bsp {
+            format,
+            entities: entities(lump_data(Lump::Entities)),
+            planes: items(Lump::Planes, v29::PLANE_SIZE).parse(v29::plane)?,
+            textures: textures(lump_data(Lump::Textures),
+                               ranges[Lump::Textures as usize].start)?,
+            vertexes: items(Lump::Vertexes, v29::VERTEX_SIZE)
+                .parse(v29::vertex)?,
+            visibility: lump_data(Lump::Visibility).to_vec(),
+            nodes,
+            texinfo: items(Lump::TexInfo, v29::TEXINFO_SIZE)
+                .parse(v29::texinfo)?,
+            faces,
+            lighting: lump_data(Lump::Lighting).to_vec(),
+            clip_nodes,
+            leafs,
+            mark_surfaces,
+            edges,
+            surf_edges: items(Lump::SurfEdges, v29::SURFEDGE_SIZE)
+                .parse(v29::surf_edge)?,
+            models: items(Lump::Models, v29::MODEL_SIZE).parse(v29::model)?,
+        };
+        bsp.validate()?;
+        Ok(bsp)
+    }
+
+    /// Check that every index refers to something that exists.
+    fn validate(&self) -> Result<(), BspError> {
+        if self.models.is_empty() {
+            return Err(BspError::NoModels);
+        }
+
+        for (i, node) in self.nodes.iter().enumerate() {
+            let check = |field, value: i64, len| {
+                check_index(Lump::Nodes, i, field, value, len)
+            };
+            check("plane", node.plane.into(), self.planes.len())?;
+            for &child in &node.children {
+                self.check_node_child(Lump::Nodes, i, child)?;
+            }
+            check_range(Lump::Nodes, i, "faces", node.first_face,
+                        node.num_faces, self.faces.len())?;
+        }
+
+        for (i, node) in self.clip_nodes.iter().enumerate() {
+            check_index(Lump::ClipNodes, i, "plane", node.plane.into(),
+                        self.planes.len())?;
+            for &child in node.children.iter().filter(|&&c| c >= 0) {
+                check_index(Lump::ClipNodes, i, "child", child.into(),
+                            self.clip_nodes.len())?;
+            }
+        }
+
+        for (i, texinfo) in self.texinfo.iter().enumerate() {
+            // Quake allows a map with no textures at all.
+            if !self.textures.is_empty() {
+                check_index(Lump::TexInfo, i, "miptex", texinfo.miptex.into(),
+                            self.textures.len())?;
+            }
+        }
+
+        for (i, face) in self.faces.iter().enumerate() {
+            let check = |field, value: u32, len| {
+                check_index(Lump::Faces, i, field, value.into(), len)
+            };
+            check("plane", face.plane, self.planes.len())?;
+            check("texinfo", face.texinfo, self.texinfo.len())?;
+            check_range(Lump::Faces, i, "edges", face.first_edge,
+                        face.num_edges, self.surf_edges.len())?;
+            if let Some(offset) = face.light_offset {
+                check_index(Lump::Faces, i, "light offset", offset as i64,
+                            self.lighting.len())?;
+            }
+        }
+
+        for (i, leaf) in self.leafs.iter().enumerate() {
+            if let Some(offset) = leaf.vis_offset {
+                check_index(Lump::Leafs, i, "vis offset", offset as i64,
+                            self.visibility.len())?;
+            }
+            check_range(Lump::Leafs, i, "marksurfaces",
+                        leaf.first_mark_surface, leaf.num_mark_surfaces,
+                        self.mark_surfaces.len())?;
+        }
+
+        for (i, &face) in self.mark_surfaces.iter().enumerate() {
+            check_index(Lump::MarkSurfaces, i, "face", face.into(),
+                        self.faces.len())?;
+        }
+
+        for (i, edge) in self.edges.iter().enumerate() {
+            for &vertex in edge {
+                check_index(Lump::Edges, i, "vertex", vertex.into(),
+                            self.vertexes.len())?;
+            }
+        }
+
+        for (i, &edge) in self.surf_edges.iter().enumerate() {
+            check_index(Lump::SurfEdges, i, "edge",
+                        i64::from(edge).abs(), self.edges.len())?;
+        }
+
+        for (i, model) in self.models.iter().enumerate() {
+            self.check_node_child(Lump::Models, i, model.head_nodes[0])?;
+            // Hulls 1 and 2 are used for collision.  Hull 3 is unused, and
+            // its head node isn't always valid.
+            for &head in model.head_nodes[1..3].iter().filter(|&&h| h >= 0) {
+                check_index(Lump::Models, i, "clip node", head.into(),
+                            self.clip_nodes.len())?;
+            }
+            check_range(Lump::Models, i, "faces", model.first_face,
+                        model.num_faces, self.faces.len())?;
+        }
+
+        Ok(())
+    }
+
+    /// Check a reference to a node (`n >= 0`) or a leaf (`-1 - n`).
+    fn check_node_child(&self, lump: Lump, i: usize, child: i32)
+        -> Result<(), BspError>
+    {
+        if child >= 0 {
+            check_index(lump, i, "node", child.into(), self.nodes.len())
+        } else {
+            check_index(lump, i, "leaf", -1 - i64::from(child),
+                        self.leafs.len())
+        }
+    }
+}
+
+/// The items in one lump, which are all `size` bytes long.
+struct Items<'a> {
+    data: &'a [u8],
+    at: usize,
+    lump: Lump,
+    size: usize,
+}
+
+impl<'a> Items<'a> {
+    fn parse<T, F>(&self, parse_item: F) -> Result<Vec<T>, BspError>
+        where F: Fn(&[u8]) -> T
+    {
+        if !self.data.len().is_multiple_of(self.size) {
+            return Err(BspError::BadLumpSize {
+                lump: self.lump,
+                at: self.at,
+                len: self.data.len(),
+                item_size: self.size,
+            });
+        }
+        Ok(self.data.chunks(self.size).map(parse_item).collect())
+    }
+}
+
+/// Read the position of a lump from the header, and check that it's within
+/// the file.
+fn lump_range(data: &[u8], lump: Lump) -> Result<Range<usize>, BspError> {
+    let at = 4 + lump as usize * 8;
+    let value = LittleEndian::read_i32(&data[at..at+4]);
+    let offset = usize::try_from_temp(value)
+        .map_err(|_| BspError::NegativeOffset { lump, at, value })?;
+    let value = LittleEndian::read_i32(&data[at+4..at+8]);
+    let len = usize::try_from_temp(value)
+        .map_err(|_| BspError::NegativeSize { lump, at: at + 4, value })?;
+    if offset.checked_add(len).is_none_or(|end| end > data.len()) {
+        return Err(BspError::OutOfBounds { at: offset, len });
+    }
+    Ok(offset..offset + len)
+}
+
+/// The entities are text, which may be nul terminated.
+fn entities(data: &[u8]) -> String {
+    let len = data.iter().position(|&c| c == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..len]).into_owned()
+}
+
+/// Parse the textures lump: a count, followed by the offset of each texture
+/// from the start of the lump.  An offset of -1 means the texture is missing.
+///
+/// `at` is the offset of the lump in the file.
+fn textures(data: &[u8], at: usize) -> Result<Vec<Option<MipTex>>, BspError> {
+    if data.is_empty() {
+        return Ok(Vec::new());
+    }
+    if data.len() < 4 {
+        return Err(BspError::OutOfBounds { at, len: 4 });
+    }
+    let value = LittleEndian::read_i32(&data[0..4]);
+    let count = usize::try_from_temp(value)
+        .map_err(|_| BspError::NegativeCount {
+            lump: Lump::Textures, at, value })?;
+    let table_len = 4 + count * 4;
+    if table_len > data.len() {
+        return Err(BspError::OutOfBounds { at, len: table_len });
+    }
+
+    let mut textures = Vec::with_capacity(count);
+    for (index, entry) in data[4..table_len].chunks(4).enumerate() {
+        let offset = LittleEndian::read_i32(entry);
+        if offset == -1 {
+            textures.push(None);
+            continue;
+        }
+        if offset < 0 || offset as usize >= data.len() {
+            return Err(BspError::OutOfBounds {
+                at: at + 4 + index*4, len: 4 });
+        }
+        let texture = MipTex::from_bytes(&data[offset as usize..])
+            .map_err(|e| BspError::BadTexture {
+                index, error: e.to_string() })?;
+        textures.push(Some(texture));
+    }
+    Ok(textures)
+}
+
+fn check_index(lump: Lump, index: usize, field: &'static str, value: i64,
+               len: usize)
+    -> Result<(), BspError>
+{
+    if value < 0 || value >= len as i64 {
+        return Err(BspError::BadIndex { lump, index, field, value, len });
+    }
+    Ok(())
+}
+
+/// Check that `count` items starting at `first` are within `len`.
+fn check_range(lump: Lump, index: usize, field: &'static str, first: u32,
+               count: u32, len: usize)
+    -> Result<(), BspError>
+{
+    let end = i64::from(first) + i64::from(count);
+    if end > len as i64 {
+        return Err(BspError::BadIndex {
+            lump, index, field, value: end - 1, len });
+    }
+    Ok(())
+}
+
+/// The ways that loading a BSP can fail.
+///
+/// Errors about a value in the BSP header have `at`, the offset of that value
+/// within the file.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BspError {
+    /// The BSP isn't a version that we can load.  See `Format`.
+    BadVersion(i32),
+    /// Part of the BSP extends past the end of the data.
+    OutOfBounds {
+        /// The offset of the part.
+        at: usize,
+        /// The size of the part, in bytes.
+        len: usize,
+    },
+    /// A lump offset is negative.
+    NegativeOffset {
+        /// The lump.
+        lump: Lump,
+        /// Where the offset is stored.
+        at: usize,
+        /// The offset.
+        value: i32,
+    },
+    /// A lump size is negative.
+    NegativeSize {
+        /// The lump.
+        lump: Lump,
+        /// Where the size is stored.
+        at: usize,
+        /// The size.
+        value: i32,
+    },
+    /// A count of items is negative.
+    NegativeCount {
+        /// The lump.
+        lump: Lump,
+        /// Where the count is stored.
+        at: usize,
+        /// The count.
+        value: i32,
+    },
+    /// A lump's size isn't a multiple of the size of its items.
+    BadLumpSize {
+        /// The lump.
+        lump: Lump,
+        /// The offset of the lump.
+        at: usize,
+        /// The size of the lump, in bytes.
+        len: usize,
+        /// The size of each item, in bytes.
+        item_size: usize,
+    },
+    /// An item in a lump refers to something that doesn't exist.
+    BadIndex {
+        /// The lump that holds the item.
+        lump: Lump,
+        /// The index of the item in the lump.
+        index: usize,
+        /// What the item refers to.
+        field: &'static str,
+        /// The bad index or offset.
+        value: i64,
+        /// The number of things that the index could refer to.
+        len: usize,
+    },
+    /// A texture couldn't be parsed.
+    BadTexture {
+        /// The index of the texture.
+        index: usize,
+        /// Why the texture couldn't be parsed.
+        error: String,
+    },
+    /// There are no models, so there is no world.
+    NoModels,
+}
+
+impl fmt::Display for BspError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BspError::BadVersion(version) =>
+                write!(f, "BSP has wrong version number ({} should be {}, \
+                           BSP2 or 2PSB)", version, BSP_VERSION),
+            BspError::OutOfBounds { at, len } =>
+                write!(f, "{} bytes at offset {} are past the end of the BSP",
+                       len, at),
+            BspError::NegativeOffset { lump, at, value } =>
+                write!(f, "{} lump has negative offset {} (at {})",
+                       lump, value, at),
+            BspError::NegativeSize { lump, at, value } =>
+                write!(f, "{} lump has negative size {} (at {})",
+                       lump, value, at),
+            BspError::NegativeCount { lump, at, value } =>
+                write!(f, "{} lump has negative count {} (at {})",
+                       lump, value, at),
+            BspError::BadLumpSize { lump, at, len, item_size } =>
+                write!(f, "funny {} lump size {} at offset {}: not a \
+                           multiple of {}", lump, len, at, item_size),
+            BspError::BadIndex { lump, index, field, value, len } =>
+                write!(f, "{} {}: {} {} is out of range (there are {})",
+                       lump, index, field, value, len),
+            BspError::BadTexture { index, ref error } =>
+                write!(f, "texture {}: {}", index, error),
+            BspError::NoModels =>
+                write!(f, "BSP has no models"),
+        }
+    }
+}
+
+impl error::Error for BspError {}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::lumps::{CONTENTS_EMPTY, CONTENTS_SOLID, CONTENTS_WATER};
+    use byteorder::WriteBytesExt;
+    use std::io::Write;
+    use test_common::{self as common, write_floats, write_ints, write_shorts};
+
+    type LE = LittleEndian;
+
+    /// The lumps of a tiny map: one triangle, in one node that splits two
+    /// leafs.
+    fn map_lumps() -> Vec<Vec<u8>> {
+        let mut lumps = vec![Vec::new(); NUM_LUMPS];
+
+        lumps[Lump::Entities as usize] =
+            b"{\n\"classname\" \"worldspawn\"\n}\n\0".to_vec();
+
+        let planes = &mut lumps[Lump::Planes as usize];
+        write_floats(planes, &[0.0, 0.0, 1.0]);
+        planes.write_f32::<LE>(16.0).unwrap();
+        planes.write_i32::<LE>(2).unwrap();
+        write_floats(planes, &[1.0, 0.0, 0.0]);
+        planes.write_f32::<LE>(-8.0).unwrap();
+        planes.write_i32::<LE>(0).unwrap();
+
+        // One 8x8 texture, and one missing texture.
+        let textures = &mut lumps[Lump::Textures as usize];
+        write_ints(textures, &[2, 12, -1]);
+        textures.write_all(b"floor\0\0\0\0\0\0\0\0\0\0\0").unwrap();
+        write_ints(textures, &[8, 8, 40, 104, 120, 124]);
+        textures.write_all(&[7; 64 + 16 + 4 + 1]).unwrap();
+
+        let vertexes = &mut lumps[Lump::Vertexes as usize];
+        write_floats(vertexes, &[0.0, 0.0, 16.0]);
+        write_floats(vertexes, &[64.0, 0.0, 16.0]);
+        write_floats(vertexes, &[0.0, 64.0, 16.0]);
+
+        lumps[Lump::Visibility as usize] = vec![0xff, 0x00, 0x02, 0x00];
+
+        let nodes = &mut lumps[Lump::Nodes as usize];
+        write_ints(nodes, &[0]);
+        write_shorts(nodes, &[-1, -2, -8, -8, -8, 64, 64, 64]);
+        write_shorts(nodes, &[0, 1]);
+
+        let texinfo = &mut lumps[Lump::TexInfo as usize];
+        for &x in &[1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0] {
+            texinfo.write_f32::<LE>(x).unwrap();
+        }
+        write_ints(texinfo, &[0, 0]);
+
+        let faces = &mut lumps[Lump::Faces as usize];
+        write_shorts(faces, &[0, 1]);
+        write_ints(faces, &[0]);
+        write_shorts(faces, &[3, 0]);
+        faces.write_all(&[0, 255, 255, 255]).unwrap();
+        write_ints(faces, &[0]);
+
+        lumps[Lump::Lighting as usize] = vec![200; 16];
+
+        let clip_nodes = &mut lumps[Lump::ClipNodes as usize];
+        write_ints(clip_nodes, &[1]);
+        write_shorts(clip_nodes, &[CONTENTS_EMPTY as i16,
+                                   CONTENTS_SOLID as i16]);
+
+        let leafs = &mut lumps[Lump::Leafs as usize];
+        write_ints(leafs, &[CONTENTS_SOLID, -1]);
+        write_shorts(leafs, &[0; 8]);
+        leafs.write_all(&[0; 4]).unwrap();
+        write_ints(leafs, &[CONTENTS_WATER, 2]);
+        write_shorts(leafs, &[-8, -8, -8, 64, 64, 16, 0, 1]);
+        leafs.write_all(&[0, 10, 0, 255]).unwrap();
+
+        write_shorts(&mut lumps[Lump::MarkSurfaces as usize], &[0]);
+
+        write_shorts(&mut lumps[Lump::Edges as usize],
+                     &[0, 0, 0, 1, 1, 2, 2, 0]);
+
+        write_ints(&mut lumps[Lump::SurfEdges as usize], &[1, 2, -3]);
+
+        let models = &mut lumps[Lump::Models as usize];
+        write_floats(models, &[-8.0, -8.0, -8.0]);
+        write_floats(models, &[64.0, 64.0, 64.0]);
+        write_floats(models, &[0.0, 0.0, 0.0]);
+        write_ints(models, &[0, 0, 0, 0, 1, 0, 1]);
+
+        lumps
+    }
+
+    /// The lumps of the same map as `map_lumps()`, in BSP2 format, or in
+    /// 2PSB format if `rmq` is true.
+    fn bsp2_map_lumps(rmq: bool) -> Vec<Vec<u8>> {
+        let mut lumps = map_lumps();
+        let write_bounds = |data: &mut Vec<u8>, bounds: &[i16]| {
+            if rmq {
+                write_shorts(data, bounds);
+            } else {
+                for &x in bounds {
+                    data.write_f32::<LE>(x.into()).unwrap();
+                }
+            }
+        };
+
+        let nodes = &mut lumps[Lump::Nodes as usize];
+        nodes.clear();
+        write_ints(nodes, &[0, -1, -2]);
+        write_bounds(nodes, &[-8, -8, -8, 64, 64, 64]);
+        write_ints(nodes, &[0, 1]);
+
+        let faces = &mut lumps[Lump::Faces as usize];
+        faces.clear();
+        write_ints(faces, &[0, 1, 0, 3, 0]);
+        faces.write_all(&[0, 255, 255, 255]).unwrap();
+        write_ints(faces, &[0]);
+
+        let clip_nodes = &mut lumps[Lump::ClipNodes as usize];
+        clip_nodes.clear();
+        write_ints(clip_nodes, &[1, CONTENTS_EMPTY, CONTENTS_SOLID]);
+
+        let leafs = &mut lumps[Lump::Leafs as usize];
+        leafs.clear();
+        write_ints(leafs, &[CONTENTS_SOLID, -1]);
+        write_bounds(leafs, &[0; 6]);
+        write_ints(leafs, &[0, 0]);
+        leafs.write_all(&[0; 4]).unwrap();
+        write_ints(leafs, &[CONTENTS_WATER, 2]);
+        write_bounds(leafs, &[-8, -8, -8, 64, 64, 16]);
+        write_ints(leafs, &[0, 1]);
+        leafs.write_all(&[0, 10, 0, 255]).unwrap();
+
+        lumps[Lump::MarkSurfaces as usize].clear();
+        write_ints(&mut lumps[Lump::MarkSurfaces as usize], &[0]);
+
+        lumps[Lump::Edges as usize].clear();
+        write_ints(&mut lumps[Lump::Edges as usize],
+                   &[0, 0, 0, 1, 1, 2, 2, 0]);
+
+        lumps
+    }
+
+    /// Replace part of a lump in `map_lumps()`, and try to load the result.
+    fn load_modified(lump: Lump, at: usize, bytes: &[u8])
+        -> Result<Bsp, BspError>
+    {
+        let mut lumps = map_lumps();
+        lumps[lump as usize][at..at + bytes.len()].copy_from_slice(bytes);
+        Bsp::from_bytes(&common::bsp_bytes(BSP_VERSION, &lumps))
+    }
+
+    #[test]
+    fn load() {
+        let bsp = Bsp::from_bytes(
+            &common::bsp_bytes(BSP_VERSION, &map_lumps())).unwrap();
+
+        assert_eq!(bsp.entities, "{\n\"classname\" \"worldspawn\"\n}\n");
+        assert_eq!(bsp.planes[0],
+                   Plane { normal: [0.0, 0.0, 1.0], dist: 16.0, kind: 2 });
+        assert_eq!(bsp.textures.len(), 2);
+        let floor = bsp.textures[0].as_ref().unwrap();
+        assert_eq!((floor.name.as_str(), floor.width, floor.height),
+                   ("floor", 8, 8));
+        assert_eq!(floor.mips[3], &[7]);
+        assert_eq!(bsp.textures[1], None);
+        assert_eq!(bsp.vertexes[1], [64.0, 0.0, 16.0]);
+        assert_eq!(bsp.visibility, &[0xff, 0x00, 0x02, 0x00]);
+        assert_eq!(bsp.nodes, &[Node {
+            plane: 0,
+            children: [-1, -2],
+            mins: [-8.0, -8.0, -8.0],
+            maxs: [64.0, 64.0, 64.0],
+            first_face: 0,
+            num_faces: 1,
+        }]);
+        assert_eq!(bsp.texinfo[0].vecs, [[1.0, 0.0, 0.0, 0.0],
+                                         [0.0, 1.0, 0.0, 0.0]]);
+        assert_eq!(bsp.faces, &[Face {
+            plane: 0,
+            side: true,
+            first_edge: 0,
+            num_edges: 3,
+            texinfo: 0,
+            styles: [0, 255, 255, 255],
+            light_offset: Some(0),
+        }]);
+        assert_eq!(bsp.lighting.len(), 16);
+        assert_eq!(bsp.clip_nodes, &[ClipNode {
+            plane: 1,
+            children: [CONTENTS_EMPTY, CONTENTS_SOLID],
+        }]);
+        assert_eq!(bsp.leafs[0].vis_offset, None);
+        assert_eq!(bsp.leafs[1], Leaf {
+            contents: CONTENTS_WATER,
+            vis_offset: Some(2),
+            mins: [-8.0, -8.0, -8.0],
+            maxs: [64.0, 64.0, 16.0],
+            first_mark_surface: 0,
+            num_mark_surfaces: 1,
+            ambient_level: [0, 10, 0, 255],
+        });
+        assert_eq!(bsp.mark_surfaces, &[0]);
+        assert_eq!(bsp.edges, &[[0, 0], [0, 1], [1, 2], [2, 0]]);
+        assert_eq!(bsp.surf_edges, &[1, 2, -3]);
+        assert_eq!(bsp.models.len(), 1);
+        assert_eq!(bsp.models[0].vis_leafs, 1);
+        assert_eq!(bsp.models[0].num_faces, 1);
+    }
+
+    #[test]
+    fn load_bsp2() {
+        let v29 = Bsp::from_bytes(
+            &common::bsp_bytes(BSP_VERSION, &map_lumps())).unwrap();
+        assert_eq!(v29.format, Format::Bsp29);
+
+        for &(version, rmq, format) in
+            &[(BSP2_VERSION, false, Format::Bsp2),
+              (BSP2_RMQ_VERSION, true, Format::Bsp2Rmq)]
+        {
+            let data = common::bsp_bytes(version, &bsp2_map_lumps(rmq));
+            let bsp = Bsp::from_bytes(&data).unwrap();
+            assert_eq!(bsp.format, format);
+            assert_eq!(Bsp { format: Format::Bsp29, ..bsp }, v29);
+        }
+
+        assert_eq!(&common::bsp_bytes(BSP2_VERSION, &[])[..4], b"BSP2");
+        assert_eq!(&common::bsp_bytes(BSP2_RMQ_VERSION, &[])[..4], b"2PSB");
+    }
+
+    #[test]
+    fn bsp2_errors() {
+        // A v29 map isn't a valid BSP2 map.
+        match Bsp::from_bytes(&common::bsp_bytes(BSP2_VERSION, &map_lumps())) {
+            Err(BspError::BadLumpSize { lump: Lump::Nodes, len: 24,
+                                        item_size: 44, .. }) => (),
+            other => panic!("expected a BadLumpSize error, got {:?}", other),
+        }
+
+        // Indices that don't fit in a short are still checked.
+        let mut lumps = bsp2_map_lumps(false);
+        LittleEndian::write_i32(&mut lumps[Lump::Nodes as usize][8..12],
+                                -70000);
+        match Bsp::from_bytes(&common::bsp_bytes(BSP2_VERSION, &lumps)) {
+            Err(BspError::BadIndex { lump: Lump::Nodes, field: "leaf",
+                                     value: 69999, .. }) => (),
+            other => panic!("expected a BadIndex error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn header_errors() {
+        let data = common::bsp_bytes(BSP_VERSION, &map_lumps());
+        assert_eq!(Bsp::from_bytes(&data[..HEADER_SIZE - 1]),
+                   Err(BspError::OutOfBounds { at: 0, len: HEADER_SIZE }));
+        assert_eq!(Bsp::from_bytes(&common::bsp_bytes(30, &map_lumps())),
+                   Err(BspError::BadVersion(30)));
+
+        // The models are the last lump.
+        let models_len = map_lumps()[Lump::Models as usize].len();
+        assert_eq!(Bsp::from_bytes(&data[..data.len() - 1]),
+                   Err(BspError::OutOfBounds {
+                       at: data.len() - models_len, len: models_len }));
+
+        let mut bad = data.clone();
+        let at = 4 + Lump::Faces as usize * 8;
+        LittleEndian::write_i32(&mut bad[at..at+4], -1);
+        assert_eq!(Bsp::from_bytes(&bad),
+                   Err(BspError::NegativeOffset {
+                       lump: Lump::Faces, at, value: -1 }));
+
+        let mut bad = data.clone();
+        LittleEndian::write_i32(&mut bad[at+4..at+8], -20);
+        assert_eq!(Bsp::from_bytes(&bad),
+                   Err(BspError::NegativeSize {
+                       lump: Lump::Faces, at: at + 4, value: -20 }));
+
+        let mut bad = data.clone();
+        LittleEndian::write_i32(&mut bad[at+4..at+8], 19);
+        match Bsp::from_bytes(&bad) {
+            Err(BspError::BadLumpSize { lump: Lump::Faces, len: 19,
+                                        item_size: 20, .. }) => (),
+            other => panic!("expected a BadLumpSize error, got {:?}", other),
+        }
+
+        let mut lumps = map_lumps();
+        lumps[Lump::Models as usize].clear();
+        assert_eq!(Bsp::from_bytes(&common::bsp_bytes(BSP_VERSION, &lumps)),
+                   Err(BspError::NoModels));
+    }
+
+    #[test]
+    fn texture_errors() {
+        match load_modified(Lump::Textures, 0, &[0, 1, 0, 0]) {
+            Err(BspError::OutOfBounds { len: 1028, .. }) => (),
+            other => panic!("expected an OutOfBounds error, got {:?}", other),
+        }
+        match load_modified(Lump::Textures, 0, &[0xff; 4]) {
+            Err(BspError::NegativeCount { lump: Lump::Textures,
+                                          value: -1, .. }) => (),
+            other => panic!("expected a NegativeCount error, got {:?}", other),
+        }
+        match load_modified(Lump::Textures, 4, &[0xfe, 0, 0, 0]) {
+            Err(BspError::OutOfBounds { len: 4, .. }) => (),
+            other => panic!("expected an OutOfBounds error, got {:?}", other),
+        }
+        // Mip level 3 is past the end of the lump.
+        match load_modified(Lump::Textures, 12 + 36, &[0xf0, 0, 0, 0]) {
+            Err(BspError::BadTexture { index: 0, .. }) => (),
+            other => panic!("expected a BadTexture error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn index_errors() {
+        let bad_index = |lump, at, bytes: &[u8]| {
+            match load_modified(lump, at, bytes) {
+                Err(BspError::BadIndex { lump, index, field, value, len }) =>
+                    (lump, index, field, value, len),
+                other => panic!("expected a BadIndex error, got {:?}", other),
+            }
+        };
+
+        assert_eq!(bad_index(Lump::Nodes, 0, &[2, 0, 0, 0]),
+                   (Lump::Nodes, 0, "plane", 2, 2));
+        assert_eq!(bad_index(Lump::Nodes, 4, &[1, 0]),
+                   (Lump::Nodes, 0, "node", 1, 1));
+        assert_eq!(bad_index(Lump::Nodes, 6, &[0xfd, 0xff]),
+                   (Lump::Nodes, 0, "leaf", 2, 2));
+        assert_eq!(bad_index(Lump::Nodes, 22, &[2, 0]),
+                   (Lump::Nodes, 0, "faces", 1, 1));
+        assert_eq!(bad_index(Lump::ClipNodes, 4, &[1, 0]),
+                   (Lump::ClipNodes, 0, "child", 1, 1));
+        assert_eq!(bad_index(Lump::TexInfo, 32, &[2, 0, 0, 0]),
+                   (Lump::TexInfo, 0, "miptex", 2, 2));
+        assert_eq!(bad_index(Lump::Faces, 10, &[1, 0]),
+                   (Lump::Faces, 0, "texinfo", 1, 1));
+        assert_eq!(bad_index(Lump::Faces, 8, &[4, 0]),
+                   (Lump::Faces, 0, "edges", 3, 3));
+        assert_eq!(bad_index(Lump::Faces, 16, &[16, 0, 0, 0]),
+                   (Lump::Faces, 0, "light offset", 16, 16));
+        assert_eq!(bad_index(Lump::Leafs, 28 + 4, &[4, 0, 0, 0]),
+                   (Lump::Leafs, 1, "vis offset", 4, 4));
+        assert_eq!(bad_index(Lump::Leafs, 28 + 20, &[1, 0]),
+                   (Lump::Leafs, 1, "marksurfaces", 1, 1));
+        assert_eq!(bad_index(Lump::MarkSurfaces, 0, &[1, 0]),
+                   (Lump::MarkSurfaces, 0, "face", 1, 1));
+        assert_eq!(bad_index(Lump::Edges, 6, &[3, 0]),
+                   (Lump::Edges, 1, "vertex", 3, 3));
+        assert_eq!(bad_index(Lump::SurfEdges, 8, &[0xfc, 0xff, 0xff, 0xff]),
+                   (Lump::SurfEdges, 2, "edge", 4, 4));
+        assert_eq!(bad_index(Lump::Models, 36, &[1, 0, 0, 0]),
+                   (Lump::Models, 0, "node", 1, 1));
+        assert_eq!(bad_index(Lump::Models, 40, &[1, 0, 0, 0]),
+                   (Lump::Models, 0, "clip node", 1, 1));
+        assert_eq!(bad_index(Lump::Models, 60, &[2, 0, 0, 0]),
+                   (Lump::Models, 0, "faces", 1, 1));
+
+        // Hull 3 isn't checked.
+        assert!(load_modified(Lump::Models, 48, &[9, 0, 0, 0]).is_ok());
+    }
+
+    /// Load the first map of a full version of standard Quake 1.
+    #[cfg(feature = "retail-data")]
+    #[test]
+    fn retail_load_e1m1() {
+        let fs = common::retail_fs();
+        let bsp = Bsp::load(&fs, "maps/e1m1.bsp").unwrap().unwrap();
+        assert!(bsp.entities.contains("\"classname\" \"worldspawn\""));
+        assert!(bsp.models.len() > 1);
+        assert!(bsp.textures.iter().any(|t| t.is_some()));
+        assert!(!bsp.faces.is_empty());
+    }
+}
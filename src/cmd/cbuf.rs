@@ -0,0 +1,195 @@
+// Copyright (C) 1996-1997 Id Software, Inc.
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+//
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 59 Temple Place - Suite 330, Boston, MA  02111-1307, USA.
+
+// Modified by Adrian Chan, March 2018
+// Cbuf_* from cmd.c
+
+//! The command buffer.
+
+
+/// A buffer of console command text, waiting to be executed.
+///
+/// Text can come from the command line, scripts, key bindings and the
+/// console.  Commands are separated by newlines, or by `;` outside of a
+/// quoted string.
+#[derive(Debug, Default)]
+pub struct Cbuf {
+    text: String,
+    wait: bool,
+}
+
+impl Cbuf {
+    /// Construct a new, empty `Cbuf`.
+    pub fn new() -> Self {
+        Cbuf { text: String::new(), wait: false }
+    }
+
+    /// Add text to the end of the buffer.
+    pub fn add(&mut self, text: &str) {
+        self.text.push_str(text);
+    }
+
+    /// Insert text at the start of the buffer, so that it's executed before
+    /// anything already in the buffer.
+    ///
+    /// Adds a newline after the text, so that it can't run together with the
+    /// existing commands.
+    pub fn insert(&mut self, text: &str) {
+        let mut new_text = String::with_capacity(
+            text.len() + 1 + self.text.len());
+        new_text.push_str(text);
+        new_text.push('\n');
+        new_text.push_str(&self.text);
+        self.text = new_text;
+    }
+
+    /// Is there no text in the buffer?
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// Remove all of the text in the buffer.
+    pub fn clear(&mut self) {
+        self.text.clear();
+    }
+
+    /// Stop executing commands until the next frame.
+    ///
+    /// This lets a script wait for the effects of its earlier commands, such
+    /// as `+attack` followed by `wait` and `-attack`.
+    pub fn wait(&mut self) {
+        self.wait = true;
+    }
+
+    /// Remove the next command line from the buffer, without its separator.
+    ///
+    /// Returns `None` if the buffer is empty.  After a call to `wait()`, the
+    /// next call returns `None`, to end the frame's execution.
+    pub fn next_line(&mut self) -> Option<String> {
+        if self.wait {
+            self.wait = false;
+            return None;
+        }
+        if self.text.is_empty() {
+            return None;
+        }
+
+        let mut in_quotes = false;
+        let mut end = self.text.len();
+        for (i, c) in self.text.bytes().enumerate() {
+            match c {
+                b'"' => in_quotes = !in_quotes,
+                b';' if !in_quotes => { end = i; break; },
+                b'\n' => { end = i; break; },
+                _ => (),
+            }
+        }
+
+        let line = self.text[..end].to_string();
+        let rest = (end + 1).min(self.text.len());
+        self.text.drain(..rest);
+        Some(line)
+    }
+
+    /// Execute the commands in the buffer, one line at a time, until it's
+    /// empty or a command calls `wait()`.
+    ///
+    /// `exec` is called with each line, and may add or insert more text into
+    /// the buffer.
+    pub fn execute<F>(&mut self, mut exec: F)
+        where F: FnMut(&mut Cbuf, &str)
+    {
+        while let Some(line) = self.next_line() {
+            exec(self, &line);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(cbuf: &mut Cbuf) -> Vec<String> {
+        let mut lines = Vec::new();
+        cbuf.execute(|_, line| lines.push(line.to_string()));
+        lines
+    }
+
+    #[test]
+    fn separators() {
+        let mut cbuf = Cbuf::new();
+        assert!(cbuf.is_empty());
+        assert_eq!(cbuf.next_line(), None);
+
+        cbuf.add("map e1m1\nskill 3;  echo hi\n\n");
+        cbuf.add("say \"a;b\";say \"c\nd\"");
+        assert_eq!(lines(&mut cbuf),
+                   &["map e1m1", "skill 3", "  echo hi", "", "say \"a;b\"",
+                     "say \"c", "d\""]);
+        assert!(cbuf.is_empty());
+    }
+
+    #[test]
+    fn add_and_insert() {
+        let mut cbuf = Cbuf::new();
+        cbuf.add("b\n");
+        cbuf.insert("a");
+        cbuf.add("c");
+        assert_eq!(lines(&mut cbuf), &["a", "b", "c"]);
+
+        cbuf.add("d");
+        cbuf.clear();
+        assert!(cbuf.is_empty());
+    }
+
+    #[test]
+    fn execute_inserts() {
+        // Text inserted by a command runs before the rest of the buffer, and
+        // text added runs after it.
+        let mut cbuf = Cbuf::new();
+        cbuf.add("exec;last\n");
+        let mut seen = Vec::new();
+        cbuf.execute(|cbuf, line| {
+            seen.push(line.to_string());
+            if line == "exec" {
+                cbuf.add("added\n");
+                cbuf.insert("inserted 1;inserted 2");
+            }
+        });
+        assert_eq!(seen,
+                   &["exec", "inserted 1", "inserted 2", "last", "added"]);
+    }
+
+    #[test]
+    fn wait() {
+        let mut cbuf = Cbuf::new();
+        cbuf.add("+attack;wait;-attack\n");
+        let mut seen = Vec::new();
+        cbuf.execute(|cbuf, line| {
+            seen.push(line.to_string());
+            if line == "wait" {
+                cbuf.wait();
+            }
+        });
+        assert_eq!(seen, &["+attack", "wait"]);
+        assert!(!cbuf.is_empty());
+
+        // The rest runs on the next frame.
+        assert_eq!(lines(&mut cbuf), &["-attack"]);
+    }
+}
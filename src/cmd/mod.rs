@@ -0,0 +1,214 @@
+// Copyright (C) 1996-1997 Id Software, Inc.
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+//
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 59 Temple Place - Suite 330, Boston, MA  02111-1307, USA.
+
+// Modified by Adrian Chan, March 2018
+// Parts of cmd.c
+
+//! Console commands.
+//!
+//! Command text is queued in a `Cbuf`, split into lines, and each line is
+//! tokenized into `Args` and run by the matching function in a `Commands`.
+
+use failure::Error;
+
+use std::collections::BTreeMap;
+
+pub mod cbuf;
+pub use self::cbuf::Cbuf;
+pub mod parse;
+
+/// The maximum number of arguments in a command line, including the command
+/// name.  Any extra arguments are dropped.
+pub const MAX_ARGS: usize = 80;
+
+
+/// The tokenized arguments of a single command line.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Args {
+    argv: Vec<String>,
+    args: String,
+}
+
+impl Args {
+    /// Tokenize a command line.
+    ///
+    /// Tokenizing stops at the first newline.
+    pub fn new(text: &str) -> Self {
+        let mut text = text.split('\n').next().unwrap_or("");
+        let mut argv = Vec::new();
+        let mut args = String::new();
+
+        loop {
+            text = text.trim_start_matches(|c: char| c <= ' ');
+            if argv.len() == 1 {
+                args = text.trim_end().to_string();
+            }
+            match parse::parse(text) {
+                None => break,
+                Some((token, rest)) => {
+                    if argv.len() < MAX_ARGS {
+                        argv.push(token);
+                    }
+                    text = rest;
+                },
+            }
+        }
+
+        Args { argv, args }
+    }
+
+    /// The number of arguments, including the command name.
+    pub fn argc(&self) -> usize {
+        self.argv.len()
+    }
+
+    /// The argument at index `i`, where 0 is the command name.
+    ///
+    /// Returns an empty string if there is no such argument.
+    pub fn argv(&self, i: usize) -> &str {
+        self.argv.get(i).map_or("", |a| a.as_str())
+    }
+
+    /// All of the text after the command name, untokenized.
+    pub fn args(&self) -> &str {
+        &self.args
+    }
+}
+
+/// The function that runs a command, given some context and the command's
+/// arguments.
+pub type Command<C> = fn(&mut C, &Args);
+
+/// A registry of console commands that act on a context of type `C`.
+///
+/// Command names are case-insensitive.
+pub struct Commands<C> {
+    commands: BTreeMap<String, Command<C>>,
+}
+
+impl<C> Default for Commands<C> {
+    fn default() -> Self {
+        Commands { commands: BTreeMap::new() }
+    }
+}
+
+impl<C> Commands<C> {
+    /// Construct a new `Commands` with no commands.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a new command.
+    pub fn add(&mut self, name: &str, command: Command<C>)
+        -> Result<(), Error>
+    {
+        let key = name.to_lowercase();
+        if self.commands.contains_key(&key) {
+            bail!("Cmd_AddCommand: {} already defined", name);
+        }
+        self.commands.insert(key, command);
+        Ok(())
+    }
+
+    /// Is there a command with this name?
+    pub fn exists(&self, name: &str) -> bool {
+        self.commands.contains_key(&name.to_lowercase())
+    }
+
+    /// Get the function for the named command, if there is one.
+    pub fn get(&self, name: &str) -> Option<Command<C>> {
+        self.commands.get(&name.to_lowercase()).cloned()
+    }
+
+    /// The names of all the commands, in sorted order.
+    pub fn names(&self) -> impl Iterator<Item=&str> {
+        self.commands.keys().map(|k| k.as_str())
+    }
+
+    /// Run the command named by `args.argv(0)`.
+    ///
+    /// Returns `false` if there is no such command, or if `args` is empty.
+    pub fn execute(&self, context: &mut C, args: &Args) -> bool {
+        match self.get(args.argv(0)) {
+            Some(command) if args.argc() > 0 => {
+                command(context, args);
+                true
+            },
+            _ => false,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn args() {
+        let args = Args::new("");
+        assert_eq!(args.argc(), 0);
+        assert_eq!(args.argv(0), "");
+        assert_eq!(args.args(), "");
+
+        let args = Args::new("  map\te1m1  ");
+        assert_eq!(args.argc(), 2);
+        assert_eq!(args.argv(0), "map");
+        assert_eq!(args.argv(1), "e1m1");
+        assert_eq!(args.argv(2), "");
+        assert_eq!(args.args(), "e1m1");
+
+        let args = Args::new("say \"hello  there\" you // comment\nnext");
+        assert_eq!(args.argc(), 3);
+        assert_eq!(args.argv(1), "hello  there");
+        assert_eq!(args.argv(2), "you");
+        assert_eq!(args.args(), "\"hello  there\" you // comment");
+
+        let long: Vec<String> = (0..100).map(|i| i.to_string()).collect();
+        let args = Args::new(&long.join(" "));
+        assert_eq!(args.argc(), MAX_ARGS);
+        assert_eq!(args.argv(MAX_ARGS - 1), (MAX_ARGS - 1).to_string());
+    }
+
+    #[test]
+    fn commands() {
+        fn push(log: &mut Vec<String>, args: &Args) {
+            log.push(args.args().to_string());
+        }
+        fn clear(log: &mut Vec<String>, _args: &Args) {
+            log.clear();
+        }
+
+        let mut commands = Commands::new();
+        commands.add("push", push).unwrap();
+        commands.add("clear", clear).unwrap();
+        assert!(commands.add("PUSH", push).is_err());
+        assert!(commands.exists("push"));
+        assert!(commands.exists("Clear"));
+        assert!(!commands.exists("nope"));
+        assert_eq!(commands.names().collect::<Vec<_>>(), &["clear", "push"]);
+
+        let mut log = Vec::new();
+        assert!(commands.execute(&mut log, &Args::new("push a b")));
+        assert!(commands.execute(&mut log, &Args::new("PUSH c")));
+        assert_eq!(log, &["a b", "c"]);
+        assert!(commands.execute(&mut log, &Args::new("clear")));
+        assert!(log.is_empty());
+        assert!(!commands.execute(&mut log, &Args::new("nope")));
+        assert!(!commands.execute(&mut log, &Args::new("")));
+    }
+}
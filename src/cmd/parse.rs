@@ -0,0 +1,151 @@
+// Copyright (C) 1996-1997 Id Software, Inc.
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+//
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 59 Temple Place - Suite 330, Boston, MA  02111-1307, USA.
+
+// Modified by Adrian Chan, March 2018
+// COM_Parse from common.c
+
+//! Splitting text into tokens, the way Quake does for console commands,
+//! scripts and entity strings.
+
+/// Is this a byte that is always a token by itself?
+fn is_single_char(c: u8) -> bool {
+    matches!(c, b'{' | b'}' | b'(' | b')' | b'\'' | b':')
+}
+
+/// Parse the next token from `data`.
+///
+/// Returns the token and the remaining text, or `None` if there are no more
+/// tokens.  A token is one of:
+///
+/// * A quoted string.  The quotes are removed, and the string runs until the
+///   closing quote or the end of the text.
+/// * One of the characters `{ } ( ) ' :`.
+/// * A word, which runs until whitespace or one of the above characters.
+///
+/// Whitespace (including newlines) and `//` comments between tokens are
+/// skipped.  Anything up to and including a space counts as whitespace.
+///
+/// # Example
+///
+/// ```
+/// use rqs::cmd::parse::parse;
+///
+/// let (token, rest) = parse("  bind \"x\" // a comment").unwrap();
+/// assert_eq!(token, "bind");
+/// let (token, rest) = parse(rest).unwrap();
+/// assert_eq!(token, "x");
+/// assert_eq!(parse(rest), None);
+/// ```
+pub fn parse(data: &str) -> Option<(String, &str)> {
+    let bytes = data.as_bytes();
+    let mut i = 0;
+
+    // Skip whitespace and comments.
+    loop {
+        while i < bytes.len() && bytes[i] <= b' ' {
+            i += 1;
+        }
+        if i == bytes.len() {
+            return None;
+        }
+        if bytes[i..].starts_with(b"//") {
+            while i < bytes.len() && bytes[i] != b'\n' {
+                i += 1;
+            }
+        } else {
+            break;
+        }
+    }
+
+    // All of the bytes that end a token are ASCII, so slicing at them always
+    // gives valid strs.
+    let c = bytes[i];
+    if c == b'"' {
+        let start = i + 1;
+        match bytes[start..].iter().position(|&c| c == b'"') {
+            Some(len) => Some((data[start..start + len].to_string(),
+                               &data[start + len + 1..])),
+            None => Some((data[start..].to_string(), "")),
+        }
+    } else if is_single_char(c) {
+        Some((data[i..i + 1].to_string(), &data[i + 1..]))
+    } else {
+        let start = i;
+        while i < bytes.len() && bytes[i] > b' ' && !is_single_char(bytes[i]) {
+            i += 1;
+        }
+        Some((data[start..i].to_string(), &data[i..]))
+    }
+}
+
+/// Split all of `data` into tokens with `parse()`.
+pub fn tokens(mut data: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    while let Some((token, rest)) = parse(data) {
+        tokens.push(token);
+        data = rest;
+    }
+    tokens
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse(" \t\r\n "), None);
+        assert_eq!(tokens("map e1m1"), &["map", "e1m1"]);
+        assert_eq!(tokens("  \tmap\n\ne1m1\r\n"), &["map", "e1m1"]);
+        assert_eq!(tokens("bind ] \"+attack\""), &["bind", "]", "+attack"]);
+        assert_eq!(tokens("say héllo"), &["say", "héllo"]);
+        // Semicolons aren't special to the tokenizer, only to the Cbuf.
+        assert_eq!(tokens("a;b"), &["a;b"]);
+    }
+
+    #[test]
+    fn quotes() {
+        assert_eq!(tokens("say \"hello world\" x"),
+                   &["say", "hello world", "x"]);
+        assert_eq!(tokens("\"\""), &[""]);
+        // Quotes only start a string at the beginning of a token.
+        assert_eq!(tokens("a\"b c\"d"), &["a\"b", "c\"d"]);
+        assert_eq!(tokens("\"// not a comment\""), &["// not a comment"]);
+        assert_eq!(tokens("\"two\nlines\""), &["two\nlines"]);
+        // An unterminated quote runs to the end.
+        assert_eq!(tokens("echo \"open ended"), &["echo", "open ended"]);
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(tokens("// comment"), Vec::<String>::new());
+        assert_eq!(tokens("a // b c\nd"), &["a", "d"]);
+        assert_eq!(tokens("//\n//\na"), &["a"]);
+        // Comments only start between tokens.
+        assert_eq!(tokens("a/b c//d"), &["a/b", "c//d"]);
+    }
+
+    #[test]
+    fn single_chars() {
+        assert_eq!(tokens("{\"classname\" \"worldspawn\"}"),
+                   &["{", "classname", "worldspawn", "}"]);
+        assert_eq!(tokens("f(x):y'z"),
+                   &["f", "(", "x", ")", ":", "y", "'", "z"]);
+    }
+}
@@ -0,0 +1,100 @@
+// Copyright (C) 1996-1997 Id Software, Inc.
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+//
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 59 Temple Place - Suite 330, Boston, MA  02111-1307, USA.
+
+// Modified by Adrian Chan, March 2018
+// Ported crc.c
+
+//! The 16 bit CRC used by Quake.
+//!
+//! This is CRC-CCITT: polynomial 0x1021, an initial value of 0xffff, and no
+//! final XOR.
+
+const INIT_VALUE: u16 = 0xffff;
+const XOR_VALUE: u16 = 0x0000;
+const POLYNOMIAL: u16 = 0x1021;
+
+
+/// A CRC that is being calculated, one byte at a time.
+#[derive(Clone, Copy, Debug)]
+pub struct Crc {
+    value: u16,
+}
+
+impl Crc {
+    /// Start a new CRC.
+    pub fn new() -> Self {
+        Self { value: INIT_VALUE }
+    }
+
+    /// Add a byte to the CRC.
+    pub fn process_byte(&mut self, data: u8) {
+        let mut value = self.value ^ (u16::from(data) << 8);
+        for _ in 0..8 {
+            value = match value & 0x8000 {
+                0 => value << 1,
+                _ => (value << 1) ^ POLYNOMIAL,
+            };
+        }
+        self.value = value;
+    }
+
+    /// Add some bytes to the CRC.
+    pub fn process(&mut self, data: &[u8]) {
+        for &b in data {
+            self.process_byte(b);
+        }
+    }
+
+    /// The CRC of all the bytes processed so far.
+    pub fn value(&self) -> u16 {
+        self.value ^ XOR_VALUE
+    }
+}
+
+impl Default for Crc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Calculate the CRC of a block of bytes.
+pub fn block(data: &[u8]) -> u16 {
+    let mut crc = Crc::new();
+    crc.process(data);
+    crc.value()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_value() {
+        assert_eq!(block(b""), 0xffff);
+        assert_eq!(block(b"123456789"), 0x29b1);
+    }
+
+    #[test]
+    fn incremental() {
+        let mut crc = Crc::new();
+        crc.process(b"1234");
+        crc.process_byte(b'5');
+        crc.process(b"6789");
+        assert_eq!(crc.value(), 0x29b1);
+    }
+}
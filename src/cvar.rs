@@ -0,0 +1,491 @@
+// Copyright (C) 1996-1997 Id Software, Inc.
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+//
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 59 Temple Place - Suite 330, Boston, MA  02111-1307, USA.
+
+// Modified by Adrian Chan, March 2018
+// cvar.c
+
+//! Console variables.
+//!
+//! A cvar is a named string value that can be changed from the console,
+//! scripts and the command line.  Cvars with the archive flag are saved to
+//! `config.cfg`, and changes to cvars with the server flag are broadcast to
+//! clients.
+
+use failure::Error;
+
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use cmd::{Args, Commands};
+use parms::Parms;
+use util;
+
+
+/// A console variable.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cvar {
+    name: String,
+    string: String,
+    value: f32,
+    archive: bool,
+    server: bool,
+}
+
+impl Cvar {
+    /// Construct a new `Cvar` with the given name and initial value.
+    pub fn new(name: &str, string: &str) -> Self {
+        Cvar {
+            name: name.to_string(),
+            string: string.to_string(),
+            value: util::atof(string),
+            archive: false,
+            server: false,
+        }
+    }
+
+    /// Set the archive flag, so that the cvar is saved to `config.cfg`.
+    pub fn archive(mut self) -> Self {
+        self.archive = true;
+        self
+    }
+
+    /// Set the server flag, so that changes are broadcast to clients.
+    pub fn server(mut self) -> Self {
+        self.server = true;
+        self
+    }
+
+    /// The name of the cvar.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The value of the cvar, as a string.
+    pub fn string(&self) -> &str {
+        &self.string
+    }
+
+    /// The value of the cvar, as a number.
+    ///
+    /// This is parsed like Quake's `atof`, so anything that isn't a number
+    /// gives 0.
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    /// The value of the cvar, as a bool.  Any non-zero number is `true`.
+    pub fn as_bool(&self) -> bool {
+        self.value != 0.0
+    }
+
+    /// Try to parse the string value of the cvar as some other type.
+    pub fn parse<F: FromStr>(&self) -> Option<F> {
+        self.string.parse().ok()
+    }
+
+    /// Is the cvar saved to `config.cfg`?
+    pub fn is_archive(&self) -> bool {
+        self.archive
+    }
+
+    /// Are changes to the cvar broadcast to clients?
+    pub fn is_server(&self) -> bool {
+        self.server
+    }
+}
+
+/// A function that's called after a cvar has changed.
+pub type Callback = Box<dyn FnMut(&Cvar)>;
+
+/// The registry of all console variables.
+///
+/// Cvar names are case-sensitive, as in Quake.
+pub struct Cvars {
+    vars: BTreeMap<String, Cvar>,
+    callbacks: BTreeMap<String, Vec<Callback>>,
+    server_callback: Option<Callback>,
+}
+
+impl Cvars {
+    /// Construct a new `Cvars`, with the `cmdline` cvar already registered.
+    ///
+    /// `cmdline` reports the command line arguments that Quake was started
+    /// with.
+    pub fn new(parms: &Parms) -> Self {
+        let mut cvars = Cvars {
+            vars: BTreeMap::new(),
+            callbacks: BTreeMap::new(),
+            server_callback: None,
+        };
+        cvars.vars.insert(
+            "cmdline".into(), Cvar::new("cmdline", parms.cmdline()).server());
+        cvars
+    }
+
+    /// Register a new cvar.
+    pub fn register(&mut self, cvar: Cvar) -> Result<(), CvarError> {
+        if self.vars.contains_key(&cvar.name) {
+            return Err(CvarError::AlreadyDefined(cvar.name));
+        }
+        self.vars.insert(cvar.name.clone(), cvar);
+        Ok(())
+    }
+
+    /// Is there a cvar with this name?
+    pub fn exists(&self, name: &str) -> bool {
+        self.vars.contains_key(name)
+    }
+
+    /// Get the named cvar, if it exists.
+    pub fn get(&self, name: &str) -> Option<&Cvar> {
+        self.vars.get(name)
+    }
+
+    /// All of the cvars, in name order.
+    pub fn iter(&self) -> impl Iterator<Item=&Cvar> {
+        self.vars.values()
+    }
+
+    /// The numeric value of the named cvar, or 0 if it doesn't exist.
+    pub fn value(&self, name: &str) -> f32 {
+        self.get(name).map_or(0.0, |v| v.value)
+    }
+
+    /// The string value of the named cvar, or an empty string if it doesn't
+    /// exist.
+    pub fn string(&self, name: &str) -> &str {
+        self.get(name).map_or("", |v| v.string.as_str())
+    }
+
+    /// Try to parse the value of the named cvar as some other type.
+    pub fn parse<F: FromStr>(&self, name: &str) -> Option<F> {
+        self.get(name).and_then(|v| v.parse())
+    }
+
+    /// Set the value of a cvar.
+    ///
+    /// If the value changed, the cvar's callbacks are called, followed by
+    /// the server callback if it's a server cvar.
+    pub fn set(&mut self, name: &str, string: &str) -> Result<(), CvarError> {
+        let var = self.vars.get_mut(name)
+            .ok_or_else(|| CvarError::NotFound(name.to_string()))?;
+        if var.string == string {
+            return Ok(());
+        }
+        var.string = string.to_string();
+        var.value = util::atof(string);
+
+        if let Some(callbacks) = self.callbacks.get_mut(name) {
+            for callback in callbacks.iter_mut() {
+                callback(var);
+            }
+        }
+        if var.server {
+            if let Some(ref mut callback) = self.server_callback {
+                callback(var);
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the value of a cvar to a number.
+    pub fn set_value(&mut self, name: &str, value: f32)
+        -> Result<(), CvarError>
+    {
+        self.set(name, &value.to_string())
+    }
+
+    /// Add a function to call whenever the named cvar changes.
+    pub fn add_callback(&mut self, name: &str, callback: Callback)
+        -> Result<(), CvarError>
+    {
+        if !self.exists(name) {
+            return Err(CvarError::NotFound(name.to_string()));
+        }
+        self.callbacks.entry(name.to_string())
+            .or_default()
+            .push(callback);
+        Ok(())
+    }
+
+    /// Set the function to call whenever any server cvar changes, so that the
+    /// change can be broadcast to clients.
+    pub fn set_server_callback(&mut self, callback: Callback) {
+        self.server_callback = Some(callback);
+    }
+
+    /// Write a `name "value"` line for each archived cvar, as used by
+    /// `config.cfg`.
+    pub fn write_archive<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for var in self.iter().filter(|v| v.archive) {
+            writeln!(writer, "{} \"{}\"", var.name, var.string)?;
+        }
+        Ok(())
+    }
+}
+
+/// The error type for the cvar registry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CvarError {
+    /// A cvar with this name is already registered.
+    AlreadyDefined(String),
+    /// There is no cvar with this name.
+    NotFound(String),
+}
+
+impl fmt::Display for CvarError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CvarError::AlreadyDefined(ref name) =>
+                write!(f, "Can't register variable {}, already defined", name),
+            CvarError::NotFound(ref name) =>
+                write!(f, "variable {} not found", name),
+        }
+    }
+}
+
+impl error::Error for CvarError {}
+
+/// The parts of the engine that the cvar commands need.
+pub trait CvarContext {
+    /// The cvar registry.
+    fn cvars(&mut self) -> &mut Cvars;
+    /// Print some text to the console.
+    fn print(&mut self, text: &str);
+}
+
+/// Register the `set`, `toggle` and `cvarlist` commands.
+pub fn add_commands<C: CvarContext>(commands: &mut Commands<C>)
+    -> Result<(), Error>
+{
+    commands.add("set", set_f)?;
+    commands.add("toggle", toggle_f)?;
+    commands.add("cvarlist", cvarlist_f)?;
+    Ok(())
+}
+
+/// Handle a command line that names a cvar, rather than a command.
+///
+/// With no arguments, prints the value of the cvar.  With an argument, sets
+/// the cvar to that value.
+///
+/// Returns `false` if there is no such cvar.
+pub fn command<C: CvarContext>(context: &mut C, args: &Args) -> bool {
+    let name = args.argv(0);
+    let text = match context.cvars().get(name) {
+        None => return false,
+        Some(var) => format!("\"{}\" is \"{}\"\n", var.name, var.string),
+    };
+    if args.argc() == 1 {
+        context.print(&text);
+    } else {
+        // We know the cvar exists.
+        let _ = context.cvars().set(name, args.argv(1));
+    }
+    true
+}
+
+/// `set <variable> <value>`: set a cvar, creating it if it doesn't exist.
+fn set_f<C: CvarContext>(context: &mut C, args: &Args) {
+    if args.argc() != 3 {
+        context.print("set <variable> <value>\n");
+        return;
+    }
+    let (name, value) = (args.argv(1), args.argv(2));
+    let cvars = context.cvars();
+    if !cvars.exists(name) {
+        let _ = cvars.register(Cvar::new(name, ""));
+    }
+    let _ = cvars.set(name, value);
+}
+
+/// `toggle <variable>`: switch a cvar between 0 and 1.
+fn toggle_f<C: CvarContext>(context: &mut C, args: &Args) {
+    if args.argc() != 2 {
+        context.print("toggle <variable>\n");
+        return;
+    }
+    let name = args.argv(1);
+    let value = match context.cvars().get(name) {
+        None => None,
+        Some(var) => Some(if var.as_bool() { "0" } else { "1" }),
+    };
+    match value {
+        Some(value) => { let _ = context.cvars().set(name, value); },
+        None => context.print(&format!("toggle: {}\n",
+                                       CvarError::NotFound(name.into()))),
+    }
+}
+
+/// `cvarlist [prefix]`: list the cvars, and their flags and values.
+fn cvarlist_f<C: CvarContext>(context: &mut C, args: &Args) {
+    let prefix = args.argv(1).to_string();
+    let mut text = String::new();
+    let mut count = 0;
+    for var in context.cvars().iter().filter(|v| v.name.starts_with(&prefix))
+    {
+        text.push_str(&format!(
+            "{}{} {} \"{}\"\n",
+            if var.archive { '*' } else { ' ' },
+            if var.server { 's' } else { ' ' },
+            var.name, var.string));
+        count += 1;
+    }
+    text.push_str(&format!("{} cvars\n", count));
+    context.print(&text);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct Context {
+        cvars: Cvars,
+        output: String,
+    }
+
+    impl CvarContext for Context {
+        fn cvars(&mut self) -> &mut Cvars {
+            &mut self.cvars
+        }
+        fn print(&mut self, text: &str) {
+            self.output.push_str(text);
+        }
+    }
+
+    fn new_cvars() -> Cvars {
+        Cvars::new(&Parms::new(
+            vec!("-game".into(), "mymod".into()), "cwd".into()))
+    }
+
+    #[test]
+    fn cmdline() {
+        let cvars = new_cvars();
+        let var = cvars.get("cmdline").unwrap();
+        assert_eq!(var.string(), "-game mymod");
+        assert!(var.is_server());
+        assert!(!var.is_archive());
+        assert_eq!(cvars.iter().count(), 1);
+    }
+
+    #[test]
+    fn get_and_set() {
+        let mut cvars = new_cvars();
+        cvars.register(Cvar::new("sv_gravity", "800").server()).unwrap();
+        cvars.register(Cvar::new("name", "player").archive()).unwrap();
+        assert_eq!(cvars.register(Cvar::new("name", "x")),
+                   Err(CvarError::AlreadyDefined("name".into())));
+
+        assert_eq!(cvars.value("sv_gravity"), 800.0);
+        assert_eq!(cvars.parse::<u32>("sv_gravity"), Some(800));
+        assert_eq!(cvars.string("name"), "player");
+        assert_eq!(cvars.value("name"), 0.0);
+        assert_eq!(cvars.parse::<u32>("name"), None);
+        assert!(!cvars.exists("Name"));
+        assert_eq!(cvars.value("nope"), 0.0);
+        assert_eq!(cvars.string("nope"), "");
+
+        cvars.set("sv_gravity", "100.5").unwrap();
+        assert_eq!(cvars.value("sv_gravity"), 100.5);
+        assert_eq!(cvars.string("sv_gravity"), "100.5");
+        cvars.set_value("sv_gravity", 0.25).unwrap();
+        assert_eq!(cvars.string("sv_gravity"), "0.25");
+        assert!(cvars.get("sv_gravity").unwrap().as_bool());
+        cvars.set_value("sv_gravity", 0.0).unwrap();
+        assert_eq!(cvars.string("sv_gravity"), "0");
+        assert!(!cvars.get("sv_gravity").unwrap().as_bool());
+        assert_eq!(cvars.set("nope", "1"),
+                   Err(CvarError::NotFound("nope".into())));
+
+        let mut config = Vec::new();
+        cvars.write_archive(&mut config).unwrap();
+        assert_eq!(config, b"name \"player\"\n");
+    }
+
+    #[test]
+    fn callbacks() {
+        let mut cvars = new_cvars();
+        cvars.register(Cvar::new("fov", "90").archive()).unwrap();
+        cvars.register(Cvar::new("sv_gravity", "800").server()).unwrap();
+
+        let changes = Rc::new(RefCell::new(Vec::new()));
+        let log = changes.clone();
+        cvars.add_callback("fov", Box::new(move |v| {
+            log.borrow_mut().push(format!("fov {}", v.string()));
+        })).unwrap();
+        let log = changes.clone();
+        cvars.set_server_callback(Box::new(move |v| {
+            log.borrow_mut().push(format!("server {} {}", v.name(), v.value()));
+        }));
+        assert!(cvars.add_callback("nope", Box::new(|_| ())).is_err());
+
+        cvars.set("fov", "110").unwrap();
+        // No change, no callback.
+        cvars.set("fov", "110").unwrap();
+        cvars.set("sv_gravity", "100").unwrap();
+        assert_eq!(*changes.borrow(), &["fov 110", "server sv_gravity 100"]);
+    }
+
+    #[test]
+    fn commands() {
+        let mut commands = Commands::new();
+        add_commands(&mut commands).unwrap();
+        let mut context = Context { cvars: new_cvars(), output: String::new() };
+        context.cvars.register(Cvar::new("fov", "90").archive()).unwrap();
+        let run = |context: &mut Context, line: &str| {
+            let args = Args::new(line);
+            commands.execute(context, &args) || command(context, &args)
+        };
+
+        assert!(run(&mut context, "set fov 100"));
+        assert_eq!(context.cvars.string("fov"), "100");
+        assert!(run(&mut context, "set mine \"a b\""));
+        assert_eq!(context.cvars.string("mine"), "a b");
+        assert!(run(&mut context, "toggle mine"));
+        assert_eq!(context.cvars.string("mine"), "1");
+        assert!(run(&mut context, "toggle mine"));
+        assert_eq!(context.cvars.string("mine"), "0");
+        assert!(context.output.is_empty());
+
+        assert!(run(&mut context, "fov 75"));
+        assert_eq!(context.cvars.value("fov"), 75.0);
+        assert!(run(&mut context, "fov"));
+        assert_eq!(context.output, "\"fov\" is \"75\"\n");
+        context.output.clear();
+        assert!(!run(&mut context, "nope 1"));
+
+        assert!(run(&mut context, "toggle nope"));
+        assert!(run(&mut context, "set fov"));
+        assert_eq!(context.output,
+                   "toggle: variable nope not found\nset <variable> <value>\n");
+        context.output.clear();
+
+        assert!(run(&mut context, "cvarlist"));
+        assert_eq!(context.output,
+                   " s cmdline \"-game mymod\"\n\
+                    *  fov \"75\"\n   mine \"0\"\n3 cvars\n");
+        context.output.clear();
+        assert!(run(&mut context, "cvarlist f"));
+        assert_eq!(context.output, "*  fov \"75\"\n1 cvars\n");
+    }
+}
@@ -28,3 +28,12 @@ pub const MAX_NUM_ARGVS: usize = 50;
 /// The `cmdline` cvar will report the command line arguments used, up to this
 /// length.
 pub const CMDLINE_LENGTH: usize = 256;
+
+/// The default amount of memory for the hunk, in bytes.  Can be changed with
+/// `-heapsize <kb>`.
+pub const DEFAULT_HEAP_SIZE: usize = 0x80_0000;
+/// The smallest hunk that Quake can run with, in bytes.
+pub const MINIMUM_HEAP_SIZE: usize = 0x55_0000;
+/// The default size of the zone, in bytes.  Can be changed with
+/// `-zone <kb>`.
+pub const DEFAULT_ZONE_SIZE: usize = 0xc000;
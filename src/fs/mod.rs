@@ -20,50 +20,82 @@
 
 //! Things relating to the Quake filesystem.
 
+pub mod name;
 pub mod pack;
 pub mod reader;
+pub mod zip;
+pub use self::name::NameError;
 pub use self::reader::FsReader;
 
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs::File;
-use std::io::Read;
-use std::path::PathBuf;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 
-use failure::Error;
+use failure::{Error, Fail};
 
 use defs;
 use parms::Parms;
+use util;
+use zone::{Hunk, HunkBlock};
+
+/// The most memory that `FileSys::load_file()` reserves for a file before
+/// reading it.
+const MAX_LOAD_RESERVE: usize = 1 << 20;
 
 
 /// Represents the Quake filesystem.
 ///
 /// The `FileSys` has an underlying set of search paths, which specify the
-/// directories and/or .pak files that may contain the game's files.
+/// directories, .pak files and/or .pk3 files that may contain the game's
+/// files.
 ///
 /// `FileSys` methods allow a "file" to be opened and read in various ways.
 /// Under the hood, these methods use the search path to find the file, which
-/// may be stored on disk or inside a .pak file.
+/// may be stored on disk or inside a .pak or .pk3 file.
+///
+/// None of the methods that read files need mutable access, and a `FileSys`
+/// can be shared between threads.
 pub struct FileSys {
     /// This is considered to be the main game directory.
     game_dir: PathBuf,
     /// Use Quake 1 progs with Quake 2 maps?
     use_proghack: bool,
+    /// How file names are matched against the files in the search paths.
+    name_match: NameMatch,
     /// The `SearchPath`s that will be used when looking for a file.
     /// The items are stored in reverse priority order.
     search_paths: Vec<SearchPath>,
+    /// Is this the registered version of Quake?
+    registered: bool,
+    /// Is the game different from the original id release?
+    modified: bool,
 }
 
 impl FileSys {
     /// Create and initialise the file system.
+    ///
+    /// File names are matched exactly, unless the `-nocase` parameter is
+    /// given; see `NameMatch::Folded`.
     pub fn new(parms: &Parms) -> Result<Self, Error> {
         let game_dir = PathBuf::new();
         let use_proghack = parms.has("-proghack");
+        let name_match = match parms.has("-nocase") {
+            true => NameMatch::Folded,
+            false => NameMatch::Exact,
+        };
         let search_paths = Vec::with_capacity(5);
         let mut fs = Self {
             game_dir,
             use_proghack,
+            name_match,
             search_paths,
+            registered: false,
+            modified: false,
         };
         fs.init(parms)?;
+        fs.check_registered()?;
         Ok(fs)
     }
 
@@ -90,6 +122,15 @@ impl FileSys {
         // Always use the default GAMENAME.
         self.add_game_dir(subdir(defs::GAMENAME))?;
 
+        // Check whether the base game's pak0 is the one that id shipped.
+        // There's no reference CRC for pak1, so only pak0 can be checked.
+        self.modified = match self.search_paths.get(1) {
+            Some(SearchPath::Pack(pak)) =>
+                pak.file_infos().len() != pack::PAK0_COUNT
+                || pak.crc() != pack::PAK0_CRC,
+            _ => true,
+        };
+
         // Any mission packs that are being used.
         if parms.is_rogue() {
             self.add_game_dir(subdir("rogue"))?;
@@ -100,24 +141,30 @@ impl FileSys {
 
         // `-game <gamedir>` adds basedir/<gamedir> as an override game.
         if let Some(gamedir) = parms.value("-game") {
+            self.modified = true;
             self.add_game_dir(subdir(gamedir))?;
         }
 
         // `-path <dir or packfile> [<dir or packfile>] ...` lets the user fully
         // specify the exact search path, overriding the one we just generated.
+        // Packfiles may be .pak files or .pk3 (or .zip) files.
         if let Some(path_overrides) = parms.values("-path") {
+            self.modified = true;
             for path in path_overrides.iter() {
-                let search = match path.ends_with(".pak") {
-                    true => {
-                        let pak = pack::Pack::load(path.into())?;
-                        if pak.is_none() {
-                            // If the user explicitly asked us to use a pak
-                            // then it must exist.
-                            bail!("Couldn't load packfile {}", path);
-                        }
-                        SearchPath::Pack(pak.unwrap())
-                    },
-                    false => SearchPath::Directory(path.into()),
+                // If the user explicitly asked us to use a packfile then it
+                // must exist.
+                let search = if path.ends_with(".pak") {
+                    match pack::Pack::load(path.into())? {
+                        Some(pak) => SearchPath::Pack(pak),
+                        None => bail!("Couldn't load packfile {}", path),
+                    }
+                } else if path.ends_with(".pk3") || path.ends_with(".zip") {
+                    match zip::Zip::load(path.into())? {
+                        Some(zip) => SearchPath::Zip(zip),
+                        None => bail!("Couldn't load packfile {}", path),
+                    }
+                } else {
+                    SearchPath::Directory(path.into())
                 };
                 self.search_paths.push(search);
             }
@@ -126,24 +173,99 @@ impl FileSys {
         Ok(())
     }
 
+    /// Look for the `gfx/pop.lmp` file that only comes with the registered
+    /// version of Quake, and make sure that it hasn't been tampered with.
+    fn check_registered(&mut self) -> Result<(), Error> {
+        let pop = match self.load_file("gfx/pop.lmp")? {
+            None => {
+                // Playing the shareware version.
+                self.registered = false;
+                return Ok(());
+            },
+            Some(pop) => pop,
+        };
+
+        // The file holds big-endian shorts.
+        let valid = pop.len() >= POP.len() * 2
+            && POP.iter()
+                .zip(pop.chunks(2))
+                .all(|(&expected, bytes)|
+                     expected == u16::from(bytes[0]) << 8 | u16::from(bytes[1]));
+        if !valid {
+            bail!("Corrupted data file: gfx/pop.lmp");
+        }
+        self.registered = true;
+        Ok(())
+    }
+
+    /// Is this the registered version of Quake?
+    ///
+    /// The registered version contains `gfx/pop.lmp`; the shareware version
+    /// does not.
+    pub fn is_registered(&self) -> bool {
+        self.registered
+    }
+
+    /// Is the game different from the original id release?
+    ///
+    /// This is true if `-game` or `-path` were used, or if the base game's
+    /// pak0.pak is missing or doesn't match the original's file count and
+    /// CRC.
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
     /// Add the given directory to the start of the search path, make it the
-    /// main game directory, then add any .pak files that it contains to the
-    /// start of the search path.
+    /// main game directory, then add any .pak and .pk3 files that it contains
+    /// to the start of the search path.
     fn add_game_dir(&mut self, path: PathBuf) -> Result<(), Error> {
         // Add the directory to the search path, lower priority.
         self.search_paths.push(SearchPath::Directory(path.clone()));
 
-        // Add any .pak files contained in the directory, higher priority.
-        // Look for pak0.pak, pak1.pak, ..., and stop when the file is not
-        // found.
+        // Add any numbered packfiles contained in the directory, higher
+        // priority.  Look for pak0.pak, pak0.pk3, pak1.pak, pak1.pk3, ...,
+        // and stop when neither file is found.
+        let mut numbered = Vec::new();
         for i in 0.. {
-            let mut path = path.clone();
+            let mut found = false;
             let filename = format!("pak{}.pak", i);
-            path.push(filename);
-            match pack::Pack::load(path) {
-                Err(e) => return Err(e),
-                Ok(None) => break,  // File not found.
-                Ok(Some(pak)) => self.search_paths.push(SearchPath::Pack(pak)),
+            if let Some(file) = self.name_match.find_on_disk(&path, &filename) {
+                if let Some(pak) = pack::Pack::load(file)? {
+                    self.search_paths.push(SearchPath::Pack(pak));
+                    found = true;
+                }
+            }
+            let filename = format!("pak{}.pk3", i);
+            if let Some(file) = self.name_match.find_on_disk(&path, &filename) {
+                if let Some(zip) = zip::Zip::load(file.clone())? {
+                    self.search_paths.push(SearchPath::Zip(zip));
+                    numbered.push(file);
+                    found = true;
+                }
+            }
+            if !found {
+                break;
+            }
+        }
+
+        // Then any other .pk3 files, in name order, so that mods can override
+        // the numbered packfiles.
+        let mut others = Vec::new();
+        if path.is_dir() {
+            for entry in path.read_dir()? {
+                let file = entry?.path();
+                let is_pk3 = file.file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| self.name_match.key(n).ends_with(".pk3"));
+                if is_pk3 && file.is_file() && !numbered.contains(&file) {
+                    others.push(file);
+                }
+            }
+        }
+        others.sort();
+        for file in others {
+            if let Some(zip) = zip::Zip::load(file)? {
+                self.search_paths.push(SearchPath::Zip(zip));
             }
         }
 
@@ -152,8 +274,9 @@ impl FileSys {
         Ok(())
     }
 
-    /// Find a file by name, returning a `FsReader` of the file if found.
-    fn find_file(&mut self, name: &str) -> Result<Option<FsReader>, Error> {
+    /// The `SearchPath`s that are used when looking for a file, in priority
+    /// order.
+    fn searched_paths(&self) -> impl Iterator<Item = &SearchPath> {
         // Conditionally skip the first entry, because "gross hack to use quake
         // 1 progs with quake 2 maps".
         let skip_n = match self.use_proghack {
@@ -161,26 +284,37 @@ impl FileSys {
             false => 0,
         };
         // Search paths are stored in reverse priority order.
-        for search in self.search_paths.iter_mut().rev().skip(skip_n) {
-            match search {
-                &mut SearchPath::Pack(ref mut pak) => {
+        self.search_paths.iter().rev().skip(skip_n)
+    }
+
+    /// Find a file by name, returning a `FsReader` of the file if found.
+    ///
+    /// The `FsReader` can read and seek within the file without loading all
+    /// of it into memory.
+    ///
+    /// A `NameError` is returned if the name could reach a file outside of
+    /// the search path; see `name::check()`.  This applies to all of the
+    /// methods that look up a file.
+    pub fn find_file(&self, name: &str) -> Result<Option<FsReader>, Error> {
+        name::check(name)?;
+        for search in self.searched_paths() {
+            match *search {
+                SearchPath::Pack(ref pak) => {
                     // Try to find a matching file contained in the pack.
-                    match pak.file(name) {
-                        Err(e) => return Err(e),
-                        Ok(None) => (),
-                        Ok(Some(reader)) => return Ok(Some(reader)),
+                    if let Some(i) = pak.index_of(name, self.name_match) {
+                        return Ok(Some(pak.file_at(i)?));
+                    }
+                },
+                SearchPath::Zip(ref zip) => {
+                    if let Some(i) = zip.index_of(name, self.name_match) {
+                        return Ok(Some(zip.file_at(i)?));
                     }
                 },
-                &mut SearchPath::Directory(ref dir) => {
+                SearchPath::Directory(ref dir) => {
                     // Try to find a matching file on the disk.
-                    let mut path = dir.clone();
-                    path.push(name);
-                    match path.is_file() {
-                        false => (),
-                        true => {
-                            let file = File::open(path)?;
-                            return Ok(Some(FsReader::for_file(file)?))
-                        },
+                    if let Some(path) = self.name_match.find_on_disk(dir, name) {
+                        let file = File::open(path)?;
+                        return Ok(Some(FsReader::for_file(file)?))
                     }
                 },
             }
@@ -191,25 +325,474 @@ impl FileSys {
 
     /// Load a file. This method allocates the buffer that the data is returned
     /// in.
-    pub fn load_file(&mut self, name: &str) -> Result<Option<Vec<u8>>, Error> {
+    pub fn load_file(&self, name: &str) -> Result<Option<Vec<u8>>, Error> {
         match self.find_file(name)? {
             None => Ok(None),
             Some(mut fsr) => {
-                let mut buf = Vec::with_capacity(fsr.len());
+                // A .pk3 can claim that a file is much larger than it is, so
+                // don't trust the length with more than a little memory.
+                let mut buf =
+                    Vec::with_capacity(fsr.len().min(MAX_LOAD_RESERVE));
                 fsr.read_to_end(&mut buf)?;
                 Ok(Some(buf))
             }
         }
     }
 
-    /// TODO: load a file into the given cache.
-    pub fn load_file_into_cache(&self, _name: &str, _cache: ()) {
-        unimplemented!();
+    /// Load a file and parse it with `parse`.
+    ///
+    /// If parsing fails, the error is wrapped in a `FileError` with the name
+    /// of the file.
+    pub fn load_parsed<T, E, F>(&self, name: &str, parse: F)
+        -> Result<Option<T>, Error>
+        where F: FnOnce(&[u8]) -> Result<T, E>,
+              E: Into<Error>,
+    {
+        match self.load_file(name)? {
+            None => Ok(None),
+            Some(data) => parse(&data)
+                .map(Some)
+                .map_err(|e| FileError {
+                    name: name.to_string(),
+                    error: e.into(),
+                }.into()),
+        }
+    }
+
+    /// List the files whose names match `pattern`, sorted by name.
+    ///
+    /// If `pattern` contains `*` or `?` then it is a glob pattern that must
+    /// match the whole name.  `*` matches any number of characters and `?`
+    /// matches exactly one, but neither will match a `/`.  Otherwise,
+    /// `pattern` is a prefix, and all names that start with it are listed.
+    /// An empty pattern lists everything.
+    ///
+    /// Files from every pack and directory in the search path are included.
+    /// When the same name appears more than once, the entry says which copy
+    /// would be loaded by `load_file()`.
+    pub fn list(&self, pattern: &str) -> Result<Vec<ListEntry>, Error> {
+        let is_glob = pattern.contains(['*', '?']);
+        let pattern = self.name_match.key(pattern);
+        let matches = |key: &str| match is_glob {
+            true => util::glob_match(&pattern, key),
+            false => key.starts_with(&pattern),
+        };
+
+        // Keyed by `NameMatch::key()`, so that names which would load the
+        // same file are merged.  Search paths are visited in priority order,
+        // so the first entry for a name is the one that wins.
+        let mut found = BTreeMap::new();
+        for search in self.searched_paths() {
+            match *search {
+                SearchPath::Pack(ref pak) => {
+                    for (i, info) in pak.file_infos().iter().enumerate() {
+                        let key = self.name_match.key(&info.name);
+                        if matches(&key) {
+                            found.entry(key).or_insert_with(|| ListEntry {
+                                name: info.name.clone(),
+                                source: FileSource::for_pack_file(pak, i),
+                            });
+                        }
+                    }
+                },
+                SearchPath::Zip(ref zip) => {
+                    for (i, entry) in zip.entries().iter().enumerate() {
+                        let key = self.name_match.key(&entry.name);
+                        if matches(&key) {
+                            found.entry(key).or_insert_with(|| ListEntry {
+                                name: entry.name.clone(),
+                                source: FileSource::for_zip_file(zip, i),
+                            });
+                        }
+                    }
+                },
+                SearchPath::Directory(ref dir) => {
+                    if !dir.is_dir() {
+                        continue;
+                    }
+                    let mut files = Vec::new();
+                    collect_files(dir, "", &mut files)?;
+                    for (name, path) in files {
+                        let key = self.name_match.key(&name);
+                        if matches(&key) {
+                            found.entry(key).or_insert_with(|| ListEntry {
+                                name,
+                                source: FileSource::Directory {
+                                    dir: dir.clone(),
+                                    path,
+                                },
+                            });
+                        }
+                    }
+                },
+            }
+        }
+
+        Ok(found.into_values().collect())
+    }
+
+    /// Find out where the named file would be loaded from.
+    ///
+    /// Returns `None` if the file does not exist.  Otherwise, the `Location`
+    /// gives the copy of the file that `load_file()` would use, and every
+    /// other copy in the search path that it shadows.
+    pub fn locate(&self, name: &str) -> Result<Option<Location>, Error> {
+        name::check(name)?;
+        let mut sources = Vec::new();
+        for search in self.searched_paths() {
+            match *search {
+                SearchPath::Pack(ref pak) => {
+                    if let Some(i) = pak.index_of(name, self.name_match) {
+                        sources.push(FileSource::for_pack_file(pak, i));
+                    }
+                },
+                SearchPath::Zip(ref zip) => {
+                    if let Some(i) = zip.index_of(name, self.name_match) {
+                        sources.push(FileSource::for_zip_file(zip, i));
+                    }
+                },
+                SearchPath::Directory(ref dir) => {
+                    if let Some(path) = self.name_match.find_on_disk(dir, name) {
+                        sources.push(FileSource::Directory {
+                            dir: dir.clone(),
+                            path,
+                        });
+                    }
+                },
+            }
+        }
+
+        if sources.is_empty() {
+            return Ok(None);
+        }
+        let winner = sources.remove(0);
+        Ok(Some(Location {
+            winner,
+            shadowed: sources,
+        }))
+    }
+
+    /// Describe the search path, like the `path` console command.
+    ///
+    /// Each search path is listed on its own line, highest priority first.
+    /// Packs and zips also show the number of files they contain.
+    pub fn path_dump(&self) -> String {
+        let mut dump = String::from("Current search path:\n");
+        for search in self.search_paths.iter().rev() {
+            match *search {
+                SearchPath::Pack(ref pak) => {
+                    dump.push_str(&format!("{} ({} files)\n",
+                                           pak.path().display(),
+                                           pak.file_infos().len()));
+                },
+                SearchPath::Zip(ref zip) => {
+                    dump.push_str(&format!("{} ({} files)\n",
+                                           zip.path().display(),
+                                           zip.entries().len()));
+                },
+                SearchPath::Directory(ref dir) => {
+                    dump.push_str(&format!("{}\n", dir.display()));
+                },
+            }
+        }
+        dump
+    }
+
+    /// Load a file into the given buffer, returning the size of the file.
+    ///
+    /// It is an error if the buffer is too small to hold the file.
+    pub fn load_file_into_buf(&self, name: &str, buf: &mut [u8])
+        -> Result<Option<usize>, Error>
+    {
+        match self.find_file(name)? {
+            None => Ok(None),
+            Some(mut fsr) => {
+                let len = fsr.len();
+                if len > buf.len() {
+                    bail!("File {} ({} bytes) is too big for the buffer \
+                           ({} bytes)", name, len, buf.len());
+                }
+                fsr.read_exact(&mut buf[..len])?;
+                Ok(Some(len))
+            }
+        }
+    }
+
+    /// Load a file into memory allocated from the low end of the hunk.
+    pub fn load_file_into_hunk(&self, name: &str, hunk: &mut Hunk)
+        -> Result<Option<HunkBlock>, Error>
+    {
+        match self.find_file(name)? {
+            None => Ok(None),
+            Some(mut fsr) => {
+                // Don't leave the allocation behind if the read fails.
+                let mark = hunk.low_mark();
+                let block = hunk.alloc_low(fsr.len())?;
+                if let Err(e) = fsr.read_exact(hunk.block_mut(&block)) {
+                    hunk.free_to_low_mark(mark)?;
+                    return Err(e.into());
+                }
+                Ok(Some(block))
+            }
+        }
+    }
+
+    /// Load a file into the hunk's cache, using the file name as the name of
+    /// the cache entry.
+    ///
+    /// If the file is already cached, the cached copy is returned without
+    /// reading the file again.
+    pub fn load_file_into_cache<'h>(&self, name: &str, hunk: &'h mut Hunk)
+        -> Result<Option<&'h [u8]>, Error>
+    {
+        if hunk.cache_check(name).is_some() {
+            return Ok(hunk.cache_check(name));
+        }
+        match self.load_file(name)? {
+            None => Ok(None),
+            Some(data) => Ok(Some(hunk.cache_alloc(name, data)?)),
+        }
+    }
+
+    /// The directory that files are written to: the highest-priority
+    /// directory in the search path that isn't read-only.
+    ///
+    /// This is normally the main game directory, unless `-path` was used.
+    pub fn write_dir(&self) -> Result<&Path, Error> {
+        self.search_paths.iter().rev()
+            .filter_map(|search| match *search {
+                SearchPath::Directory(ref dir) => Some(dir.as_path()),
+                _ => None,
+            })
+            .find(|dir| match dir.metadata() {
+                // The directory will be created when a file is written.
+                Err(_) => true,
+                Ok(meta) => !meta.permissions().readonly(),
+            })
+            .ok_or_else(|| format_err!("No writable directory in the search \
+                                        path"))
+    }
+
+    /// Create a file in the `write_dir()`, replacing any file that's already
+    /// there, and return it opened for writing.
+    ///
+    /// `name` is relative to the directory, and any subdirectories that it
+    /// names are created.  A `NameError` is returned if the name could end up
+    /// outside of the directory; see `name::check()`.
+    pub fn create_file(&self, name: &str) -> Result<File, Error> {
+        name::check(name)?;
+        let path = self.write_dir()?.join(name);
+        if let Some(parent) = path.parent() {
+            ::std::fs::create_dir_all(parent)?;
+        }
+        Ok(File::create(path)?)
+    }
+
+    /// Write a file in the `write_dir()`, replacing any file that's already
+    /// there.  See `create_file()`.
+    pub fn write_file(&self, name: &str, data: &[u8]) -> Result<(), Error> {
+        let mut file = self.create_file(name)?;
+        file.write_all(data)?;
+        Ok(())
+    }
+}
+
+/// The contents of `gfx/pop.lmp` in the registered version of Quake.
+pub const POP: [u16; 128] = [
+    0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000, 0x0000,
+    0x0000, 0x0000, 0x6600, 0x0000, 0x0000, 0x0000, 0x6600, 0x0000,
+    0x0000, 0x0066, 0x0000, 0x0000, 0x0000, 0x0000, 0x0067, 0x0000,
+    0x0000, 0x6665, 0x0000, 0x0000, 0x0000, 0x0000, 0x0065, 0x6600,
+    0x0063, 0x6561, 0x0000, 0x0000, 0x0000, 0x0000, 0x0061, 0x6563,
+    0x0064, 0x6561, 0x0000, 0x0000, 0x0000, 0x0000, 0x0061, 0x6564,
+    0x0064, 0x6564, 0x0000, 0x6469, 0x6969, 0x6400, 0x0064, 0x6564,
+    0x0063, 0x6568, 0x6200, 0x0064, 0x6864, 0x0000, 0x6268, 0x6563,
+    0x0000, 0x6567, 0x6963, 0x0064, 0x6764, 0x0063, 0x6967, 0x6500,
+    0x0000, 0x6266, 0x6769, 0x6a68, 0x6768, 0x6a69, 0x6766, 0x6200,
+    0x0000, 0x0062, 0x6566, 0x6666, 0x6666, 0x6666, 0x6562, 0x0000,
+    0x0000, 0x0000, 0x0062, 0x6364, 0x6664, 0x6362, 0x0000, 0x0000,
+    0x0000, 0x0000, 0x0000, 0x0062, 0x6662, 0x0000, 0x0000, 0x0000,
+    0x0000, 0x0000, 0x0000, 0x0061, 0x6661, 0x0000, 0x0000, 0x0000,
+    0x0000, 0x0000, 0x0000, 0x0000, 0x6500, 0x0000, 0x0000, 0x0000,
+    0x0000, 0x0000, 0x0000, 0x0000, 0x6400, 0x0000, 0x0000, 0x0000,
+];
+
+/// A file that was loaded, but couldn't be parsed.
+#[derive(Debug)]
+pub struct FileError {
+    /// The name of the file.
+    pub name: String,
+    /// Why the file couldn't be parsed.  This is normally the parser's own
+    /// error type, which can be had with `downcast_ref()`.
+    pub error: Error,
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.error)
+    }
+}
+
+impl Fail for FileError {
+    fn cause(&self) -> Option<&dyn Fail> {
+        Some(self.error.as_fail())
+    }
+}
+
+/// A file found by `FileSys::list()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ListEntry {
+    /// The name of the file, as stored in its pack or directory.
+    pub name: String,
+    /// Where the file would be loaded from.
+    pub source: FileSource,
+}
+
+/// Where a file in the `FileSys` is stored.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FileSource {
+    /// A file on disk, within a directory in the search path.
+    Directory {
+        /// The directory in the search path.
+        dir: PathBuf,
+        /// The location of the file on disk.
+        path: PathBuf,
+    },
+    /// A file within a .pak file in the search path.
+    Pack {
+        /// The location of the .pak file on disk.
+        pack: PathBuf,
+        /// The index of the file within `Pack::file_infos()`.
+        index: usize,
+        /// The offset of the file's data within the .pak file.
+        offset: u64,
+        /// The size of the file, in bytes.
+        size: usize,
+    },
+    /// A file within a .pk3 file in the search path.
+    Zip {
+        /// The location of the .pk3 file on disk.
+        zip: PathBuf,
+        /// The index of the file within `Zip::entries()`.
+        index: usize,
+        /// The size of the file once decompressed, in bytes.
+        size: usize,
+    },
+}
+
+impl FileSource {
+    fn for_pack_file(pak: &pack::Pack, index: usize) -> Self {
+        let info = &pak.file_infos()[index];
+        FileSource::Pack {
+            pack: pak.path().to_path_buf(),
+            index,
+            offset: info.offset,
+            size: info.size,
+        }
+    }
+
+    fn for_zip_file(zip: &zip::Zip, index: usize) -> Self {
+        FileSource::Zip {
+            zip: zip.path().to_path_buf(),
+            index,
+            size: zip.entries()[index].size,
+        }
+    }
+}
+
+/// The result of `FileSys::locate()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    /// The copy of the file that will be loaded.
+    pub winner: FileSource,
+    /// Other copies of the file, which are lower in the search order and so
+    /// will never be loaded.  Highest priority first.
+    pub shadowed: Vec<FileSource>,
+}
+
+/// Recursively find all files in `dir`, naming them relative to the
+/// directory that the search started in.
+fn collect_files(dir: &Path, prefix: &str, files: &mut Vec<(String, PathBuf)>)
+    -> Result<(), Error>
+{
+    for entry in dir.read_dir()? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let file_name = file_name.to_str()
+            .ok_or_else(|| format_err!("Non-unicode file name {:?}",
+                                       entry.path()))?;
+        let name = format!("{}{}", prefix, file_name);
+        if entry.file_type()?.is_dir() {
+            collect_files(&entry.path(), &format!("{}/", name), files)?;
+        } else {
+            files.push((name, entry.path()));
+        }
     }
+    Ok(())
+}
+
+/// How a file name is matched against the names of the files in the
+/// search path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NameMatch {
+    /// Names must be identical.
+    Exact,
+    /// Quake-style matching: ASCII case is ignored, and `\` is treated the
+    /// same as `/`.  This works the same way for files in packs and files on
+    /// disk, so `Maps\E1M1.BSP` will find `maps/e1m1.bsp`.
+    Folded,
+}
+
+impl NameMatch {
+    /// Convert a name into the form used to compare names.
+    ///
+    /// Two names match if they have the same key.
+    pub fn key(self, name: &str) -> String {
+        match self {
+            NameMatch::Exact => name.to_string(),
+            NameMatch::Folded =>
+                name.chars()
+                    .map(|c| match c {
+                        '\\' => '/',
+                        c => c.to_ascii_lowercase(),
+                    })
+                    .collect(),
+        }
+    }
+
+    /// Look for a file called `name` within the directory `dir`, returning
+    /// its path if it exists.
+    fn find_on_disk(self, dir: &Path, name: &str) -> Option<PathBuf> {
+        // Try the name as given first, since that's the common case.
+        let path = dir.join(name);
+        if path.is_file() {
+            return Some(path);
+        }
+        if self == NameMatch::Exact {
+            return None;
+        }
 
-    /// TODO: load a file into the given buffer.
-    pub fn load_file_into_buf(&self, _name: &str, _buf: &mut [u8]) {
-        unimplemented!();
+        // Walk down the directory tree one component at a time, looking for
+        // an entry that matches each component.
+        let mut path = dir.to_path_buf();
+        for component in name.split(['/', '\\'])
+            .filter(|c| !c.is_empty())
+        {
+            let exact = path.join(component);
+            if exact.exists() {
+                path = exact;
+                continue;
+            }
+            let entry = path.read_dir().ok()?
+                .filter_map(|e| e.ok())
+                .find(|e| e.file_name().to_str()
+                      .is_some_and(|n| n.eq_ignore_ascii_case(component)))?;
+            path = entry.path();
+        }
+        match path.is_file() {
+            true => Some(path),
+            false => None,
+        }
     }
 }
 
@@ -220,6 +803,8 @@ enum SearchPath {
     Directory(PathBuf),
     /// Files in a .pak file.
     Pack(pack::Pack),
+    /// Files in a .pk3 file.
+    Zip(zip::Zip),
 }
 
 
@@ -228,15 +813,13 @@ mod tests {
     use super::*;
     use test_common as common;
 
-    /// Create a new FileSys using the base directory of a full version of
-    /// standard Quake 1.
+    /// Create a new FileSys using the base directory of a synthetic Quake
+    /// install.
     /// Expect a particular search path.
     #[test]
     fn fs_search_path() {
-        let parms = Parms::new(
-            vec!["-basedir".into(), common::base_dir().to_string_lossy().to_string()],
-            "cwd".into());
-        let fs = FileSys::new(&parms).unwrap();
+        let fixture = common::fixture();
+        let fs = FileSys::new(&fixture.parms()).unwrap();
         assert_eq!(fs.search_paths.len(), 3);
 
         match fs.search_paths[0] {
@@ -264,14 +847,571 @@ mod tests {
     #[test]
     /// Load a resource from the file system.
     fn fs_load_file() {
+        let fixture = common::fixture();
+        let fs = FileSys::new(&fixture.parms()).unwrap();
+        let wav = fs.load_file("sound/items/r_item1.wav").unwrap().unwrap();
+
+        // Simple check: does it look like the WAV file we expect?
+        assert_eq!(wav.len(), 6822);
+        assert_eq!(&wav[0..4], b"RIFF");
+    }
+
+    /// Loose files in the game directory are found, but files in a pack take
+    /// priority over them.
+    #[test]
+    fn fs_load_loose_file() {
+        let fixture = common::FixtureBuilder::new()
+            .pak(vec![("progs.dat", b"pak".to_vec())])
+            .file("progs.dat", b"loose".to_vec())
+            .file("maps/start.bsp", b"map".to_vec())
+            .build();
+        let fs = FileSys::new(&fixture.parms()).unwrap();
+        assert_eq!(fs.load_file("progs.dat").unwrap().unwrap(), b"pak");
+        assert_eq!(fs.load_file("maps/start.bsp").unwrap().unwrap(), b"map");
+        assert_eq!(fs.load_file("maps/nope.bsp").unwrap(), None);
+    }
+
+    /// Case-insensitive, slash-normalised lookups work for pack files and
+    /// files on disk.
+    #[test]
+    fn fs_folded_names() {
+        let fixture = common::FixtureBuilder::new()
+            .pak(vec![("Progs/Player.mdl", b"pak".to_vec())])
+            .file("Maps/Start.BSP", b"map".to_vec())
+            .build();
+        let game_dir = fixture.game_dir();
+        ::std::fs::rename(game_dir.join("pak0.pak"), game_dir.join("PAK0.PAK"))
+            .unwrap();
+
+        // Exact matching, the default.
+        let fs = FileSys::new(&fixture.parms()).unwrap();
+        assert_eq!(fs.search_paths.len(), 1);
+        assert_eq!(fs.load_file("maps/start.bsp").unwrap(), None);
+        assert_eq!(fs.load_file("Maps/Start.BSP").unwrap().unwrap(), b"map");
+
+        // Folded matching.
+        let parms = Parms::new(
+            vec!["-basedir".into(),
+                 fixture.base_dir().to_string_lossy().to_string(),
+                 "-nocase".into()],
+            "cwd".into());
+        let fs = FileSys::new(&parms).unwrap();
+        assert_eq!(fs.search_paths.len(), 2);
+        assert_eq!(fs.load_file("progs/player.mdl").unwrap().unwrap(), b"pak");
+        assert_eq!(fs.load_file("PROGS\\PLAYER.MDL").unwrap().unwrap(), b"pak");
+        assert_eq!(fs.load_file("maps/start.bsp").unwrap().unwrap(), b"map");
+        assert_eq!(fs.load_file("maps\\START.bsp").unwrap().unwrap(), b"map");
+        assert_eq!(fs.load_file("maps/nope.bsp").unwrap(), None);
+    }
+
+    #[test]
+    fn fs_list() {
+        let fixture = common::FixtureBuilder::new()
+            .pak(vec![
+                ("maps/e1m1.bsp", b"pak0".to_vec()),
+                ("maps/e1m2.bsp", b"pak0".to_vec()),
+                ("maps/b_bh10.bsp", b"pak0".to_vec()),
+                ("progs.dat", b"pak0".to_vec()),
+            ])
+            .pak(vec![("maps/e1m2.bsp", b"pak1".to_vec())])
+            .file("maps/e1m1.bsp", b"disk".to_vec())
+            .file("maps/mymap.bsp", b"disk".to_vec())
+            .file("s0.sav", b"disk".to_vec())
+            .build();
+        let fs = FileSys::new(&fixture.parms()).unwrap();
+        let pak = |n: usize| fixture.game_dir().join(format!("pak{}.pak", n));
+
+        let maps = fs.list("maps/").unwrap();
+        let names: Vec<&str> = maps.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["maps/b_bh10.bsp", "maps/e1m1.bsp", "maps/e1m2.bsp",
+                           "maps/mymap.bsp"]);
+
+        // Packs take priority over the directory, and later packs over
+        // earlier ones.
+        match maps[1].source {
+            FileSource::Pack { ref pack, index, .. } => {
+                assert_eq!(pack, &pak(0));
+                assert_eq!(index, 0);
+            },
+            ref x => panic!("expected e1m1 from pak0, got {:?}", x),
+        }
+        match maps[2].source {
+            FileSource::Pack { ref pack, index, offset, size } => {
+                assert_eq!(pack, &pak(1));
+                assert_eq!((index, offset, size), (0, 12, 4));
+            },
+            ref x => panic!("expected e1m2 from pak1, got {:?}", x),
+        }
+        match maps[3].source {
+            FileSource::Directory { ref path, .. } => {
+                assert_eq!(path, &fixture.game_dir().join("maps/mymap.bsp"));
+            },
+            ref x => panic!("expected mymap from disk, got {:?}", x),
+        }
+
+        // Globs.
+        let names = |pattern| -> Vec<String> {
+            fs.list(pattern).unwrap().into_iter().map(|e| e.name).collect()
+        };
+        assert_eq!(names("maps/e1m?.bsp"), ["maps/e1m1.bsp", "maps/e1m2.bsp"]);
+        assert_eq!(names("*.sav"), ["s0.sav"]);
+        assert_eq!(names("*.bsp"), Vec::<String>::new());
+        // Everything, including the .pak files in the game directory.
+        assert_eq!(names("").len(), 8);
+    }
+
+    #[test]
+    fn fs_locate() {
+        let fixture = common::FixtureBuilder::new()
+            .pak(vec![
+                ("gfx/conback.lmp", b"pak0".to_vec()),
+                ("progs.dat", b"pak0".to_vec()),
+            ])
+            .pak(vec![("progs.dat", b"pak1".to_vec())])
+            .file("progs.dat", b"disk".to_vec())
+            .build();
+        let fs = FileSys::new(&fixture.parms()).unwrap();
+        let game_dir = fixture.game_dir();
+
+        let location = fs.locate("progs.dat").unwrap().unwrap();
+        assert_eq!(location.winner, FileSource::Pack {
+            pack: game_dir.join("pak1.pak"),
+            index: 0,
+            offset: 12,
+            size: 4,
+        });
+        assert_eq!(location.shadowed, vec![
+            FileSource::Pack {
+                pack: game_dir.join("pak0.pak"),
+                index: 1,
+                offset: 16,
+                size: 4,
+            },
+            FileSource::Directory {
+                dir: game_dir.clone(),
+                path: game_dir.join("progs.dat"),
+            },
+        ]);
+
+        let location = fs.locate("gfx/conback.lmp").unwrap().unwrap();
+        assert!(location.shadowed.is_empty());
+
+        assert_eq!(fs.locate("nope").unwrap(), None);
+    }
+
+    #[test]
+    fn fs_path_dump() {
+        let fixture = common::fixture();
+        let fs = FileSys::new(&fixture.parms()).unwrap();
+        let game_dir = fixture.game_dir();
+        assert_eq!(
+            fs.path_dump(),
+            format!("Current search path:\n\
+                     {}/pak1.pak (1 files)\n\
+                     {}/pak0.pak (3 files)\n\
+                     {}\n",
+                    game_dir.display(), game_dir.display(),
+                    game_dir.display()));
+    }
+
+    /// Files can be loaded from several threads at once.
+    #[test]
+    fn fs_shared_between_threads() {
+        use std::sync::Arc;
+        use std::thread;
+
+        fn assert_sync<T: Sync + Send>() {}
+        assert_sync::<FileSys>();
+
+        let fixture = common::fixture();
+        let fs = Arc::new(FileSys::new(&fixture.parms()).unwrap());
+        let threads: Vec<_> = (0..4).map(|_| {
+            let fs = fs.clone();
+            thread::spawn(move || {
+                for _ in 0..10 {
+                    let wav = fs.load_file("sound/items/r_item1.wav")
+                        .unwrap().unwrap();
+                    assert_eq!(&wav[0..4], b"RIFF");
+                    let map = fs.load_file("maps/e1m1.bsp").unwrap().unwrap();
+                    assert_eq!(map, b"not really a map");
+                }
+            })
+        }).collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+    }
+
+    /// Files on disk can seek too.
+    #[test]
+    fn fs_seek_loose_file() {
+        use std::io::{Seek, SeekFrom};
+
+        let fixture = common::FixtureBuilder::new()
+            .file("demo1.dem", b"0123456789".to_vec())
+            .build();
+        let fs = FileSys::new(&fixture.parms()).unwrap();
+        let mut reader = fs.find_file("demo1.dem").unwrap().unwrap();
+        let mut buf = [0; 3];
+
+        assert_eq!(reader.seek(SeekFrom::End(-3)).unwrap(), 7);
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"789");
+        assert!(reader.seek(SeekFrom::Current(1)).is_err());
+        assert!(reader.seek(SeekFrom::Current(-11)).is_err());
+    }
+
+    /// Parse errors come back with the name of the file, and the parser's
+    /// error can still be had.
+    #[test]
+    fn fs_load_parsed() {
+        use bsp::{Bsp, BspError};
+
+        let fixture = common::fixture();
+        let fs = FileSys::new(&fixture.parms()).unwrap();
+        let name = "maps/e1m1.bsp";
+
+        let len = fs.load_parsed(name, |data| Ok::<_, Error>(data.len()));
+        assert_eq!(len.unwrap(), Some(16));
+        assert!(fs.load_parsed("nope", Bsp::from_bytes).unwrap().is_none());
+
+        let err = fs.load_parsed(name, Bsp::from_bytes).unwrap_err();
+        let file_err = err.downcast_ref::<FileError>().unwrap();
+        assert_eq!(file_err.name, name);
+        let bsp_err = file_err.error.downcast_ref::<BspError>().unwrap();
+        assert!(err.to_string().starts_with("maps/e1m1.bsp: "), "{}", err);
+        assert!(err.to_string().ends_with(&bsp_err.to_string()), "{}", err);
+    }
+
+    #[test]
+    fn fs_load_file_into_memory() {
+        let fixture = common::fixture();
+        let fs = FileSys::new(&fixture.parms()).unwrap();
+        let name = "maps/e1m1.bsp";
+        let contents = b"not really a map";
+
+        let mut buf = [0; 20];
+        assert_eq!(fs.load_file_into_buf(name, &mut buf).unwrap(), Some(16));
+        assert_eq!(&buf[..16], contents);
+        assert!(fs.load_file_into_buf(name, &mut buf[..15]).is_err());
+        assert_eq!(fs.load_file_into_buf("nope", &mut buf).unwrap(), None);
+
+        let mut hunk = Hunk::new(40);
+        let block = fs.load_file_into_hunk(name, &mut hunk).unwrap().unwrap();
+        assert_eq!(hunk.block(&block), contents);
+        assert_eq!(hunk.free_space(), 24);
+
+        assert_eq!(fs.load_file_into_cache(name, &mut hunk).unwrap().unwrap(),
+                   contents);
+        assert_eq!(hunk.free_space(), 8);
+        assert_eq!(fs.load_file_into_cache(name, &mut hunk).unwrap().unwrap(),
+                   contents);
+        assert_eq!(hunk.free_space(), 8);
+
+        // The hunk has a hard limit.
+        let mut hunk = Hunk::new(10);
+        assert!(fs.load_file_into_hunk(name, &mut hunk).is_err());
+        assert!(fs.load_file_into_cache(name, &mut hunk).is_err());
+    }
+
+    /// A file that fails to read doesn't use up any of the hunk.
+    #[test]
+    fn fs_load_file_into_hunk_error() {
+        // Replace the start of the deflate stream with an invalid block type.
+        let mut zip = common::zip_bytes(&[("bad", vec![b'a'; 100], true)]);
+        let data_start = 30 + "bad".len();
+        for b in &mut zip[data_start..data_start+4] {
+            *b = 0xff;
+        }
+        let fixture = common::FixtureBuilder::new()
+            .file("pak0.pk3", zip)
+            .build();
+        let fs = FileSys::new(&fixture.parms()).unwrap();
+
+        let mut hunk = Hunk::new(200);
+        assert!(fs.load_file_into_hunk("bad", &mut hunk).is_err());
+        assert_eq!(hunk.free_space(), 200);
+    }
+
+    /// A file in a .pk3 that claims to be huge is an error, and doesn't
+    /// reserve all that memory up front.
+    #[test]
+    fn fs_load_file_huge() {
+        use byteorder::{ByteOrder, LittleEndian};
+
+        let mut zip = common::zip_bytes(&[("huge", vec![b'a'; 100], true)]);
+        let dir_start = zip.windows(4)
+            .position(|w| w == b"PK\x01\x02")
+            .unwrap();
+        LittleEndian::write_u32(&mut zip[dir_start+24..dir_start+28],
+                                0xffff_fff0);
+        let fixture = common::FixtureBuilder::new()
+            .file("pak0.pk3", zip)
+            .build();
+        let fs = FileSys::new(&fixture.parms()).unwrap();
+
+        assert!(fs.load_file("huge").is_err());
+    }
+
+    /// .pk3 files next to the .pak files are searched, and override them.
+    #[test]
+    fn fs_zips() {
+        let fixture = common::FixtureBuilder::new()
+            .pak(vec![
+                ("progs.dat", b"pak0".to_vec()),
+                ("gfx.wad", b"pak0".to_vec()),
+            ])
+            .file("pak0.pk3", common::zip_bytes(&[
+                ("progs.dat", b"pak0.pk3".to_vec(), true),
+                ("maps/", Vec::new(), false),
+                ("maps/mod.bsp", b"pak0.pk3".to_vec(), false),
+            ]))
+            .file("zzz_mod.pk3", common::zip_bytes(&[
+                ("maps/mod.bsp", b"zzz_mod.pk3".to_vec(), true),
+            ]))
+            .build();
+        let game_dir = fixture.game_dir();
+        let fs = FileSys::new(&fixture.parms()).unwrap();
+        assert_eq!(
+            fs.path_dump(),
+            format!("Current search path:\n\
+                     {0}/zzz_mod.pk3 (1 files)\n\
+                     {0}/pak0.pk3 (2 files)\n\
+                     {0}/pak0.pak (2 files)\n\
+                     {0}\n",
+                    game_dir.display()));
+
+        assert_eq!(fs.load_file("progs.dat").unwrap().unwrap(), b"pak0.pk3");
+        assert_eq!(fs.load_file("gfx.wad").unwrap().unwrap(), b"pak0");
+        assert_eq!(fs.load_file("maps/mod.bsp").unwrap().unwrap(),
+                   b"zzz_mod.pk3");
+
+        let location = fs.locate("maps/mod.bsp").unwrap().unwrap();
+        assert_eq!(location.winner, FileSource::Zip {
+            zip: game_dir.join("zzz_mod.pk3"),
+            index: 0,
+            size: 11,
+        });
+        assert_eq!(location.shadowed.len(), 1);
+        let names: Vec<String> = fs.list("maps/").unwrap()
+            .into_iter().map(|e| e.name).collect();
+        assert_eq!(names, ["maps/mod.bsp"]);
+
+        // A .pk3 can be given in -path.
+        let parms = Parms::new(
+            vec!["-basedir".into(),
+                 fixture.base_dir().to_string_lossy().to_string(),
+                 "-path".into(),
+                 game_dir.join("zzz_mod.pk3").to_string_lossy().to_string()],
+            "cwd".into());
+        let fs = FileSys::new(&parms).unwrap();
+        assert_eq!(fs.search_paths.len(), 5);
+        assert_eq!(fs.locate("maps/mod.bsp").unwrap().unwrap().shadowed.len(),
+                   2);
+    }
+
+    #[test]
+    fn fs_write_file() {
+        let fixture = common::fixture();
+        let fs = FileSys::new(&fixture.parms()).unwrap();
+        let game_dir = fixture.game_dir();
+        assert_eq!(fs.write_dir().unwrap(), game_dir);
+
+        fs.write_file("config.cfg", b"bind x +jump").unwrap();
+        fs.write_file("save/s0.sav", b"save").unwrap();
+        assert_eq!(::std::fs::read(game_dir.join("save/s0.sav")).unwrap(),
+                   b"save");
+        assert_eq!(fs.load_file("config.cfg").unwrap().unwrap(),
+                   b"bind x +jump");
+
+        // Writing again replaces the file.
+        {
+            let mut file = fs.create_file("config.cfg").unwrap();
+            file.write_all(b"unbindall").unwrap();
+        }
+        assert_eq!(fs.load_file("config.cfg").unwrap().unwrap(), b"unbindall");
+
+        // Nothing outside of the directory.
+        for name in &["", "/tmp/x", "\\x", "c:x", "../x", "save/../../x",
+                      "save\\..\\..\\x", "x\0"] {
+            assert!(fs.write_file(name, b"nope").is_err(), "{:?}", name);
+        }
+        assert!(!fixture.base_dir().join("x").exists());
+    }
+
+    /// Files are written to the highest-priority directory.
+    #[test]
+    fn fs_write_dir() {
+        let fixture = common::fixture();
+        let base_dir = fixture.base_dir().to_string_lossy().to_string();
+
+        // The -game directory doesn't have to exist yet.
         let parms = Parms::new(
-            vec!["-basedir".into(), common::base_dir().to_string_lossy().to_string()],
+            vec!["-basedir".into(), base_dir.clone(),
+                 "-game".into(), "mymod".into()],
             "cwd".into());
-        let mut fs = FileSys::new(&parms).unwrap();
+        let fs = FileSys::new(&parms).unwrap();
+        fs.write_file("s0.sav", b"save").unwrap();
+        assert!(fixture.base_dir().join("mymod/s0.sav").is_file());
+
+        let parms = Parms::new(
+            vec!["-basedir".into(), base_dir,
+                 "-path".into(),
+                 fixture.pak0_path().to_string_lossy().to_string(),
+                 fixture.base_dir().join("out").to_string_lossy().to_string()],
+            "cwd".into());
+        let fs = FileSys::new(&parms).unwrap();
+        assert_eq!(fs.write_dir().unwrap(), fixture.base_dir().join("out"));
+    }
+
+    /// Lookups can't escape from the directories in the search path.
+    #[test]
+    fn fs_traversal() {
+        let fixture = common::fixture();
+        ::std::fs::write(fixture.base_dir().join("secret"), b"secret").unwrap();
+        let parms = Parms::new(
+            vec!["-basedir".into(),
+                 fixture.base_dir().to_string_lossy().to_string(),
+                 "-nocase".into()],
+            "cwd".into());
+        let fs = FileSys::new(&parms).unwrap();
+        let secret = fixture.base_dir().join("secret");
+
+        let name_error = |e: Error| e.downcast::<NameError>().unwrap();
+        let traversal = ["../secret", "..\\secret", "maps/../../secret",
+                         "MAPS\\..\\..\\SECRET"];
+        for name in &traversal {
+            assert_eq!(name_error(fs.load_file(name).unwrap_err()),
+                       NameError::ParentDir(name.to_string()));
+            assert!(fs.locate(name).is_err());
+            assert!(fs.find_file(name).is_err());
+        }
+        let absolute = secret.to_string_lossy().to_string();
+        assert_eq!(name_error(fs.load_file(&absolute).unwrap_err()),
+                   NameError::Absolute(absolute.clone()));
+        assert_eq!(name_error(fs.load_file("").unwrap_err()), NameError::Empty);
+        assert_eq!(name_error(fs.load_file("secret\0").unwrap_err()),
+                   NameError::Nul("secret\0".into()));
+
+        let mut buf = [0; 10];
+        assert!(fs.load_file_into_buf("../secret", &mut buf).is_err());
+        let mut hunk = Hunk::new(100);
+        assert!(fs.load_file_into_hunk("../secret", &mut hunk).is_err());
+        assert!(fs.load_file_into_cache("../secret", &mut hunk).is_err());
+
+        // Writes are checked the same way.
+        assert_eq!(name_error(fs.write_file("../secret", b"x").unwrap_err()),
+                   NameError::ParentDir("../secret".into()));
+        assert_eq!(::std::fs::read(&secret).unwrap(), b"secret");
+
+        // Names that only look a bit like traversal are fine.
+        fs.write_file("..notes", b"notes").unwrap();
+        assert_eq!(fs.load_file("..notes").unwrap().unwrap(), b"notes");
+        assert_eq!(fs.load_file("maps/..e1m1.bsp").unwrap(), None);
+    }
+
+    /// The contents of a genuine `gfx/pop.lmp`.
+    fn pop_lmp() -> Vec<u8> {
+        POP.iter().flat_map(|&p| vec![(p >> 8) as u8, p as u8]).collect()
+    }
+
+    #[test]
+    fn fs_registered() {
+        // Shareware.
+        let fixture = common::fixture();
+        let fs = FileSys::new(&fixture.parms()).unwrap();
+        assert!(!fs.is_registered());
+
+        // Registered.
+        let fixture = common::FixtureBuilder::new()
+            .pak(vec![("gfx/pop.lmp", pop_lmp())])
+            .build();
+        let fs = FileSys::new(&fixture.parms()).unwrap();
+        assert!(fs.is_registered());
+
+        // Tampered with.
+        let mut pop = pop_lmp();
+        pop[20] ^= 1;
+        let fixture = common::FixtureBuilder::new()
+            .pak(vec![("gfx/pop.lmp", pop)])
+            .build();
+        assert!(FileSys::new(&fixture.parms()).is_err());
+    }
+
+    #[test]
+    fn fs_modified() {
+        // The fixture's pak0 is not the original.
+        let fixture = common::fixture();
+        let fs = FileSys::new(&fixture.parms()).unwrap();
+        assert!(fs.is_modified());
+
+        // Neither is a missing pak0.
+        let fixture = common::FixtureBuilder::new().build();
+        let fs = FileSys::new(&fixture.parms()).unwrap();
+        assert!(fs.is_modified());
+    }
+
+    /// Create a new FileSys using the base directory of a full version of
+    /// standard Quake 1.
+    /// Expect a particular search path.
+    #[cfg(feature = "retail-data")]
+    #[test]
+    fn retail_fs_search_path() {
+        let parms = common::parms_for(&common::base_dir());
+        let fs = FileSys::new(&parms).unwrap();
+        assert_eq!(fs.search_paths.len(), 3);
+
+        match fs.search_paths[0] {
+            SearchPath::Directory(ref path) => {
+                assert!(path.to_str().unwrap().ends_with("id1"));
+            },
+            ref x => panic!("expected a directory search path for 'id1', got {:?}", x),
+        }
+
+        match fs.search_paths[1] {
+            SearchPath::Pack(ref p) => {
+                assert!(p.path().to_str().unwrap().ends_with("pak0.pak"));
+            },
+            ref x => panic!("expected a pack search path for 'pak0.pak', got {:?}", x),
+        }
+
+        match fs.search_paths[2] {
+            SearchPath::Pack(ref p) => {
+                assert!(p.path().to_str().unwrap().ends_with("pak1.pak"));
+            },
+            ref x => panic!("expected a pack search path for 'pak1.pak', got {:?}", x),
+        }
+    }
+
+    /// Load a resource from the file system of a full version of standard
+    /// Quake 1.
+    #[cfg(feature = "retail-data")]
+    #[test]
+    fn retail_fs_load_file() {
+        let parms = common::parms_for(&common::base_dir());
+        let fs = FileSys::new(&parms).unwrap();
         let wav = fs.load_file("sound/items/r_item1.wav").unwrap().unwrap();
 
         // Simple check: does it look like the WAV file we expect?
         assert_eq!(wav.len(), 6822);
         assert_eq!(&wav[0..4], b"RIFF");
     }
+
+    /// A full version of standard Quake 1 is unmodified, and a `-game` makes
+    /// it modified.
+    #[cfg(feature = "retail-data")]
+    #[test]
+    fn retail_fs_modified() {
+        let parms = common::parms_for(&common::base_dir());
+        let fs = FileSys::new(&parms).unwrap();
+        assert!(!fs.is_modified());
+
+        let parms = Parms::new(
+            vec!["-basedir".into(),
+                 common::base_dir().to_string_lossy().to_string(),
+                 "-game".into(), "nonexistent".into()],
+            "cwd".into());
+        let fs = FileSys::new(&parms).unwrap();
+        assert!(fs.is_modified());
+    }
 }
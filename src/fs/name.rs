@@ -0,0 +1,111 @@
+// Copyright (C) 2018 Adrian Chan
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+//
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 59 Temple Place - Suite 330, Boston, MA  02111-1307, USA.
+
+//! Validation of the names used to look up files in the Quake filesystem.
+//!
+//! Names can come from maps, mods and the network, so a name must not be able
+//! to reach a file outside of the directories in the search path.
+
+use std::error::Error;
+use std::fmt;
+
+
+/// Why a file name was rejected.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NameError {
+    /// The name is empty.
+    Empty,
+    /// The name is an absolute path: it starts with `/` or `\`, or has a
+    /// drive letter or other `:` prefix.
+    Absolute(String),
+    /// The name has a `..` component.
+    ParentDir(String),
+    /// The name contains a nul byte, which would truncate it.
+    Nul(String),
+}
+
+/// Check that `name` is safe to look up relative to a directory in the
+/// search path.
+///
+/// Both `/` and `\` are treated as separators, since either may end up as a
+/// separator on disk.  Empty and `.` components are allowed, because they
+/// can't leave the directory.
+pub fn check(name: &str) -> Result<(), NameError> {
+    if name.is_empty() {
+        return Err(NameError::Empty);
+    }
+    if name.contains('\0') {
+        return Err(NameError::Nul(name.to_string()));
+    }
+    if name.starts_with(['/', '\\']) || name.contains(':') {
+        return Err(NameError::Absolute(name.to_string()));
+    }
+    if name.split(['/', '\\']).any(|c| c == "..") {
+        return Err(NameError::ParentDir(name.to_string()));
+    }
+    Ok(())
+}
+
+impl fmt::Display for NameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NameError::Empty =>
+                write!(f, "file name is empty"),
+            NameError::Absolute(ref name) =>
+                write!(f, "file name {} must be a relative path", name),
+            NameError::ParentDir(ref name) =>
+                write!(f, "file name {} must not contain ..", name),
+            NameError::Nul(ref name) =>
+                write!(f, "file name {:?} contains a nul byte", name),
+        }
+    }
+}
+
+impl Error for NameError {}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_names() {
+        for name in &["progs.dat", "maps/e1m1.bsp", "Maps\\E1M1.BSP",
+                      "./progs.dat", "sound//misc/h2ohit1.wav", "a..b",
+                      "..progs", "maps/..."] {
+            assert_eq!(check(name), Ok(()), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn invalid_names() {
+        let err = |name: &str| check(name).unwrap_err();
+        assert_eq!(err(""), NameError::Empty);
+        assert_eq!(err("progs.dat\0.txt"),
+                   NameError::Nul("progs.dat\0.txt".into()));
+
+        for name in &["/etc/passwd", "\\windows\\win.ini", "\\\\server\\share",
+                      "c:/autoexec.bat", "C:autoexec.bat", "file:x"] {
+            assert_eq!(err(name), NameError::Absolute(name.to_string()));
+        }
+
+        for name in &["..", "../../etc/passwd", "maps/../../x", "maps/..",
+                      "..\\..\\x", "maps\\..\\..\\x", "maps/..\\../x"] {
+            assert_eq!(err(name), NameError::ParentDir(name.to_string()));
+        }
+    }
+}
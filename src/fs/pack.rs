@@ -20,67 +20,139 @@
 
 //! Things related to working with .pak files.
 
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
 use std::fs::File;
-use std::io::{BufReader, Read};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::ops::Range;
 use std::path::{Path, PathBuf};
 
 use failure::Error;
+use tempfile::NamedTempFile;
 
-use fs::FsReader;
-use try_from_temp::TryFromTemp;
-use util;
+use crc;
+use fs::{collect_files, FsReader, NameMatch};
+use util::{self, CStrError};
 
 
 /// Represents a .pak file -- a bundle of other files, a bit like a .tar file.
 #[derive(Debug)]
 pub struct Pack {
-    /// The location of this .pak file.
+    /// The location of this .pak file.  Each `FsReader` opens its own handle
+    /// to the file, so the `Pack` doesn't need to keep one open.
     file_path: PathBuf,
-    /// An open reader.
-    reader: BufReader<File>,
     /// Information about each file contained within the pack.
     file_infos: Vec<FileInfo>,
+    /// Maps each file name to its index in `file_infos`.
+    index: HashMap<String, usize>,
+    /// Maps each folded file name (see `NameMatch::Folded`) to its index in
+    /// `file_infos`.
+    folded_index: HashMap<String, usize>,
+    /// The CRC of the file information table, as stored on disk.
+    crc: u16,
 }
 
-const MAX_FILES_IN_PACK: usize = 2048;
+/// The maximum number of files that a `Pack` may contain.
+pub const MAX_FILES_IN_PACK: usize = 2048;
+/// The number of files in the original, unmodified pak0.pak.
+pub const PAK0_COUNT: usize = 339;
+/// The CRC of the file information table in the original, unmodified
+/// pak0.pak.
+pub const PAK0_CRC: u16 = 32981;
 
 impl Pack {
     /// Attempt to load the given .pak file.
     ///
     /// If no file exists at the given path then `Ok(None)` is returned.
     /// Otherwise, an attempt is made to read the file and parse its contents.
-    /// Any problems while doing this result in an `Err(Error)` being
+    /// Any problems while doing this result in an `Err(PackError)` being
     /// returned.
-    pub fn load(path: PathBuf) -> Result<Option<Self>, Error> {
-        use std::io::{Seek, SeekFrom};
-
+    pub fn load(path: PathBuf) -> Result<Option<Self>, PackError> {
         let file = match File::open(&path) {
             Err(_) => return Ok(None),
             // If file doesn't exist it's not an error.
             Ok(file) => file
         };
-        let mut reader = BufReader::new(file);
+        Ok(Some(Self::from_reader(BufReader::new(file), path)?))
+    }
 
-        let header = PackHeader::from(&mut reader)?;
+    /// Parse a .pak file from `reader`.
+    ///
+    /// `path` is recorded as the location of the .pak file; it's where
+    /// `file_at()` will read the contents of the files from.
+    ///
+    /// The file information table, and the offset and size of every file,
+    /// are checked against the size of the .pak file.
+    pub fn from_reader<R: Read + Seek>(mut reader: R, path: PathBuf)
+        -> Result<Self, PackError>
+    {
+        let pack_len = reader.seek(SeekFrom::End(0))
+            .and_then(|len| reader.seek(SeekFrom::Start(0)).map(|_| len))
+            .map_err(|e| PackError::io(&path, e))?;
+
+        let header = PackHeader::from(&mut reader, &path)?;
         let num_pack_files = header.dir_len / FILE_INFO_SIZE_ON_DISK;
         if num_pack_files > MAX_FILES_IN_PACK {
-            bail!("too many files ({}) in pack", num_pack_files);
+            return Err(PackError::TooManyFiles {
+                path,
+                count: num_pack_files,
+            });
+        }
+        if header.dir_offset + header.dir_len as u64 > pack_len {
+            return Err(PackError::OutOfBounds {
+                path,
+                at: header.dir_offset,
+                len: header.dir_len as u64,
+            });
         }
 
+        let mut dir = vec![0; header.dir_len];
+        reader.seek(SeekFrom::Start(header.dir_offset))
+            .and_then(|_| reader.read_exact(&mut dir))
+            .map_err(|e| PackError::io(&path, e))?;
+        // The CRC is used to tell whether this is an original id pack.
+        let crc = crc::block(&dir);
+
         let mut file_infos = Vec::with_capacity(num_pack_files);
-        reader.seek(SeekFrom::Start(header.dir_offset))?;
-        for _i in 0..num_pack_files {
-            let info = FileInfo::from(&mut reader)?;
+        let mut dir_reader = &dir[..];
+        for i in 0..num_pack_files {
+            let at = header.dir_offset + (i * FILE_INFO_SIZE_ON_DISK) as u64;
+            let info = FileInfo::from(&mut dir_reader, &path, at)?;
+            if info.offset + info.size as u64 > pack_len {
+                return Err(PackError::OutOfBounds {
+                    path,
+                    at: info.offset,
+                    len: info.size as u64,
+                });
+            }
             file_infos.push(info);
         }
-        // Skip the CRC check, I don't care whether it was modified or not.
 
-        Ok(Some(Self {
+        // If a name appears more than once, the first one wins, as it would
+        // with a linear search.
+        let mut index = HashMap::with_capacity(num_pack_files);
+        let mut folded_index = HashMap::with_capacity(num_pack_files);
+        for (i, info) in file_infos.iter().enumerate() {
+            index.entry(info.name.clone()).or_insert(i);
+            folded_index.entry(NameMatch::Folded.key(&info.name))
+                .or_insert(i);
+        }
+
+        Ok(Self {
             file_path: path,
-            reader,
             file_infos,
-        }))
+            index,
+            folded_index,
+            crc,
+        })
+    }
+
+    /// The CRC of the pack's file information table.
+    ///
+    /// Compare with `PAK0_CRC` to tell whether this is the original pak0.pak.
+    pub fn crc(&self) -> u16 {
+        self.crc
     }
 
     /// The location of this .pak file on disk.
@@ -88,18 +160,42 @@ impl Pack {
         self.file_path.as_path()
     }
 
-    /// Retrieve an `FsReader` for the given file within the `Pack`, if it
+    /// Information about each file contained within the pack, in the order
+    /// they are stored.
+    pub fn file_infos(&self) -> &[FileInfo] {
+        &self.file_infos
+    }
+
+    /// Find the index (within `file_infos()`) of the named file, if it
     /// exists.
-    pub fn file(&mut self, name: &str) -> Result<Option<FsReader>, Error> {
-        match self.file_infos.iter().find(|f| f.name == name) {
-            Some(info) => {
-                Ok(Some(
-                    FsReader::for_pack_file(
-                        &mut self.reader, &info)?))
-            },
+    pub fn index_of(&self, name: &str, matching: NameMatch) -> Option<usize> {
+        match matching {
+            NameMatch::Exact => self.index.get(name).cloned(),
+            NameMatch::Folded =>
+                self.folded_index.get(&matching.key(name)).cloned(),
+        }
+    }
+
+    /// Retrieve an `FsReader` for the given file within the `Pack`, if it
+    /// exists.  The name must match exactly.
+    pub fn file(&self, name: &str) -> Result<Option<FsReader>, Error> {
+        match self.index_of(name, NameMatch::Exact) {
+            Some(i) => Ok(Some(self.file_at(i)?)),
             None => Ok(None),
         }
     }
+
+    /// Retrieve an `FsReader` for the file at the given index within
+    /// `file_infos()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index is out of range.
+    pub fn file_at(&self, i: usize) -> Result<FsReader, Error> {
+        let info = &self.file_infos[i];
+        let file = File::open(&self.file_path)?;
+        Ok(FsReader::for_pack_file(file, info)?)
+    }
 }
 
 /// Represents the `Pack`'s header information.
@@ -113,28 +209,40 @@ struct PackHeader {
 }
 
 impl PackHeader {
-    /// Parse the `PackHeader` from a reader at its current position.
-
-    // TODO: switch to impl trait?
-    fn from(reader: &mut BufReader<File>) -> Result<Self, Error> {
+    /// Parse the `PackHeader` from the start of the .pak file at `path`.
+    fn from<R: Read>(reader: &mut R, path: &Path) -> Result<Self, PackError> {
         use byteorder::{ByteOrder, LittleEndian};
 
-        const HEADER_SIZE: usize = 4+4+4;
         const ID_SLICE: Range<usize> = 0..4;
         const DIR_OFFSET_SLICE: Range<usize> = 4..8;
         const DIR_LEN_SLICE: Range<usize> = 8..12;
 
-        let mut header = [0; HEADER_SIZE];
-        reader.read_exact(&mut header)?;
+        let mut header = [0; PACK_HEADER_SIZE];
+        reader.read_exact(&mut header)
+            .map_err(|e| PackError::io(path, e))?;
 
         if &header[ID_SLICE] != b"PACK" {
-            bail!("Not a packfile header");
+            return Err(PackError::BadMagic { path: path.to_path_buf() });
         }
 
-        let dir_offset = u64::try_from_temp(
-            LittleEndian::read_i32(&header[DIR_OFFSET_SLICE]))?;
-        let dir_len = usize::try_from_temp(
-            LittleEndian::read_i32(&header[DIR_LEN_SLICE]))?;
+        let dir_offset = LittleEndian::read_i32(&header[DIR_OFFSET_SLICE]);
+        if dir_offset < 0 {
+            return Err(PackError::NegativeOffset {
+                path: path.to_path_buf(),
+                at: DIR_OFFSET_SLICE.start as u64,
+                value: dir_offset,
+            });
+        }
+        let dir_len = LittleEndian::read_i32(&header[DIR_LEN_SLICE]);
+        if dir_len < 0 {
+            return Err(PackError::NegativeSize {
+                path: path.to_path_buf(),
+                at: DIR_LEN_SLICE.start as u64,
+                value: dir_len,
+            });
+        }
+        let dir_offset = dir_offset as u64;
+        let dir_len = dir_len as usize;
 
         Ok(Self {
             dir_offset,
@@ -157,26 +265,52 @@ pub struct FileInfo {
 }
 
 const FILE_INFO_SIZE_ON_DISK: usize = 56+4+4;
+/// The space reserved for a file name on disk, including the nul terminator.
+const FILE_NAME_SIZE_ON_DISK: usize = 56;
+const PACK_HEADER_SIZE: usize = 4+4+4;
 
 impl FileInfo {
     /// Parse the `FileInfo` from a reader at its current position.
-
-    // TODO: switch to impl trait?
-    fn from(reader: &mut BufReader<File>) -> Result<Self, Error> {
+    ///
+    /// `path` is the .pak file, and `at` is the offset of the `FileInfo`
+    /// within it; they are only used to describe errors.
+    fn from<R: Read>(reader: &mut R, path: &Path, at: u64)
+        -> Result<Self, PackError>
+    {
         use byteorder::{ByteOrder, LittleEndian};
 
-        const NAME_SLICE: Range<usize> = 0..56;
+        const NAME_SLICE: Range<usize> = 0..FILE_NAME_SIZE_ON_DISK;
         const OFFSET_SLICE: Range<usize> = 56..60;
         const SIZE_SLICE: Range<usize> = 60..64;
 
         let mut info = [0; FILE_INFO_SIZE_ON_DISK];
-        reader.read_exact(&mut info)?;
-
-        let name = util::cstr_buf_to_string(&info[NAME_SLICE])?;
-        let offset = u64::try_from_temp(
-            LittleEndian::read_i32(&info[OFFSET_SLICE]))?;
-        let size = usize::try_from_temp(
-            LittleEndian::read_i32(&info[SIZE_SLICE]))?;
+        reader.read_exact(&mut info)
+            .map_err(|e| PackError::io(path, e))?;
+
+        let name = util::cstr_buf_to_string(&info[NAME_SLICE])
+            .map_err(|error| PackError::BadFileName {
+                path: path.to_path_buf(),
+                at,
+                error,
+            })?;
+        let offset = LittleEndian::read_i32(&info[OFFSET_SLICE]);
+        if offset < 0 {
+            return Err(PackError::NegativeOffset {
+                path: path.to_path_buf(),
+                at: at + OFFSET_SLICE.start as u64,
+                value: offset,
+            });
+        }
+        let size = LittleEndian::read_i32(&info[SIZE_SLICE]);
+        if size < 0 {
+            return Err(PackError::NegativeSize {
+                path: path.to_path_buf(),
+                at: at + SIZE_SLICE.start as u64,
+                value: size,
+            });
+        }
+        let offset = offset as u64;
+        let size = size as usize;
 
         Ok(Self {
             name,
@@ -186,17 +320,357 @@ impl FileInfo {
     }
 }
 
+/// The ways that loading a .pak file can fail.
+///
+/// Each error has the path of the .pak file.  Errors about a particular value
+/// in the file also have `at`, the offset of that value within the file.
+#[derive(Debug)]
+pub enum PackError {
+    /// The file couldn't be read.
+    Io {
+        /// The .pak file.
+        path: PathBuf,
+        /// The underlying error.
+        error: io::Error,
+    },
+    /// The file doesn't start with `PACK`, so isn't a .pak file.
+    BadMagic {
+        /// The .pak file.
+        path: PathBuf,
+    },
+    /// The pack has more than `MAX_FILES_IN_PACK` files.
+    TooManyFiles {
+        /// The .pak file.
+        path: PathBuf,
+        /// The number of files in the pack.
+        count: usize,
+    },
+    /// An offset is negative.
+    NegativeOffset {
+        /// The .pak file.
+        path: PathBuf,
+        /// Where the offset is stored.
+        at: u64,
+        /// The offset.
+        value: i32,
+    },
+    /// A size is negative.
+    NegativeSize {
+        /// The .pak file.
+        path: PathBuf,
+        /// Where the size is stored.
+        at: u64,
+        /// The size.
+        value: i32,
+    },
+    /// Part of the pack extends past the end of the file.
+    OutOfBounds {
+        /// The .pak file.
+        path: PathBuf,
+        /// The offset of the part.
+        at: u64,
+        /// The size of the part, in bytes.
+        len: u64,
+    },
+    /// A file name isn't a valid string.
+    BadFileName {
+        /// The .pak file.
+        path: PathBuf,
+        /// Where the `FileInfo` with the bad name is stored.
+        at: u64,
+        /// Why the name isn't valid.
+        error: CStrError,
+    },
+}
+
+impl PackError {
+    fn io(path: &Path, error: io::Error) -> Self {
+        PackError::Io { path: path.to_path_buf(), error }
+    }
+
+    /// The .pak file that the error is about.
+    pub fn path(&self) -> &Path {
+        match *self {
+            PackError::Io { ref path, .. }
+            | PackError::BadMagic { ref path }
+            | PackError::TooManyFiles { ref path, .. }
+            | PackError::NegativeOffset { ref path, .. }
+            | PackError::NegativeSize { ref path, .. }
+            | PackError::OutOfBounds { ref path, .. }
+            | PackError::BadFileName { ref path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for PackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: ", self.path().display())?;
+        match *self {
+            PackError::Io { ref error, .. } =>
+                write!(f, "{}", error),
+            PackError::BadMagic { .. } =>
+                write!(f, "not a packfile"),
+            PackError::TooManyFiles { count, .. } =>
+                write!(f, "too many files ({}) in pack (max {})",
+                       count, MAX_FILES_IN_PACK),
+            PackError::NegativeOffset { at, value, .. } =>
+                write!(f, "negative offset {} at byte {}", value, at),
+            PackError::NegativeSize { at, value, .. } =>
+                write!(f, "negative size {} at byte {}", value, at),
+            PackError::OutOfBounds { at, len, .. } =>
+                write!(f, "{} bytes at byte {} are past the end of the file",
+                       len, at),
+            PackError::BadFileName { at, ref error, .. } =>
+                write!(f, "bad file name at byte {}: {}", at, error),
+        }
+    }
+}
+
+impl error::Error for PackError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            PackError::Io { ref error, .. } => Some(error),
+            PackError::BadFileName { ref error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Builds a new .pak file from a list of files.
+///
+/// Each file is given a virtual name (the name it will have within the pack)
+/// and its contents, either as bytes in memory or as a file on disk.  Files
+/// on disk are not read until the pack is written.
+///
+/// The limits of the .pak format are checked as files are added, so that
+/// `write()` will not produce a pack that Quake can't read.
+#[derive(Debug)]
+pub struct PackWriter {
+    /// The name, contents and size of each file, in the order they'll be
+    /// written.
+    entries: Vec<(String, PackSource, u64)>,
+    /// Total size of the header and all file data, used to check that the
+    /// offsets will fit in an i32.
+    data_end: u64,
+}
+
+impl Default for PackWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Where the contents of a file in a `PackWriter` come from.
+#[derive(Debug)]
+pub enum PackSource {
+    /// The file contents, in memory.
+    Bytes(Vec<u8>),
+    /// The path to a file on disk.
+    Path(PathBuf),
+}
+
+impl PackWriter {
+    /// Create an empty `PackWriter`.
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            data_end: PACK_HEADER_SIZE as u64,
+        }
+    }
+
+    /// Add a file with the given contents.
+    pub fn add_bytes(&mut self, name: &str, data: Vec<u8>)
+        -> Result<(), Error>
+    {
+        let size = data.len() as u64;
+        self.add(name, PackSource::Bytes(data), size)
+    }
+
+    /// Add a file whose contents will be read from the given path on disk.
+    pub fn add_file(&mut self, name: &str, path: PathBuf) -> Result<(), Error> {
+        let size = path.metadata()?.len();
+        self.add(name, PackSource::Path(path), size)
+    }
+
+    /// Add every file in the given directory and its subdirectories.
+    ///
+    /// Virtual names are the paths relative to `dir`, using `/` as the
+    /// separator.  Files are added in sorted order so that the output is
+    /// reproducible.
+    pub fn add_dir(&mut self, dir: &Path) -> Result<(), Error> {
+        let mut files = Vec::new();
+        collect_files(dir, "", &mut files)?;
+        files.sort();
+        for (name, path) in files {
+            self.add_file(&name, path)?;
+        }
+        Ok(())
+    }
+
+    fn add(&mut self, name: &str, source: PackSource, size: u64)
+        -> Result<(), Error>
+    {
+        if name.is_empty() {
+            bail!("Can't add a file with an empty name to a pack");
+        }
+        if name.len() >= FILE_NAME_SIZE_ON_DISK {
+            bail!("File name {} is too long for a pack (max {} bytes)",
+                  name, FILE_NAME_SIZE_ON_DISK - 1);
+        }
+        if name.bytes().any(|b| b == b'\0') {
+            bail!("File name {:?} contains a nul byte", name);
+        }
+        if self.entries.iter().any(|(n, _, _)| n == name) {
+            bail!("File {} is already in the pack", name);
+        }
+        if self.entries.len() >= MAX_FILES_IN_PACK {
+            bail!("Too many files in pack (max {})", MAX_FILES_IN_PACK);
+        }
+
+        // The directory is written after the file data, so its offset must
+        // fit in an i32 along with the offset of every file.
+        let data_end = self.data_end + size;
+        let dir_len = (self.entries.len() + 1) * FILE_INFO_SIZE_ON_DISK;
+        if data_end + dir_len as u64 > i32::MAX as u64 {
+            bail!("Pack would be too large to add {} ({} bytes)", name, size);
+        }
+
+        self.entries.push((name.to_string(), source, size));
+        self.data_end = data_end;
+        Ok(())
+    }
+
+    /// The number of files that have been added.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Have no files been added yet?
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Write the .pak file to the given path, replacing any existing file.
+    ///
+    /// The pack is written to a new temporary file next to `path`, which is
+    /// only renamed to `path` once it has been written successfully.  If
+    /// anything goes wrong, any existing file at `path` is left alone.
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        // The temporary file is deleted if it's dropped before it's
+        // persisted.
+        let temp = NamedTempFile::new_in(dir)?;
+        {
+            let mut writer = BufWriter::new(temp.as_file());
+            self.write_to(&mut writer)?;
+            writer.flush()?;
+        }
+        temp.as_file().sync_all()?;
+        temp.persist(path).map_err(|e| e.error)?;
+        Ok(())
+    }
+
+    /// Write the .pak file to the given writer.
+    ///
+    /// The layout is the same as id's tools produce: the header, then the
+    /// contents of each file, then the file information table.
+    ///
+    /// It is an error if a file on disk has changed size since it was added,
+    /// in which case the output is incomplete.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        use byteorder::{LittleEndian, WriteBytesExt};
+
+        let dir_len = self.entries.len() * FILE_INFO_SIZE_ON_DISK;
+        writer.write_all(b"PACK")?;
+        writer.write_i32::<LittleEndian>(self.data_end as i32)?;
+        writer.write_i32::<LittleEndian>(dir_len as i32)?;
+
+        let mut infos = Vec::with_capacity(self.entries.len());
+        let mut offset = PACK_HEADER_SIZE as u64;
+        for &(ref name, ref source, size) in &self.entries {
+            match *source {
+                PackSource::Bytes(ref data) => writer.write_all(data)?,
+                PackSource::Path(ref path) => {
+                    // Copy one byte too many, to notice files that grew.
+                    let file = File::open(path)?;
+                    let copied = io::copy(&mut file.take(size + 1), writer)?;
+                    if copied != size {
+                        bail!("{} changed size while the pack was being \
+                               written (expected {} bytes)",
+                              path.display(), size);
+                    }
+                },
+            }
+            infos.push((name, offset, size));
+            offset += size;
+        }
+        debug_assert_eq!(offset, self.data_end);
+
+        for (name, offset, size) in infos {
+            let mut name_buf = [0; FILE_NAME_SIZE_ON_DISK];
+            name_buf[..name.len()].copy_from_slice(name.as_bytes());
+            writer.write_all(&name_buf)?;
+            writer.write_i32::<LittleEndian>(offset as i32)?;
+            writer.write_i32::<LittleEndian>(size as i32)?;
+        }
+        Ok(())
+    }
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tempfile;
     use test_common as common;
 
     #[test]
     fn read_pak0() {
+        let fixture = common::fixture();
+        let pack = Pack::load(fixture.pak0_path()).unwrap().unwrap();
+        assert_eq!(pack.file_infos.len(), 3);
+
+        let f = &pack.file_infos[0];
+        assert_eq!(f.name, "sound/items/r_item1.wav");
+        assert_eq!(f.offset, 12);
+        assert_eq!(f.size, 6822);
+
+        // Simple check: does it look like there's a WAV file at the offset
+        // given?
+        use std::io::{Read, Seek, SeekFrom};
+        let mut wav_start = vec![0; 4];
+        let mut file = File::open(pack.path()).unwrap();
+        file.seek(SeekFrom::Start(f.offset)).unwrap();
+        file.read_exact(&mut wav_start).unwrap();
+        assert_eq!(wav_start, b"RIFF");
+    }
+
+    #[test]
+    fn pack_crc() {
+        use std::io::{Seek, SeekFrom};
+
+        let fixture = common::fixture();
+        let pack = Pack::load(fixture.pak0_path()).unwrap().unwrap();
+
+        let mut dir = vec![0; pack.file_infos.len() * FILE_INFO_SIZE_ON_DISK];
+        let mut file = File::open(pack.path()).unwrap();
+        file.seek(SeekFrom::End(-(dir.len() as i64))).unwrap();
+        file.read_exact(&mut dir).unwrap();
+        assert_eq!(pack.crc(), crc::block(&dir));
+        assert!(pack.crc() != PAK0_CRC);
+    }
+
+    /// As `read_pak0`, but using the pak0.pak from a full version of
+    /// standard Quake 1.
+    #[cfg(feature = "retail-data")]
+    #[test]
+    fn retail_read_pak0() {
         let path = common::pak0_path();
-        let mut pack = Pack::load(path).unwrap().unwrap();
-        assert_eq!(pack.file_infos.len(), 339);
+        let pack = Pack::load(path).unwrap().unwrap();
+        assert_eq!(pack.file_infos.len(), PAK0_COUNT);
+        assert_eq!(pack.crc(), PAK0_CRC);
 
         let f = &pack.file_infos[0];
         assert_eq!(f.name, "sound/items/r_item1.wav");
@@ -207,8 +681,305 @@ mod tests {
         // given?
         use std::io::{Read, Seek, SeekFrom};
         let mut wav_start = vec![0; 4];
-        pack.reader.seek(SeekFrom::Start(f.offset)).unwrap();
-        pack.reader.read_exact(&mut wav_start).unwrap();
+        let mut file = File::open(pack.path()).unwrap();
+        file.seek(SeekFrom::Start(f.offset)).unwrap();
+        file.read_exact(&mut wav_start).unwrap();
         assert_eq!(wav_start, b"RIFF");
     }
+
+    #[test]
+    fn load_errors() {
+        use byteorder::{ByteOrder, LittleEndian};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pak0.pak");
+        let load = |data: &[u8]| {
+            ::std::fs::write(&path, data).unwrap();
+            Pack::load(path.clone()).unwrap_err()
+        };
+
+        let mut good = Vec::new();
+        let mut writer = PackWriter::new();
+        writer.add_bytes("a", b"aaaa".to_vec()).unwrap();
+        writer.write_to(&mut good).unwrap();
+        // The FileInfo is after the header and the file data.
+        let info_at = PACK_HEADER_SIZE + 4;
+
+        match load(b"PAC") {
+            PackError::Io { path: ref p, .. } => assert_eq!(p, &path),
+            e => panic!("expected Io, got {:?}", e),
+        }
+
+        let mut data = good.clone();
+        data[0..4].copy_from_slice(b"WAD2");
+        match load(&data) {
+            PackError::BadMagic { path: ref p } => assert_eq!(p, &path),
+            e => panic!("expected BadMagic, got {:?}", e),
+        }
+
+        let mut data = good.clone();
+        LittleEndian::write_i32(&mut data[8..12],
+                                (FILE_INFO_SIZE_ON_DISK * 2049) as i32);
+        match load(&data) {
+            PackError::TooManyFiles { count, .. } => assert_eq!(count, 2049),
+            e => panic!("expected TooManyFiles, got {:?}", e),
+        }
+
+        let mut data = good.clone();
+        LittleEndian::write_i32(&mut data[4..8], -1);
+        match load(&data) {
+            PackError::NegativeOffset { at, value, .. } =>
+                assert_eq!((at, value), (4, -1)),
+            e => panic!("expected NegativeOffset, got {:?}", e),
+        }
+
+        let mut data = good.clone();
+        LittleEndian::write_i32(&mut data[info_at+60..info_at+64], -2);
+        let err = load(&data);
+        match err {
+            PackError::NegativeSize { at, value, .. } =>
+                assert_eq!((at, value), (info_at as u64 + 60, -2)),
+            ref e => panic!("expected NegativeSize, got {:?}", e),
+        }
+        assert_eq!(err.to_string(),
+                   format!("{}: negative size -2 at byte 76", path.display()));
+
+        let mut data = good.clone();
+        data[info_at] = 0xff;
+        match load(&data) {
+            PackError::BadFileName { at, error, .. } => {
+                assert_eq!(at, info_at as u64);
+                assert_eq!(error, CStrError::NotUtf8 { valid_up_to: 0 });
+            },
+            e => panic!("expected BadFileName, got {:?}", e),
+        }
+    }
+
+    /// The directory and files must be within the .pak file.
+    #[test]
+    fn bounds() {
+        use byteorder::{ByteOrder, LittleEndian};
+        use std::io::Cursor;
+
+        let mut good = Vec::new();
+        let mut writer = PackWriter::new();
+        writer.add_bytes("a", b"aaaa".to_vec()).unwrap();
+        writer.add_bytes("b", b"bb".to_vec()).unwrap();
+        writer.write_to(&mut good).unwrap();
+        let dir_at = PACK_HEADER_SIZE + 6;
+        let parse = |data: &[u8]| {
+            Pack::from_reader(Cursor::new(data.to_vec()), "mem.pak".into())
+        };
+
+        let pack = parse(&good).unwrap();
+        assert_eq!(pack.file_infos().len(), 2);
+        assert_eq!(pack.path(), Path::new("mem.pak"));
+
+        let out_of_bounds = |data: &[u8]| match parse(data) {
+            Err(PackError::OutOfBounds { at, len, .. }) => (at, len),
+            x => panic!("expected OutOfBounds, got {:?}", x),
+        };
+        assert_eq!(out_of_bounds(&good[..good.len()-1]), (dir_at as u64, 128));
+
+        let mut data = good.clone();
+        LittleEndian::write_i32(&mut data[4..8], i32::MAX);
+        assert_eq!(out_of_bounds(&data), (i32::MAX as u64, 128));
+
+        // File "b" is 2 bytes at offset 16.
+        let b_at = dir_at + FILE_INFO_SIZE_ON_DISK;
+        let mut data = good.clone();
+        LittleEndian::write_i32(&mut data[b_at+60..b_at+64], 1000);
+        assert_eq!(out_of_bounds(&data), (16, 1000));
+        let mut data = good.clone();
+        LittleEndian::write_i32(&mut data[b_at+56..b_at+60], i32::MAX);
+        assert_eq!(out_of_bounds(&data), (i32::MAX as u64, 2));
+    }
+
+    /// Read the contents of every file in the pack.
+    fn read_all(pack: &Pack) -> Vec<(String, Vec<u8>)> {
+        let names: Vec<String> =
+            pack.file_infos().iter().map(|f| f.name.clone()).collect();
+        names.into_iter().map(|name| {
+            let mut data = Vec::new();
+            pack.file(&name).unwrap().unwrap().read_to_end(&mut data).unwrap();
+            (name, data)
+        }).collect()
+    }
+
+    #[test]
+    fn write_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let on_disk = dir.path().join("on_disk.txt");
+        ::std::fs::write(&on_disk, b"from a file").unwrap();
+
+        let mut writer = PackWriter::new();
+        writer.add_bytes("sound/a.wav", b"RIFF".to_vec()).unwrap();
+        writer.add_bytes("empty", Vec::new()).unwrap();
+        writer.add_file("gfx/b.lmp", on_disk).unwrap();
+        let first_path = dir.path().join("first.pak");
+        writer.write(&first_path).unwrap();
+
+        let first = Pack::load(first_path.clone()).unwrap().unwrap();
+        let files = read_all(&first);
+        assert_eq!(files, vec![
+            ("sound/a.wav".to_string(), b"RIFF".to_vec()),
+            ("empty".to_string(), Vec::new()),
+            ("gfx/b.lmp".to_string(), b"from a file".to_vec()),
+        ]);
+
+        // Write the loaded pack back out, and check nothing changed.
+        let mut rewriter = PackWriter::new();
+        for (name, data) in files.clone() {
+            rewriter.add_bytes(&name, data).unwrap();
+        }
+        let second_path = dir.path().join("second.pak");
+        rewriter.write(&second_path).unwrap();
+        let second = Pack::load(second_path.clone()).unwrap().unwrap();
+        assert_eq!(read_all(&second), files);
+        assert_eq!(::std::fs::read(&first_path).unwrap(),
+                   ::std::fs::read(&second_path).unwrap());
+    }
+
+    #[test]
+    fn write_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        ::std::fs::create_dir_all(src.join("maps")).unwrap();
+        ::std::fs::write(src.join("maps/e1m1.bsp"), b"map").unwrap();
+        ::std::fs::write(src.join("autoexec.cfg"), b"cfg").unwrap();
+
+        let mut writer = PackWriter::new();
+        writer.add_dir(&src).unwrap();
+        let path = dir.path().join("pak0.pak");
+        writer.write(&path).unwrap();
+
+        let pack = Pack::load(path).unwrap().unwrap();
+        assert_eq!(read_all(&pack), vec![
+            ("autoexec.cfg".to_string(), b"cfg".to_vec()),
+            ("maps/e1m1.bsp".to_string(), b"map".to_vec()),
+        ]);
+    }
+
+    /// A file that changes size after it's added stops the pack from being
+    /// written, and doesn't replace the existing pack.
+    #[test]
+    fn write_changed_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let on_disk = dir.path().join("on_disk.txt");
+        let path = dir.path().join("pak0.pak");
+        ::std::fs::write(&path, b"old pack").unwrap();
+        // Other files in the directory are never touched.
+        let other = dir.path().join("pak0.pak.tmp");
+        ::std::fs::write(&other, b"not ours").unwrap();
+
+        for &contents in &[&b"shrunk"[..], b"grown, by quite a lot"] {
+            ::std::fs::write(&on_disk, b"original").unwrap();
+            let mut writer = PackWriter::new();
+            writer.add_bytes("first", b"first".to_vec()).unwrap();
+            writer.add_file("changed", on_disk.clone()).unwrap();
+            ::std::fs::write(&on_disk, contents).unwrap();
+
+            let err = writer.write(&path).unwrap_err();
+            assert!(err.to_string().contains("changed size"), "{}", err);
+            assert_eq!(::std::fs::read(&path).unwrap(), b"old pack");
+            let mut names: Vec<_> = ::std::fs::read_dir(dir.path()).unwrap()
+                .map(|e| e.unwrap().file_name())
+                .collect();
+            names.sort();
+            assert_eq!(names, ["on_disk.txt", "pak0.pak", "pak0.pak.tmp"]);
+        }
+
+        let mut writer = PackWriter::new();
+        writer.add_file("unchanged", on_disk.clone()).unwrap();
+        writer.write(&path).unwrap();
+        let pack = Pack::load(path).unwrap().unwrap();
+        assert_eq!(pack.file_infos.len(), 1);
+        assert_eq!(::std::fs::read(&other).unwrap(), b"not ours");
+        assert_eq!(::std::fs::read_dir(dir.path()).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn write_limits() {
+        let mut writer = PackWriter::new();
+
+        // Names must leave room for the nul terminator.
+        let max_name = "a".repeat(FILE_NAME_SIZE_ON_DISK - 1);
+        writer.add_bytes(&max_name, Vec::new()).unwrap();
+        let long_name = "b".repeat(FILE_NAME_SIZE_ON_DISK);
+        assert!(writer.add_bytes(&long_name, Vec::new()).is_err());
+
+        assert!(writer.add_bytes("", Vec::new()).is_err());
+        assert!(writer.add_bytes(&max_name, Vec::new()).is_err());
+
+        for i in 1..MAX_FILES_IN_PACK {
+            writer.add_bytes(&i.to_string(), Vec::new()).unwrap();
+        }
+        assert_eq!(writer.len(), MAX_FILES_IN_PACK);
+        assert!(writer.add_bytes("one_too_many", Vec::new()).is_err());
+    }
+
+    /// Several files in the same pack can be read at the same time.
+    #[test]
+    fn interleaved_reads() {
+        let fixture = common::FixtureBuilder::new()
+            .pak(vec![
+                ("a", b"aaaa".to_vec()),
+                ("b", b"bbbb".to_vec()),
+            ])
+            .build();
+        let pack = Pack::load(fixture.pak0_path()).unwrap().unwrap();
+
+        let mut a = pack.file("a").unwrap().unwrap();
+        let mut b = pack.file("b").unwrap().unwrap();
+        let mut buf = [0; 2];
+        a.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"aa");
+        b.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"bb");
+        a.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"aa");
+        assert_eq!(a.read(&mut buf).unwrap(), 0);
+    }
+
+    /// Seeking stays within the file's window inside the pack.
+    #[test]
+    fn seek_within_file() {
+        use std::io::{Seek, SeekFrom};
+
+        let fixture = common::FixtureBuilder::new()
+            .pak(vec![
+                ("a", b"aaaa".to_vec()),
+                ("b", b"0123456789".to_vec()),
+                ("c", b"cccc".to_vec()),
+            ])
+            .build();
+        let pack = Pack::load(fixture.pak0_path()).unwrap().unwrap();
+        let mut b = pack.file("b").unwrap().unwrap();
+        let mut buf = [0; 2];
+
+        assert_eq!(b.seek(SeekFrom::Start(4)).unwrap(), 4);
+        b.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"45");
+        assert_eq!(b.seek(SeekFrom::Current(-4)).unwrap(), 2);
+        b.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"23");
+        assert_eq!(b.seek(SeekFrom::End(-2)).unwrap(), 8);
+        b.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"89");
+
+        // Seeking to the end is fine, but there's nothing left to read.
+        assert_eq!(b.seek(SeekFrom::End(0)).unwrap(), 10);
+        assert_eq!(b.read(&mut buf).unwrap(), 0);
+
+        // Seeking outside of the file is an error, and doesn't move.
+        assert!(b.seek(SeekFrom::Start(11)).is_err());
+        assert!(b.seek(SeekFrom::End(1)).is_err());
+        assert!(b.seek(SeekFrom::Current(-11)).is_err());
+        assert_eq!(b.stream_position().unwrap(), 10);
+
+        assert_eq!(b.seek(SeekFrom::Start(0)).unwrap(), 0);
+        let mut all = Vec::new();
+        b.read_to_end(&mut all).unwrap();
+        assert_eq!(all, b"0123456789");
+    }
 }
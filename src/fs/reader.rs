@@ -18,59 +18,131 @@
 //! Functionality for reading the contents of the Quake filesystem.
 
 use std::fs::File;
-use std::io::{BufReader, Read, Result};
+use std::io::{self, BufReader, Read, Result, Seek, SeekFrom, Take};
+use std::path::{Path, PathBuf};
+
+use flate2::Crc;
+use flate2::read::DeflateDecoder;
 
 use fs::pack::FileInfo;
+use fs::zip::{self, ZipEntry, ZipError};
 
 
-/// Represents a bounded one-time read from a file in the Quake filesystem.
+/// Represents a bounded read from a file in the Quake filesystem.
+///
+/// The `FsReader` cannot seek or read outside of the file.  This is useful
+/// because a Quake filesystem "file" may actually be a small section of a
+/// larger file-on-disk.  Seeking to a position before the start or after the
+/// end of the file is an error.
 ///
-/// The `FsReader` cannot seek, and cannot read past the end of the file.
-/// This is useful because a Quake filesystem "file" may actually be a small
-/// section of a larger file-on-disk.
+/// Reads using the `FsReader` are buffered.  Files that are compressed within
+/// a .pk3 file are decompressed as they are read; seeking backwards within
+/// them means decompressing again from the start of the file.
 ///
-/// Reads using the `FsReader` are buffered.
+/// Each `FsReader` has its own handle to the underlying file, so any number
+/// of readers can be open at once -- even for files within the same .pak
+/// file, and on different threads.
 ///
-/// The `FsReader` has exclusive control of the underlying reader, so conflicts
-/// with multiple seekers/readers is not possible.
-pub struct FsReader<'a> {
-    inner: FsReaderKind<'a>,
+/// Files within a .pk3 file have their CRC-32 checked once they have been
+/// read up to the end.  A mismatch is an `io::ErrorKind::InvalidData` error,
+/// wrapping a `ZipError::BadCrc`.
+pub struct FsReader {
+    inner: FsReaderKind,
     len: usize,
-    n_read: usize,
+    /// The current position, relative to the start of the file.
+    pos: usize,
+    crc: Option<CrcCheck>,
+}
+
+// * If we're reading a file inside a .pak file, or a file that is stored
+//   uncompressed inside a .pk3 file, we only read a window of the underlying
+//   file, starting at `start`.
+// * If we're reading a deflated file inside a .pk3 file, we decompress the
+//   `compressed_len` bytes starting at `start`.
+// * If we're reading a file from disk, we read the whole underlying file.
+enum FsReaderKind {
+    Window { reader: BufReader<File>, start: u64 },
+    Deflate {
+        decoder: DeflateDecoder<Take<BufReader<File>>>,
+        start: u64,
+        compressed_len: u64,
+    },
+    Disk(BufReader<File>),
 }
 
-// * If we're reading a file inside a .pak file, we are only borrowing the
-//   underlying reader from the `Pack`.
-// * If we're reading a file from disk, we own the underlying reader.
-enum FsReaderKind<'a> {
-    Borrowed(&'a mut BufReader<File>),
-    Owned(BufReader<File>)
+/// The CRC-32 of the data read so far, from the start of the file up to
+/// `pos`.  Reads that don't start at `pos` (after a seek) aren't counted.
+struct CrcCheck {
+    crc: Crc,
+    pos: usize,
+    expected: u32,
+    /// The .pk3 file and the name within it, for the error.
+    path: PathBuf,
+    name: String,
 }
 
-impl<'a> FsReader<'a> {
+impl FsReader {
     /// Create a new `FsReader` that reads the given file on disk.
     pub fn for_file(file: File) -> Result<Self> {
         let len = file.metadata()?.len() as usize;
         let br = BufReader::new(file);
         Ok(Self {
-            inner: FsReaderKind::Owned(br),
+            inner: FsReaderKind::Disk(br),
             len,
-            n_read: 0,
+            pos: 0,
+            crc: None,
         })
     }
 
     /// Create a new `FsReader` that reads the given file within a `Pack`.
-    pub fn for_pack_file(
-        reader: &'a mut BufReader<File>, info: &FileInfo)
+    ///
+    /// `file` must be a handle to the .pak file.
+    pub fn for_pack_file(mut file: File, info: &FileInfo) -> Result<Self> {
+        file.seek(SeekFrom::Start(info.offset))?;
+        Ok(Self {
+            inner: FsReaderKind::Window {
+                reader: BufReader::new(file),
+                start: info.offset,
+            },
+            len: info.size,
+            pos: 0,
+            crc: None,
+        })
+    }
+
+    /// Create a new `FsReader` that reads the given file within a `Zip`.
+    ///
+    /// `file` must be a handle to the .pk3 file at `path`, and `start` is the
+    /// offset of the file's (possibly compressed) data within it.
+    pub fn for_zip_file(mut file: File, path: &Path, start: u64,
+                        entry: &ZipEntry)
         -> Result<Self>
     {
-        use std::io::{Seek, SeekFrom};
-
-        reader.seek(SeekFrom::Start(info.offset))?;
+        file.seek(SeekFrom::Start(start))?;
+        let reader = BufReader::new(file);
+        let inner = match entry.method {
+            zip::METHOD_STORED => FsReaderKind::Window { reader, start },
+            zip::METHOD_DEFLATED => FsReaderKind::Deflate {
+                decoder: DeflateDecoder::new(
+                    reader.take(entry.compressed_size)),
+                start,
+                compressed_len: entry.compressed_size,
+            },
+            method => return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported compression method {}", method))),
+        };
         Ok(Self {
-            inner: FsReaderKind::Borrowed(reader),
-            len: info.size,
-            n_read: 0,
+            inner,
+            len: entry.size,
+            pos: 0,
+            crc: Some(CrcCheck {
+                crc: Crc::new(),
+                pos: 0,
+                expected: entry.crc32,
+                path: path.to_path_buf(),
+                name: entry.name.clone(),
+            }),
         })
     }
 
@@ -78,12 +150,42 @@ impl<'a> FsReader<'a> {
     pub fn len(&self) -> usize {
         self.len
     }
+
+    /// Is the file empty?
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Update the CRC with data that was just read from `start`, and check it
+    /// if the whole file has been read.
+    fn check_crc(&mut self, start: usize, data: &[u8]) -> Result<()> {
+        let check = match self.crc {
+            Some(ref mut check) => check,
+            None => return Ok(()),
+        };
+        if start == check.pos {
+            check.crc.update(data);
+            check.pos += data.len();
+        }
+        if check.pos == self.len && check.crc.sum() != check.expected {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                ZipError::BadCrc {
+                    path: check.path.clone(),
+                    name: check.name.clone(),
+                    expected: check.expected,
+                    found: check.crc.sum(),
+                }));
+        }
+        Ok(())
+    }
 }
 
-impl<'a> Read for FsReader<'a> {
+impl Read for FsReader {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        let remain = self.len - self.n_read;
+        let remain = self.len - self.pos;
         if remain == 0 {
+            self.check_crc(self.pos, &[])?;
             return Ok(0);  // EOF
         }
         // Don't use all of the buffer if we don't have enough data to fill it.
@@ -93,12 +195,76 @@ impl<'a> Read for FsReader<'a> {
         };
 
         let res = match self.inner {
-            FsReaderKind::Borrowed(ref mut br) => br.read(buf),
-            FsReaderKind::Owned(ref mut br) => br.read(buf),
+            FsReaderKind::Window { ref mut reader, .. } => reader.read(buf),
+            FsReaderKind::Deflate { ref mut decoder, .. } =>
+                match decoder.read(buf) {
+                    // The compressed data ran out before the whole file was
+                    // decompressed.
+                    Ok(0) => Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "compressed data is truncated")),
+                    res => res,
+                },
+            FsReaderKind::Disk(ref mut br) => br.read(buf),
         };
         if let Ok(n) = res {
-            self.n_read += n;
+            let start = self.pos;
+            self.pos += n;
+            self.check_crc(start, &buf[..n])?;
         }
         res
     }
 }
+
+impl Seek for FsReader {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        // Work out the new position relative to the start of the file, and
+        // make sure that it's within the file.
+        let (base, offset) = match pos {
+            SeekFrom::Start(n) => (0, n as i128),
+            SeekFrom::End(n) => (self.len as i128, n as i128),
+            SeekFrom::Current(n) => (self.pos as i128, n as i128),
+        };
+        let new_pos = base + offset;
+        if new_pos < 0 || new_pos > self.len as i128 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("seek to {} is outside of the file (length {})",
+                        new_pos, self.len)));
+        }
+        let new_pos = new_pos as u64;
+
+        match self.inner {
+            FsReaderKind::Window { ref mut reader, start } => {
+                reader.seek(SeekFrom::Start(start + new_pos))?;
+            },
+            FsReaderKind::Deflate { ref mut decoder, start, compressed_len } => {
+                // Deflate streams can't be read backwards, so start again.
+                let from = match new_pos < self.pos as u64 {
+                    true => {
+                        let mut file =
+                            decoder.get_ref().get_ref().get_ref().try_clone()?;
+                        file.seek(SeekFrom::Start(start))?;
+                        decoder.reset(BufReader::new(file).take(compressed_len));
+                        0
+                    },
+                    false => self.pos as u64,
+                };
+                let skip = new_pos - from;
+                let mut skipper = decoder.by_ref().take(skip);
+                let skipped = io::copy(&mut skipper, &mut io::sink())?;
+                self.pos = (from + skipped) as usize;
+                if skipped != skip {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "compressed data is truncated"));
+                }
+            },
+            FsReaderKind::Disk(ref mut br) => {
+                br.seek(SeekFrom::Start(new_pos))?;
+            },
+        };
+        self.pos = new_pos as usize;
+        Ok(new_pos)
+    }
+}
@@ -0,0 +1,588 @@
+// Copyright (C) 2018 Adrian Chan
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+//
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 59 Temple Place - Suite 330, Boston, MA  02111-1307, USA.
+
+//! Things related to working with .pk3 (zip) files.
+//!
+//! Only the parts of the zip format that Quake mods actually use are
+//! supported: a single disk, no zip64 extensions, no encryption, and entries
+//! that are either stored or deflated.
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use fs::{FsReader, NameMatch};
+
+
+/// Represents a .pk3 file -- a zip archive of other files, used by more
+/// recent Quake engines in the same way as a .pak file.
+#[derive(Debug)]
+pub struct Zip {
+    /// The location of this .pk3 file.  Each `FsReader` opens its own handle
+    /// to the file, so the `Zip` doesn't need to keep one open.
+    file_path: PathBuf,
+    /// Information about each file contained within the archive.
+    entries: Vec<ZipEntry>,
+    /// Maps each file name to its index in `entries`.
+    index: HashMap<String, usize>,
+    /// Maps each folded file name (see `NameMatch::Folded`) to its index in
+    /// `entries`.
+    folded_index: HashMap<String, usize>,
+}
+
+/// The compression method of an entry that is stored as-is.
+pub const METHOD_STORED: u16 = 0;
+/// The compression method of an entry that is compressed with deflate.
+pub const METHOD_DEFLATED: u16 = 8;
+
+const END_OF_CENTRAL_DIR_SIG: &[u8] = b"PK\x05\x06";
+const END_OF_CENTRAL_DIR_SIZE: usize = 22;
+const MAX_COMMENT_SIZE: usize = 0xffff;
+const CENTRAL_DIR_ENTRY_SIG: &[u8] = b"PK\x01\x02";
+const CENTRAL_DIR_ENTRY_SIZE: usize = 46;
+const LOCAL_HEADER_SIG: &[u8] = b"PK\x03\x04";
+const LOCAL_HEADER_SIZE: usize = 30;
+/// General purpose flag bit that marks an encrypted entry.
+const FLAG_ENCRYPTED: u16 = 0x0001;
+
+impl Zip {
+    /// Attempt to load the given .pk3 file.
+    ///
+    /// If no file exists at the given path then `Ok(None)` is returned.
+    /// Otherwise, an attempt is made to read the file's central directory.
+    /// Any problems while doing this result in an `Err(ZipError)` being
+    /// returned.
+    pub fn load(path: PathBuf) -> Result<Option<Self>, ZipError> {
+        let mut file = match File::open(&path) {
+            Err(_) => return Ok(None),
+            // If file doesn't exist it's not an error.
+            Ok(file) => file
+        };
+        let file_len = file.metadata()
+            .map_err(|e| ZipError::io(&path, e))?
+            .len();
+
+        // The end of central directory record is at the end of the file,
+        // followed by a comment of up to 64KB.
+        let tail_start = file_len.saturating_sub(
+            (END_OF_CENTRAL_DIR_SIZE + MAX_COMMENT_SIZE) as u64);
+        let mut tail = vec![0; (file_len - tail_start) as usize];
+        file.seek(SeekFrom::Start(tail_start))
+            .and_then(|_| file.read_exact(&mut tail))
+            .map_err(|e| ZipError::io(&path, e))?;
+        let search_end = tail.len().saturating_sub(END_OF_CENTRAL_DIR_SIZE - 1);
+        let eocd = match (0..search_end).rev()
+            .find(|&i| &tail[i..i+4] == END_OF_CENTRAL_DIR_SIG)
+        {
+            Some(i) => &tail[i..i+END_OF_CENTRAL_DIR_SIZE],
+            None => return Err(ZipError::NotZip { path }),
+        };
+
+        let disk = LittleEndian::read_u16(&eocd[4..6]);
+        let central_dir_disk = LittleEndian::read_u16(&eocd[6..8]);
+        let num_entries = LittleEndian::read_u16(&eocd[10..12]);
+        let central_dir_len = LittleEndian::read_u32(&eocd[12..16]);
+        let central_dir_offset = LittleEndian::read_u32(&eocd[16..20]);
+        if disk != 0 || central_dir_disk != 0 {
+            return Err(ZipError::Unsupported {
+                path,
+                what: "multi-disk zip files".into(),
+            });
+        }
+        if num_entries == 0xffff || central_dir_len == 0xffff_ffff
+            || central_dir_offset == 0xffff_ffff {
+            return Err(ZipError::Unsupported {
+                path,
+                what: "zip64 files".into(),
+            });
+        }
+        let central_dir_offset = u64::from(central_dir_offset);
+        if central_dir_offset + u64::from(central_dir_len) > file_len {
+            return Err(ZipError::Corrupt {
+                path,
+                at: central_dir_offset,
+                what: "central directory is outside of the file",
+            });
+        }
+
+        let mut dir = vec![0; central_dir_len as usize];
+        file.seek(SeekFrom::Start(central_dir_offset))
+            .and_then(|_| file.read_exact(&mut dir))
+            .map_err(|e| ZipError::io(&path, e))?;
+
+        let mut entries = Vec::with_capacity(num_entries as usize);
+        let mut dir_reader = &dir[..];
+        for _i in 0..num_entries {
+            let at = central_dir_offset + (dir.len() - dir_reader.len()) as u64;
+            if let Some(entry) = ZipEntry::from(&mut dir_reader, &path, at)? {
+                entries.push(entry);
+            }
+        }
+
+        // If a name appears more than once, the first one wins, as it does
+        // for a `Pack`.
+        let mut index = HashMap::with_capacity(entries.len());
+        let mut folded_index = HashMap::with_capacity(entries.len());
+        for (i, entry) in entries.iter().enumerate() {
+            index.entry(entry.name.clone()).or_insert(i);
+            folded_index.entry(NameMatch::Folded.key(&entry.name))
+                .or_insert(i);
+        }
+
+        Ok(Some(Self {
+            file_path: path,
+            entries,
+            index,
+            folded_index,
+        }))
+    }
+
+    /// The location of this .pk3 file on disk.
+    pub fn path(&self) -> &Path {
+        self.file_path.as_path()
+    }
+
+    /// Information about each file contained within the archive, in the
+    /// order they are listed in the central directory.  Directories are not
+    /// included.
+    pub fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+
+    /// Find the index (within `entries()`) of the named file, if it exists.
+    pub fn index_of(&self, name: &str, matching: NameMatch) -> Option<usize> {
+        match matching {
+            NameMatch::Exact => self.index.get(name).cloned(),
+            NameMatch::Folded =>
+                self.folded_index.get(&matching.key(name)).cloned(),
+        }
+    }
+
+    /// Retrieve an `FsReader` for the given file within the `Zip`, if it
+    /// exists.  The name must match exactly.
+    pub fn file(&self, name: &str) -> Result<Option<FsReader>, ZipError> {
+        match self.index_of(name, NameMatch::Exact) {
+            Some(i) => Ok(Some(self.file_at(i)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Retrieve an `FsReader` for the file at the given index within
+    /// `entries()`.
+    ///
+    /// Deflated files are decompressed as they are read.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index is out of range.
+    pub fn file_at(&self, i: usize) -> Result<FsReader, ZipError> {
+        let entry = &self.entries[i];
+        let path = &self.file_path;
+        if entry.flags & FLAG_ENCRYPTED != 0 {
+            return Err(ZipError::Unsupported {
+                path: path.clone(),
+                what: format!("encrypted file {}", entry.name),
+            });
+        }
+        if entry.method != METHOD_STORED && entry.method != METHOD_DEFLATED {
+            return Err(ZipError::Unsupported {
+                path: path.clone(),
+                what: format!("compression method {} for {}",
+                              entry.method, entry.name),
+            });
+        }
+
+        // The file data follows the local header, which has its own copy of
+        // the name and extra field.  The lengths may differ from the ones in
+        // the central directory.
+        let mut file = File::open(path).map_err(|e| ZipError::io(path, e))?;
+        let mut header = [0; LOCAL_HEADER_SIZE];
+        let file_len = file.seek(SeekFrom::End(0))
+            .and_then(|len| {
+                file.seek(SeekFrom::Start(entry.header_offset))?;
+                file.read_exact(&mut header)?;
+                Ok(len)
+            })
+            .map_err(|e| ZipError::io(path, e))?;
+        if &header[0..4] != LOCAL_HEADER_SIG {
+            return Err(ZipError::Corrupt {
+                path: path.clone(),
+                at: entry.header_offset,
+                what: "bad local header",
+            });
+        }
+        let name_len = LittleEndian::read_u16(&header[26..28]);
+        let extra_len = LittleEndian::read_u16(&header[28..30]);
+        let data_offset = entry.header_offset + LOCAL_HEADER_SIZE as u64
+            + u64::from(name_len) + u64::from(extra_len);
+        if data_offset + entry.compressed_size > file_len {
+            return Err(ZipError::Corrupt {
+                path: path.clone(),
+                at: entry.header_offset,
+                what: "file data extends past the end of the archive",
+            });
+        }
+
+        FsReader::for_zip_file(file, path, data_offset, entry)
+            .map_err(|e| ZipError::io(path, e))
+    }
+}
+
+/// Information about a file stored within a `Zip`, from its central
+/// directory entry.
+#[derive(Debug)]
+pub struct ZipEntry {
+    /// Name of the file.
+    pub name: String,
+    /// How the file is compressed; see `METHOD_STORED` and
+    /// `METHOD_DEFLATED`.
+    pub method: u16,
+    /// The general purpose bit flags.
+    pub flags: u16,
+    /// The CRC-32 of the decompressed file.
+    pub crc32: u32,
+    /// Offset of the file's local header within the archive.
+    pub header_offset: u64,  // u32 on disk
+    /// Size of the file's data within the archive, in bytes.
+    pub compressed_size: u64,  // u32 on disk
+    /// Size of the file once decompressed, in bytes.
+    pub size: usize,  // u32 on disk
+}
+
+impl ZipEntry {
+    /// Parse a `ZipEntry` from the central directory, advancing `dir` past
+    /// it.
+    ///
+    /// Directory entries are skipped, and give `Ok(None)`.  `path` is the
+    /// .pk3 file, and `at` is the offset of the entry within it; they are
+    /// only used to describe errors.
+    fn from(dir: &mut &[u8], path: &Path, at: u64)
+        -> Result<Option<Self>, ZipError>
+    {
+        let corrupt = |what| ZipError::Corrupt {
+            path: path.to_path_buf(),
+            at,
+            what,
+        };
+
+        if dir.len() < CENTRAL_DIR_ENTRY_SIZE
+            || &dir[0..4] != CENTRAL_DIR_ENTRY_SIG {
+            return Err(corrupt("bad central directory entry"));
+        }
+        let flags = LittleEndian::read_u16(&dir[8..10]);
+        let method = LittleEndian::read_u16(&dir[10..12]);
+        let crc32 = LittleEndian::read_u32(&dir[16..20]);
+        let compressed_size = LittleEndian::read_u32(&dir[20..24]);
+        let size = LittleEndian::read_u32(&dir[24..28]);
+        let name_len = LittleEndian::read_u16(&dir[28..30]) as usize;
+        let extra_len = LittleEndian::read_u16(&dir[30..32]) as usize;
+        let comment_len = LittleEndian::read_u16(&dir[32..34]) as usize;
+        let header_offset = LittleEndian::read_u32(&dir[42..46]);
+
+        let entry_len = CENTRAL_DIR_ENTRY_SIZE + name_len + extra_len
+            + comment_len;
+        if dir.len() < entry_len {
+            return Err(corrupt("central directory entry is truncated"));
+        }
+        let name_bytes =
+            &dir[CENTRAL_DIR_ENTRY_SIZE..CENTRAL_DIR_ENTRY_SIZE+name_len];
+        let name = String::from_utf8_lossy(name_bytes).into_owned();
+        *dir = &dir[entry_len..];
+
+        if name.ends_with('/') {
+            return Ok(None);
+        }
+        if compressed_size == 0xffff_ffff || size == 0xffff_ffff
+            || header_offset == 0xffff_ffff {
+            return Err(ZipError::Unsupported {
+                path: path.to_path_buf(),
+                what: format!("zip64 entry {}", name),
+            });
+        }
+        if method == METHOD_STORED && compressed_size != size {
+            return Err(corrupt("stored file sizes don't match"));
+        }
+
+        Ok(Some(Self {
+            name,
+            method,
+            flags,
+            crc32,
+            header_offset: u64::from(header_offset),
+            compressed_size: u64::from(compressed_size),
+            size: size as usize,
+        }))
+    }
+}
+
+/// The ways that loading a .pk3 file, or a file within one, can fail.
+///
+/// Each error has the path of the .pk3 file.
+#[derive(Debug)]
+pub enum ZipError {
+    /// The file couldn't be read.
+    Io {
+        /// The .pk3 file.
+        path: PathBuf,
+        /// The underlying error.
+        error: io::Error,
+    },
+    /// The file has no end of central directory record, so isn't a zip file.
+    NotZip {
+        /// The .pk3 file.
+        path: PathBuf,
+    },
+    /// The file uses a part of the zip format that isn't supported.
+    Unsupported {
+        /// The .pk3 file.
+        path: PathBuf,
+        /// What isn't supported.
+        what: String,
+    },
+    /// The file's structure is invalid.
+    Corrupt {
+        /// The .pk3 file.
+        path: PathBuf,
+        /// The offset of the invalid record within the file.
+        at: u64,
+        /// What's wrong.
+        what: &'static str,
+    },
+    /// A file's CRC-32 doesn't match its central directory entry, so its data
+    /// is corrupt.
+    BadCrc {
+        /// The .pk3 file.
+        path: PathBuf,
+        /// The name of the file within the archive.
+        name: String,
+        /// The CRC-32 from the central directory.
+        expected: u32,
+        /// The CRC-32 of the data.
+        found: u32,
+    },
+}
+
+impl ZipError {
+    fn io(path: &Path, error: io::Error) -> Self {
+        ZipError::Io { path: path.to_path_buf(), error }
+    }
+
+    /// The .pk3 file that the error is about.
+    pub fn path(&self) -> &Path {
+        match *self {
+            ZipError::Io { ref path, .. }
+            | ZipError::NotZip { ref path }
+            | ZipError::Unsupported { ref path, .. }
+            | ZipError::Corrupt { ref path, .. }
+            | ZipError::BadCrc { ref path, .. } => path,
+        }
+    }
+}
+
+impl fmt::Display for ZipError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: ", self.path().display())?;
+        match *self {
+            ZipError::Io { ref error, .. } =>
+                write!(f, "{}", error),
+            ZipError::NotZip { .. } =>
+                write!(f, "not a zip file"),
+            ZipError::Unsupported { ref what, .. } =>
+                write!(f, "unsupported: {}", what),
+            ZipError::Corrupt { at, what, .. } =>
+                write!(f, "{} at byte {}", what, at),
+            ZipError::BadCrc { ref name, expected, found, .. } =>
+                write!(f, "{} has CRC {:08x}, expected {:08x}",
+                       name, found, expected),
+        }
+    }
+}
+
+impl error::Error for ZipError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            ZipError::Io { ref error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile;
+    use test_common as common;
+
+    /// Write a .pk3 to a temporary directory and load it.
+    fn load(data: &[u8]) -> (tempfile::TempDir, Result<Option<Zip>, ZipError>) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.pk3");
+        ::std::fs::write(&path, data).unwrap();
+        let zip = Zip::load(path);
+        (dir, zip)
+    }
+
+    fn read(zip: &Zip, name: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        zip.file(name).unwrap().unwrap().read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn read_entries() {
+        let text = b"deflate me, deflate me, deflate me".to_vec();
+        let (_dir, zip) = load(&common::zip_bytes(&[
+            ("maps/", Vec::new(), false),
+            ("maps/start.bsp", b"stored".to_vec(), false),
+            ("readme.txt", text.clone(), true),
+            ("empty", Vec::new(), true),
+        ]));
+        let zip = zip.unwrap().unwrap();
+
+        let names: Vec<&str> =
+            zip.entries().iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["maps/start.bsp", "readme.txt", "empty"]);
+        assert_eq!(zip.entries()[1].method, METHOD_DEFLATED);
+        assert!(zip.entries()[1].compressed_size < text.len() as u64);
+
+        assert_eq!(read(&zip, "maps/start.bsp"), b"stored");
+        assert_eq!(read(&zip, "readme.txt"), text);
+        assert_eq!(read(&zip, "empty"), b"");
+        assert!(zip.file("maps/").unwrap().is_none());
+        assert_eq!(zip.index_of("README.TXT", NameMatch::Folded), Some(1));
+    }
+
+    #[test]
+    fn seek_deflated() {
+        let data: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+        let (_dir, zip) = load(&common::zip_bytes(&[
+            ("data", data.clone(), true),
+        ]));
+        let zip = zip.unwrap().unwrap();
+        let mut reader = zip.file("data").unwrap().unwrap();
+        assert_eq!(reader.len(), data.len());
+        let mut buf = [0; 4];
+
+        assert_eq!(reader.seek(SeekFrom::Start(5000)).unwrap(), 5000);
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, &data[5000..5004]);
+        // Seeking backwards starts decompressing again from the beginning.
+        assert_eq!(reader.seek(SeekFrom::Current(-4002)).unwrap(), 1002);
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, &data[1002..1006]);
+        assert_eq!(reader.seek(SeekFrom::End(-4)).unwrap(), 9996);
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, &data[9996..]);
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+        assert!(reader.seek(SeekFrom::End(1)).is_err());
+    }
+
+    #[test]
+    fn bad_zips() {
+        // Missing files are not an error.
+        let dir = tempfile::tempdir().unwrap();
+        assert!(Zip::load(dir.path().join("nope.pk3")).unwrap().is_none());
+
+        let (_dir, zip) = load(b"PACK not a zip file at all");
+        match zip.unwrap_err() {
+            ZipError::NotZip { .. } => (),
+            e => panic!("expected NotZip, got {:?}", e),
+        }
+
+        // Truncated central directory.
+        let data = common::zip_bytes(&[("a", b"aaaa".to_vec(), false)]);
+        let (_dir, zip) = load(&data[..data.len()-1]);
+        assert!(zip.is_err());
+
+        // The central directory entry for "a" starts after its local header
+        // and data.
+        let mut data = data.clone();
+        let entry_at = LOCAL_HEADER_SIZE + 1 + 4;
+        data[entry_at + 10] = 12;  // bzip2
+        let (_dir, zip) = load(&data);
+        let zip = zip.unwrap().unwrap();
+        match zip.file("a") {
+            Err(ZipError::Unsupported { ref what, .. }) =>
+                assert_eq!(what, "compression method 12 for a"),
+            Err(e) => panic!("expected Unsupported, got {:?}", e),
+            Ok(_) => panic!("expected Unsupported, got a reader"),
+        }
+        data[entry_at] = b'X';
+        let (_dir, zip) = load(&data);
+        match zip.unwrap_err() {
+            ZipError::Corrupt { at, .. } => assert_eq!(at, entry_at as u64),
+            e => panic!("expected Corrupt, got {:?}", e),
+        }
+
+        // Corrupt deflate stream.
+        let mut data = common::zip_bytes(&[("a", vec![b'a'; 100], true)]);
+        let data_start = LOCAL_HEADER_SIZE + 1;
+        for b in &mut data[data_start..data_start+4] {
+            *b = 0xff;
+        }
+        let (_dir, zip) = load(&data);
+        let zip = zip.unwrap().unwrap();
+        let mut out = Vec::new();
+        assert!(zip.file("a").unwrap().unwrap().read_to_end(&mut out).is_err());
+    }
+
+    /// Files whose data doesn't match their CRC-32 can't be read.
+    #[test]
+    fn bad_crc() {
+        let mut data = common::zip_bytes(&[
+            ("stored", b"stored".to_vec(), false),
+            ("deflated", vec![b'd'; 1000], true),
+        ]);
+        // Change the stored data, and the deflated CRC in its central
+        // directory entry.
+        let stored_at = LOCAL_HEADER_SIZE + "stored".len();
+        data[stored_at] = b'S';
+        let dir_at = data.len() - 22 - 2 * CENTRAL_DIR_ENTRY_SIZE
+            - "stored".len() - "deflated".len();
+        let deflated_crc_at = dir_at + CENTRAL_DIR_ENTRY_SIZE
+            + "stored".len() + 16;
+        data[deflated_crc_at] ^= 1;
+        let (_dir, zip) = load(&data);
+        let zip = zip.unwrap().unwrap();
+
+        for name in &["stored", "deflated"] {
+            let mut out = Vec::new();
+            let mut reader = zip.file(name).unwrap().unwrap();
+            let err = reader.read_to_end(&mut out).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            let zip_err = err.get_ref().unwrap()
+                .downcast_ref::<ZipError>().unwrap();
+            match *zip_err {
+                ZipError::BadCrc { name: ref bad, .. } => assert_eq!(bad, name),
+                ref e => panic!("expected BadCrc, got {:?}", e),
+            }
+            // The error is reported again, rather than a clean end of file.
+            assert!(reader.read(&mut [0; 4]).is_err());
+        }
+
+        // A file that is only read part of the way isn't checked.
+        let mut reader = zip.file("deflated").unwrap().unwrap();
+        reader.seek(SeekFrom::Start(10)).unwrap();
+        reader.read_exact(&mut [0; 4]).unwrap();
+    }
+}
@@ -0,0 +1,438 @@
+// Copyright (C) 1996-1997 Id Software, Inc.
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+//
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 59 Temple Place - Suite 330, Boston, MA  02111-1307, USA.
+
+// Modified by Adrian Chan, March 2018
+// Cmd_Init, Cmd_ExecuteString and the script commands from cmd.c, and the
+// start of Host_Init from host.c
+
+//! The engine state that console commands act on.
+
+use failure::Error;
+
+use std::collections::BTreeMap;
+
+use cmd::{Args, Cbuf, Commands};
+use cvar::{self, CvarContext, Cvars};
+use defs;
+use fs::FileSys;
+use parms::Parms;
+
+/// How deeply scripts and aliases can be nested inside each other.
+///
+/// This stops scripts and aliases that run themselves from looping forever.
+/// There's no limit on how many can run one after another.
+pub const MAX_EXPANSION_DEPTH: usize = 64;
+/// Alias names must be shorter than this.
+pub const MAX_ALIAS_NAME: usize = 32;
+
+/// Inserted into the command buffer after the text of each script or alias,
+/// to mark where it ends.  Text is cut at NUL before it's inserted, so a
+/// script can't contain this line.
+const EXPANSION_END: &str = "\0";
+
+
+/// The engine state that console commands act on.
+///
+/// Owns the command buffer, the registered commands, aliases and cvars, and
+/// the file system that scripts are loaded from.  Text printed by commands is
+/// collected until it's taken with `take_output()`.
+pub struct Host {
+    fs: FileSys,
+    cbuf: Cbuf,
+    commands: Commands<Host>,
+    aliases: BTreeMap<String, String>,
+    cvars: Cvars,
+    /// The `+commands` from the command line, for `stuffcmds`.
+    stuffed: String,
+    output: String,
+    /// The number of scripts and aliases that are being run, inside each
+    /// other.
+    depth: usize,
+}
+
+impl Host {
+    /// Construct a new `Host`, with the standard commands registered.
+    ///
+    /// Prints a warning if any of the command line arguments were dropped,
+    /// or if any of the `+commands` were left out because they couldn't be
+    /// quoted; see `Parms::commands()`.
+    pub fn new(parms: &Parms) -> Result<Self, Error> {
+        let mut commands = Commands::new();
+        commands.add("stuffcmds", stuffcmds_f)?;
+        commands.add("exec", exec_f)?;
+        commands.add("echo", echo_f)?;
+        commands.add("alias", alias_f)?;
+        commands.add("wait", wait_f)?;
+        cvar::add_commands(&mut commands)?;
+
+        let mut output = String::new();
+        if !parms.dropped().is_empty() {
+            output.push_str(&format!(
+                "Too many command line arguments (max {}), ignoring: {}\n",
+                defs::MAX_NUM_ARGVS, parms.dropped().join(" ")));
+        }
+        for error in parms.bad_commands() {
+            output.push_str(&format!("Ignoring command: {}\n", error));
+        }
+
+        Ok(Host {
+            fs: FileSys::new(parms)?,
+            cbuf: Cbuf::new(),
+            commands,
+            aliases: BTreeMap::new(),
+            cvars: Cvars::new(parms),
+            stuffed: parms.commands(),
+            output,
+            depth: 0,
+        })
+    }
+
+    /// Run the startup scripts.
+    ///
+    /// This execs `quake.rc`, which normally execs `default.cfg`,
+    /// `config.cfg` and `autoexec.cfg`, and then runs `stuffcmds` to add the
+    /// commands from the command line.
+    pub fn start(&mut self) {
+        self.cbuf.insert("exec quake.rc");
+        self.execute();
+    }
+
+    /// The file system.
+    pub fn fs(&self) -> &FileSys {
+        &self.fs
+    }
+
+    /// The command buffer.
+    pub fn cbuf(&mut self) -> &mut Cbuf {
+        &mut self.cbuf
+    }
+
+    /// The registered commands.
+    pub fn commands(&mut self) -> &mut Commands<Host> {
+        &mut self.commands
+    }
+
+    /// The value of an alias, if it exists.
+    pub fn alias(&self, name: &str) -> Option<&str> {
+        self.aliases.get(&name.to_lowercase()).map(|a| a.as_str())
+    }
+
+    /// Take all of the text that has been printed since the last call.
+    pub fn take_output(&mut self) -> String {
+        ::std::mem::take(&mut self.output)
+    }
+
+    /// Execute the command buffer, until it's empty or a command waits.
+    pub fn execute(&mut self) {
+        while let Some(line) = self.cbuf.next_line() {
+            if line == EXPANSION_END {
+                self.depth = self.depth.saturating_sub(1);
+                continue;
+            }
+            self.execute_string(&line);
+        }
+        if self.cbuf.is_empty() {
+            // In case the buffer was cleared with expansions still in it.
+            self.depth = 0;
+        }
+    }
+
+    /// Execute a single command line.
+    ///
+    /// The first argument can be the name of a command, an alias or a cvar,
+    /// which are checked in that order.
+    pub fn execute_string(&mut self, text: &str) {
+        let args = Args::new(text);
+        if args.argc() == 0 {
+            return;
+        }
+
+        if let Some(command) = self.commands.get(args.argv(0)) {
+            command(self, &args);
+            return;
+        }
+
+        if let Some(value) = self.alias(args.argv(0)).map(|a| a.to_string()) {
+            self.expand(&value);
+            return;
+        }
+
+        if !cvar::command(self, &args) {
+            self.print(&format!("Unknown command \"{}\"\n", args.argv(0)));
+        }
+    }
+
+    /// Insert the text of a script or alias at the start of the command
+    /// buffer, if they aren't already nested too deeply.
+    fn expand(&mut self, text: &str) -> bool {
+        if self.depth >= MAX_EXPANSION_DEPTH {
+            self.print(&format!(
+                "Scripts and aliases are nested too deeply (max {})\n",
+                MAX_EXPANSION_DEPTH));
+            return false;
+        }
+        self.depth += 1;
+        let text = text.split('\0').next().unwrap_or("");
+        self.cbuf.insert(&format!("{}\n{}", text, EXPANSION_END));
+        true
+    }
+}
+
+impl CvarContext for Host {
+    fn cvars(&mut self) -> &mut Cvars {
+        &mut self.cvars
+    }
+
+    fn print(&mut self, text: &str) {
+        self.output.push_str(text);
+    }
+}
+
+/// `stuffcmds`: add the `+commands` from the command line to the command
+/// buffer.
+fn stuffcmds_f(host: &mut Host, args: &Args) {
+    if args.argc() != 1 {
+        host.print("stuffcmds : execute command line parameters\n");
+        return;
+    }
+    host.cbuf.insert(&host.stuffed);
+}
+
+/// `exec <filename>`: execute a script file.
+fn exec_f(host: &mut Host, args: &Args) {
+    if args.argc() != 2 {
+        host.print("exec <filename> : execute a script file\n");
+        return;
+    }
+    let name = args.argv(1);
+    let script = match host.fs.load_file(name) {
+        Ok(Some(script)) => script,
+        Ok(None) => {
+            host.print(&format!("couldn't exec {}\n", name));
+            return;
+        },
+        Err(e) => {
+            host.print(&format!("couldn't exec {}: {}\n", name, e));
+            return;
+        },
+    };
+    if host.depth < MAX_EXPANSION_DEPTH {
+        host.print(&format!("execing {}\n", name));
+    }
+    host.expand(&String::from_utf8_lossy(&script));
+}
+
+/// `echo [text]`: print the arguments.
+fn echo_f(host: &mut Host, args: &Args) {
+    let mut text = (1..args.argc())
+        .map(|i| args.argv(i))
+        .collect::<Vec<_>>()
+        .join(" ");
+    text.push('\n');
+    host.print(&text);
+}
+
+/// `alias [name [command]]`: create an alias, or list them all.
+///
+/// Running an alias runs its command, which may contain several commands
+/// separated by `;`.
+fn alias_f(host: &mut Host, args: &Args) {
+    if args.argc() == 1 {
+        let mut text = "Current alias commands:\n".to_string();
+        for (name, value) in &host.aliases {
+            text.push_str(&format!("{} : {}\n", name, value));
+        }
+        host.print(&text);
+        return;
+    }
+
+    let name = args.argv(1);
+    if name.len() >= MAX_ALIAS_NAME {
+        host.print("Alias name is too long\n");
+        return;
+    }
+    let value = (2..args.argc())
+        .map(|i| args.argv(i))
+        .collect::<Vec<_>>()
+        .join(" ");
+    host.aliases.insert(name.to_lowercase(), value);
+}
+
+/// `wait`: stop executing commands until the next frame.
+fn wait_f(host: &mut Host, _args: &Args) {
+    host.cbuf.wait();
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_common::{Fixture, FixtureBuilder};
+
+    fn parms(fixture: &Fixture, extra: &[&str]) -> Parms {
+        let mut argv = vec![
+            "-basedir".to_string(),
+            fixture.base_dir().to_string_lossy().to_string()];
+        argv.extend(extra.iter().map(|a| a.to_string()));
+        Parms::new(argv, "cwd".into())
+    }
+
+    fn quake_rc() -> Vec<u8> {
+        b"// startup\nexec default.cfg\nexec config.cfg\nexec autoexec.cfg\n\
+          stuffcmds\nstartdemos demo1 demo2\n".to_vec()
+    }
+
+    #[test]
+    fn exec_chain() {
+        let fixture = FixtureBuilder::new()
+            .pak(vec![
+                ("quake.rc", quake_rc()),
+                ("default.cfg",
+                 b"set fov 90\nset sensitivity 3\nalias zoom \"fov 10\"\n"
+                 .to_vec()),
+            ])
+            .file("config.cfg", b"sensitivity \"5\"\r\n".to_vec())
+            .build();
+        let parms = parms(&fixture, &["+echo", "from", "argv", "+zoom"]);
+        let mut host = Host::new(&parms).unwrap();
+        host.start();
+
+        assert_eq!(host.take_output(),
+                   "execing quake.rc\nexecing default.cfg\nexecing config.cfg\n\
+                    couldn't exec autoexec.cfg\nfrom argv\n\
+                    Unknown command \"startdemos\"\n");
+        assert_eq!(host.cvars.string("sensitivity"), "5");
+        assert_eq!(host.cvars.string("fov"), "10");
+        assert!(host.cbuf().is_empty());
+    }
+
+    #[test]
+    fn exec_overrides() {
+        // Scripts in a -game directory override the ones in id1.
+        let fixture = FixtureBuilder::new()
+            .file("autoexec.cfg", b"echo id1".to_vec())
+            .build();
+        let mod_dir = fixture.base_dir().join("mymod");
+        ::std::fs::create_dir(&mod_dir).unwrap();
+        ::std::fs::write(mod_dir.join("autoexec.cfg"), b"echo mymod").unwrap();
+
+        let parms = parms(&fixture, &["-game", "mymod"]);
+        let mut host = Host::new(&parms).unwrap();
+        host.cbuf().add("exec autoexec.cfg\nexec ../id1/autoexec.cfg\n");
+        host.execute();
+        let output = host.take_output();
+        assert!(output.starts_with("execing autoexec.cfg\nmymod\n\
+                                    couldn't exec ../id1/autoexec.cfg: "),
+                "{}", output);
+    }
+
+    #[test]
+    fn alias_and_wait() {
+        let fixture = FixtureBuilder::new().build();
+        let mut host = Host::new(&fixture.parms()).unwrap();
+        host.cbuf().add("alias fire \"echo bang; wait; echo done\"\n\
+                         alias\nFIRE\necho after\n");
+        host.execute();
+        assert_eq!(host.alias("Fire"), Some("echo bang; wait; echo done"));
+        assert_eq!(host.take_output(),
+                   "Current alias commands:\n\
+                    fire : echo bang; wait; echo done\nbang\n");
+        host.execute();
+        assert_eq!(host.take_output(), "done\nafter\n");
+
+        host.cbuf().add(&format!("alias {} x\n", "a".repeat(MAX_ALIAS_NAME)));
+        host.execute();
+        assert_eq!(host.take_output(), "Alias name is too long\n");
+    }
+
+    #[test]
+    fn recursion_limit() {
+        let fixture = FixtureBuilder::new()
+            .file("loop.cfg", b"echo loop\nexec loop.cfg\n".to_vec())
+            .build();
+        let mut host = Host::new(&fixture.parms()).unwrap();
+
+        host.cbuf().add("exec loop.cfg\n");
+        host.execute();
+        let output = host.take_output();
+        assert_eq!(output.matches("loop\n").count(), MAX_EXPANSION_DEPTH);
+        assert!(output.ends_with("Scripts and aliases are nested too deeply \
+                                  (max 64)\n"), "{}", output);
+        assert!(host.cbuf().is_empty());
+
+        host.cbuf().add("alias a b\nalias b a\na\necho ok\n");
+        host.execute();
+        assert_eq!(host.take_output(),
+                   "Scripts and aliases are nested too deeply (max 64)\nok\n");
+    }
+
+    /// Only nesting is limited, not the number of scripts and aliases.
+    #[test]
+    fn many_expansions() {
+        let count = MAX_EXPANSION_DEPTH * 3;
+        let mut cfg = String::new();
+        for i in 0..count {
+            cfg.push_str(&format!("alias a{} \"echo {}\"\na{}\n", i, i, i));
+        }
+        let fixture = FixtureBuilder::new()
+            .file("server.cfg", cfg.into_bytes())
+            .file("inner.cfg", b"echo inner\n".to_vec())
+            .build();
+        let mut host = Host::new(&fixture.parms()).unwrap();
+
+        host.cbuf().add("exec server.cfg\n");
+        for _ in 0..count {
+            host.cbuf().add("exec inner.cfg\n");
+        }
+        host.execute();
+        let output = host.take_output();
+        for i in 0..count {
+            assert!(output.contains(&format!("\n{}\n", i)), "{}", output);
+        }
+        assert_eq!(output.matches("execing inner.cfg\ninner\n").count(),
+                   count);
+        assert!(!output.contains("nested"), "{}", output);
+        assert_eq!(host.depth, 0);
+    }
+
+    #[test]
+    fn dropped_args() {
+        let fixture = FixtureBuilder::new().build();
+        let extra: Vec<String> = (0..defs::MAX_NUM_ARGVS)
+            .map(|i| i.to_string())
+            .collect();
+        let extra: Vec<&str> = extra.iter().map(|a| a.as_str()).collect();
+        let mut host = Host::new(&parms(&fixture, &extra)).unwrap();
+        assert_eq!(host.take_output(),
+                   "Too many command line arguments (max 50), ignoring: \
+                    48 49\n");
+    }
+
+    #[test]
+    fn bad_command_args() {
+        let fixture = FixtureBuilder::new()
+            .file("quake.rc", b"stuffcmds\n".to_vec())
+            .build();
+        let parms = parms(&fixture, &["+echo", "a\nquit x", "+echo", "ok"]);
+        let mut host = Host::new(&parms).unwrap();
+        assert_eq!(host.take_output(),
+                   "Ignoring command: argument \"a\\nquit x\" to +echo \
+                    can't be quoted\n");
+        host.start();
+        assert_eq!(host.take_output(), "execing quake.rc\nok\n");
+    }
+}
@@ -3,15 +3,25 @@
 //! Quake, ported to Rust.
 
 extern crate byteorder;
+extern crate flate2;
 #[macro_use] extern crate failure;
+extern crate tempfile;
 // #[macro_use] extern crate failure_derive;
 
+pub mod bsp;
+pub mod cmd;
+pub mod crc;
+pub mod cvar;
 pub mod defs;
 pub mod parms;
 pub use parms::Parms;
 pub mod fs;
+pub mod host;
+pub mod lmp;
+pub mod model;
 #[cfg(test)]
 mod test_common;
 pub mod try_from_temp;
 pub mod util;
 pub mod wad;
+pub mod zone;
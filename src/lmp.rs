@@ -0,0 +1,225 @@
+// Copyright (C) 2018 Adrian Chan
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+//
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 59 Temple Place - Suite 330, Boston, MA  02111-1307, USA.
+
+//! Loose picture and colour lumps (`.lmp` files) from the file system.
+//!
+//! Besides the pictures in `gfx.wad`, Quake keeps many pictures as separate
+//! `gfx/*.lmp` files, such as the menu graphics.  These use the same layout
+//! as a `LumpType::Qpic` lump, so they load into the same `Pic` type.  The
+//! palette and the colormap are also kept as `.lmp` files, but have their own
+//! layouts.
+
+use std::fmt;
+
+use failure::Error;
+
+use fs::FileSys;
+pub use wad::QPic as Pic;
+use wad::Palette;
+
+/// The name of the game palette.
+pub const PALETTE_NAME: &str = "gfx/palette.lmp";
+/// The name of the colormap.
+pub const COLORMAP_NAME: &str = "gfx/colormap.lmp";
+/// The number of light levels in a `Colormap`.
+pub const NUM_LIGHT_LEVELS: usize = 64;
+/// Size of a `Colormap` on disk, in bytes.
+pub const COLORMAP_SIZE: usize = NUM_LIGHT_LEVELS * 256 + 1;
+
+
+/// Load a picture, such as `gfx/qplaque.lmp`.
+///
+/// Returns `None` if the file doesn't exist.
+pub fn load_pic(fs: &FileSys, name: &str) -> Result<Option<Pic>, Error> {
+    fs.load_parsed(name, Pic::from_bytes)
+}
+
+/// Load the game palette from `gfx/palette.lmp`.
+///
+/// Returns `None` if the file doesn't exist.
+pub fn load_palette(fs: &FileSys) -> Result<Option<Palette>, Error> {
+    fs.load_parsed(PALETTE_NAME, Palette::from_bytes)
+}
+
+
+/// Maps a palette index and a light level to the palette index that is drawn.
+///
+/// Level 0 is the brightest and level 63 the darkest, with level 32 leaving
+/// colours roughly unchanged.  The colours at the end of the palette, from
+/// `fullbright()` onwards, are fullbright: they look the same at every level.
+#[derive(Clone)]
+pub struct Colormap {
+    table: Vec<u8>,
+    fullbright: usize,
+}
+
+impl Colormap {
+    /// Load the colormap from `gfx/colormap.lmp`.
+    ///
+    /// Returns `None` if the file doesn't exist.
+    pub fn load(fs: &FileSys) -> Result<Option<Self>, Error> {
+        fs.load_parsed(COLORMAP_NAME, Self::from_bytes)
+    }
+
+    /// Parse a `Colormap` from 64 rows of 256 palette indices, followed by a
+    /// byte holding the number of fullbright colours.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Error> {
+        if data.len() != COLORMAP_SIZE {
+            bail!("Invalid colormap: expected {} bytes, got {}",
+                  COLORMAP_SIZE, data.len());
+        }
+        let (table, num_fullbright) = data.split_at(COLORMAP_SIZE - 1);
+        Ok(Self {
+            table: table.to_vec(),
+            fullbright: 256 - num_fullbright[0] as usize,
+        })
+    }
+
+    /// The palette index to draw for colour `index` at light level `level`.
+    ///
+    /// Panics if `level` isn't less than `NUM_LIGHT_LEVELS`.
+    pub fn lookup(&self, level: usize, index: u8) -> u8 {
+        self.level(level)[index as usize]
+    }
+
+    /// The 256 palette indices for light level `level`.
+    ///
+    /// Panics if `level` isn't less than `NUM_LIGHT_LEVELS`.
+    pub fn level(&self, level: usize) -> &[u8] {
+        assert!(level < NUM_LIGHT_LEVELS, "bad light level {}", level);
+        &self.table[level * 256..(level + 1) * 256]
+    }
+
+    /// The first fullbright palette index.  This is 256 if there are no
+    /// fullbright colours.
+    pub fn fullbright(&self) -> usize {
+        self.fullbright
+    }
+
+    /// Whether the colour `index` is fullbright.
+    pub fn is_fullbright(&self, index: u8) -> bool {
+        index as usize >= self.fullbright
+    }
+}
+
+impl fmt::Debug for Colormap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Colormap {{ fullbright: {}, .. }}", self.fullbright)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::{LittleEndian, WriteBytesExt};
+    use test_common as common;
+
+    fn pic_bytes(width: i32, height: i32) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.write_i32::<LittleEndian>(width).unwrap();
+        data.write_i32::<LittleEndian>(height).unwrap();
+        data.extend((0..width * height).map(|i| i as u8));
+        data
+    }
+
+    /// A colormap where each level darkens by subtracting the level, with
+    /// 32 fullbright colours.
+    fn colormap_bytes() -> Vec<u8> {
+        let mut data = Vec::with_capacity(COLORMAP_SIZE);
+        for level in 0..NUM_LIGHT_LEVELS {
+            data.extend((0..256).map(|i| {
+                if i >= 224 { i as u8 }
+                else { (i as u8).saturating_sub(level as u8) }
+            }));
+        }
+        data.push(32);
+        data
+    }
+
+    fn fixture() -> common::Fixture {
+        let palette: Vec<u8> = (0..768).map(|i| (i / 3) as u8).collect();
+        common::FixtureBuilder::new()
+            .pak(vec![
+                ("gfx/qplaque.lmp", pic_bytes(3, 2)),
+                ("gfx/bad.lmp", pic_bytes(3, 2)[..9].to_vec()),
+                (PALETTE_NAME, palette),
+                (COLORMAP_NAME, colormap_bytes()),
+            ])
+            .build()
+    }
+
+    #[test]
+    fn pic() {
+        let fixture = fixture();
+        let fs = FileSys::new(&fixture.parms()).unwrap();
+        let pic = load_pic(&fs, "gfx/qplaque.lmp").unwrap().unwrap();
+        assert_eq!(pic, Pic { width: 3, height: 2,
+                              pixels: vec![0, 1, 2, 3, 4, 5] });
+        assert!(load_pic(&fs, "gfx/nope.lmp").unwrap().is_none());
+
+        let err = load_pic(&fs, "gfx/bad.lmp").unwrap_err();
+        assert_eq!(err.to_string(), "gfx/bad.lmp: Invalid qpic: 3x2 needs \
+                                     6 bytes of pixels, got 1");
+    }
+
+    #[test]
+    fn palette() {
+        let fixture = fixture();
+        let fs = FileSys::new(&fixture.parms()).unwrap();
+        let palette = load_palette(&fs).unwrap().unwrap();
+        assert_eq!(palette.rgb(0), [0, 0, 0]);
+        assert_eq!(palette.rgb(200), [200, 200, 200]);
+    }
+
+    #[test]
+    fn colormap() {
+        let fixture = fixture();
+        let fs = FileSys::new(&fixture.parms()).unwrap();
+        let colormap = Colormap::load(&fs).unwrap().unwrap();
+        assert_eq!(colormap.fullbright(), 224);
+        assert!(colormap.is_fullbright(224));
+        assert!(!colormap.is_fullbright(223));
+        assert_eq!(colormap.lookup(0, 100), 100);
+        assert_eq!(colormap.lookup(63, 100), 37);
+        assert_eq!(colormap.lookup(63, 240), 240);
+        assert_eq!(colormap.level(10).len(), 256);
+
+        let err = Colormap::from_bytes(&colormap_bytes()[1..]).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid colormap: expected 16385 \
+                                     bytes, got 16384");
+    }
+
+    #[test]
+    fn missing() {
+        let fixture = common::FixtureBuilder::new().build();
+        let fs = FileSys::new(&fixture.parms()).unwrap();
+        assert!(load_palette(&fs).unwrap().is_none());
+        assert!(Colormap::load(&fs).unwrap().is_none());
+    }
+
+    #[cfg(feature = "retail-data")]
+    #[test]
+    fn retail_load() {
+        let fs = common::retail_fs();
+        let pic = load_pic(&fs, "gfx/qplaque.lmp").unwrap().unwrap();
+        assert!(pic.width > 0 && pic.height > 0);
+        load_palette(&fs).unwrap().unwrap();
+        let colormap = Colormap::load(&fs).unwrap().unwrap();
+        assert_eq!(colormap.fullbright(), 224);
+    }
+}
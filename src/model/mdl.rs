@@ -0,0 +1,575 @@
+// Copyright (C) 1996-1997 Id Software, Inc.
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+//
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 59 Temple Place - Suite 330, Boston, MA  02111-1307, USA.
+
+// Modified by Adrian Chan, March 2018
+// Based on modelgen.h and Mod_LoadAliasModel from model.c
+
+//! Alias models (`.mdl`), used for monsters, weapons and items.
+//!
+//! An alias model is a triangle mesh with palettised skins.  It's animated by
+//! storing every vertex position for every frame, compressed to a byte per
+//! axis.
+
+use failure::Error;
+
+use std::error;
+use std::fmt;
+
+use fs::FileSys;
+use util;
+use super::{ReadError, Reader, SyncType};
+
+/// The version of alias model used by Quake.
+pub const ALIAS_VERSION: i32 = 6;
+/// The most skins that a model can have.
+pub const MAX_SKINS: usize = 32;
+/// The most vertices that a model can have.
+pub const MAX_ALIAS_VERTS: usize = 1024;
+/// The most triangles that a model can have.
+pub const MAX_ALIAS_TRIS: usize = 2048;
+/// The most frames that a model can have.
+pub const MAX_ALIAS_FRAMES: usize = 256;
+/// The tallest skin that a model can have.
+pub const MAX_SKIN_HEIGHT: usize = 480;
+
+
+/// An alias model.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mdl {
+    /// Multiply a compressed vertex by this, and add `scale_origin`, to get
+    /// the real position.
+    pub scale: [f32; 3],
+    /// See `scale`.
+    pub scale_origin: [f32; 3],
+    /// The radius of a sphere around the origin that contains the model.
+    pub bounding_radius: f32,
+    /// The position of the eyes, for the player's view model.
+    pub eye_position: [f32; 3],
+    /// The width of each skin, in pixels.
+    pub skin_width: usize,
+    /// The height of each skin, in pixels.
+    pub skin_height: usize,
+    /// How the frames of grouped frames are timed.
+    pub sync_type: SyncType,
+    /// Effects, such as rocket trails, that the model has.
+    pub flags: i32,
+    /// The average size of a triangle.
+    pub size: f32,
+    /// The skins.
+    pub skins: Vec<Skin>,
+    /// The texture coordinates of each vertex.
+    pub st_verts: Vec<StVert>,
+    /// The triangles.
+    pub triangles: Vec<Triangle>,
+    /// The animation frames.
+    pub frames: Vec<Frame>,
+}
+
+/// A skin, or an animated group of skins.  The pixels are 8-bit palette
+/// indices, `skin_width * skin_height` of them per skin.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Skin {
+    /// A single skin.
+    Single(Vec<u8>),
+    /// Skins that are cycled through.
+    Group {
+        /// The time at which each skin ends, in seconds from the start of the
+        /// group.
+        intervals: Vec<f32>,
+        /// The skins.
+        skins: Vec<Vec<u8>>,
+    },
+}
+
+/// The skin coordinates of a vertex.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StVert {
+    /// Is the vertex on the seam between the front and back halves of the
+    /// skin?  If so, triangles facing backwards use `s + skin_width / 2`.
+    pub on_seam: bool,
+    /// The horizontal skin coordinate, in pixels.
+    pub s: i32,
+    /// The vertical skin coordinate, in pixels.
+    pub t: i32,
+}
+
+/// A triangle of the mesh.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Triangle {
+    /// Does the triangle use the front half of the skin?
+    pub faces_front: bool,
+    /// The indices of the triangle's vertices.
+    pub vertices: [u32; 3],
+}
+
+/// A compressed vertex position.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TriVertX {
+    /// The position, which is scaled by `Mdl::scale` and offset by
+    /// `Mdl::scale_origin`.
+    pub v: [u8; 3],
+    /// The index of the vertex normal, in Quake's table of normals.
+    pub light_normal_index: u8,
+}
+
+/// A frame, or an animated group of frames.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Frame {
+    /// A single frame.
+    Single(SimpleFrame),
+    /// Frames that are cycled through.
+    Group {
+        /// The minimum corner of a box containing all of the frames.
+        bbox_min: TriVertX,
+        /// The maximum corner of a box containing all of the frames.
+        bbox_max: TriVertX,
+        /// The time at which each frame ends, in seconds from the start of
+        /// the group.
+        intervals: Vec<f32>,
+        /// The frames.
+        frames: Vec<SimpleFrame>,
+    },
+}
+
+/// The positions of every vertex in one frame of animation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimpleFrame {
+    /// The minimum corner of a box containing the frame.
+    pub bbox_min: TriVertX,
+    /// The maximum corner of a box containing the frame.
+    pub bbox_max: TriVertX,
+    /// The name of the frame.
+    pub name: String,
+    /// The position of each vertex.
+    pub verts: Vec<TriVertX>,
+}
+
+impl Mdl {
+    /// Load a model, such as `progs/player.mdl`, from the file system.
+    ///
+    /// Returns `None` if the file doesn't exist.
+    pub fn load(fs: &FileSys, name: &str) -> Result<Option<Self>, Error> {
+        fs.load_parsed(name, Self::from_bytes)
+    }
+
+    /// Parse and validate a model from the contents of a `.mdl` file.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, MdlError> {
+        let mut r = Reader::new(data);
+        if r.bytes(4)? != b"IDPO" {
+            return Err(MdlError::BadMagic);
+        }
+        let version = r.i32()?;
+        if version != ALIAS_VERSION {
+            return Err(MdlError::BadVersion(version));
+        }
+
+        let scale = r.vec3()?;
+        let scale_origin = r.vec3()?;
+        let bounding_radius = r.f32()?;
+        let eye_position = r.vec3()?;
+        let num_skins = r.count("number of skins", 1, MAX_SKINS)?;
+        let at = r.pos();
+        let skin_width = r.count("skin width", 1, usize::MAX)?;
+        if !skin_width.is_multiple_of(4) {
+            return Err(MdlError::BadValue {
+                at, field: "skin width", value: skin_width as i64 });
+        }
+        let skin_height_at = r.pos();
+        let skin_height =
+            r.count("skin height", 1, MAX_SKIN_HEIGHT)?;
+        let num_verts = r.count("number of vertices", 1,
+                              MAX_ALIAS_VERTS)?;
+        let num_tris = r.count("number of triangles", 1,
+                             MAX_ALIAS_TRIS)?;
+        let num_frames = r.count("number of frames", 1,
+                               MAX_ALIAS_FRAMES)?;
+        let sync_type = r.sync_type()?;
+        let flags = r.i32()?;
+        let size = r.f32()?;
+
+        let skin_size = skin_width.checked_mul(skin_height)
+            .ok_or(MdlError::BadValue {
+                at: skin_height_at,
+                field: "skin height",
+                value: skin_height as i64,
+            })?;
+        let mut skins = Vec::with_capacity(num_skins);
+        for _ in 0..num_skins {
+            let at = r.pos();
+            skins.push(match r.i32()? {
+                0 => Skin::Single(r.bytes(skin_size)?.to_vec()),
+                1 => {
+                    let n = r.count("skins in group", 1, usize::MAX)?;
+                    let intervals = r.intervals(n)?;
+                    let mut skins = Vec::with_capacity(n.min(MAX_SKINS));
+                    for _ in 0..n {
+                        skins.push(r.bytes(skin_size)?.to_vec());
+                    }
+                    Skin::Group { intervals, skins }
+                },
+                value => return Err(MdlError::BadValue {
+                    at, field: "skin type", value: value.into() }),
+            });
+        }
+
+        let mut st_verts = Vec::with_capacity(num_verts);
+        for _ in 0..num_verts {
+            st_verts.push(StVert {
+                on_seam: r.i32()? != 0,
+                s: r.i32()?,
+                t: r.i32()?,
+            });
+        }
+
+        let mut triangles = Vec::with_capacity(num_tris);
+        for _ in 0..num_tris {
+            let faces_front = r.i32()? != 0;
+            let mut vertices = [0; 3];
+            for v in &mut vertices {
+                *v = r.count("vertex index", 0, num_verts - 1)? as u32;
+            }
+            triangles.push(Triangle { faces_front, vertices });
+        }
+
+        let mut frames = Vec::with_capacity(num_frames);
+        for _ in 0..num_frames {
+            let at = r.pos();
+            frames.push(match r.i32()? {
+                0 => Frame::Single(simple_frame(&mut r, num_verts)?),
+                1 => {
+                    let n = r.count("frames in group", 1, usize::MAX)?;
+                    let bbox_min = trivertx(&mut r)?;
+                    let bbox_max = trivertx(&mut r)?;
+                    let intervals = r.intervals(n)?;
+                    let mut frames = Vec::with_capacity(
+                        n.min(MAX_ALIAS_FRAMES));
+                    for _ in 0..n {
+                        frames.push(simple_frame(&mut r, num_verts)?);
+                    }
+                    Frame::Group { bbox_min, bbox_max, intervals, frames }
+                },
+                value => return Err(MdlError::BadValue {
+                    at, field: "frame type", value: value.into() }),
+            });
+        }
+
+        Ok(Self {
+            scale,
+            scale_origin,
+            bounding_radius,
+            eye_position,
+            skin_width,
+            skin_height,
+            sync_type,
+            flags,
+            size,
+            skins,
+            st_verts,
+            triangles,
+            frames,
+        })
+    }
+}
+
+fn trivertx(r: &mut Reader) -> Result<TriVertX, MdlError> {
+    let data = r.bytes(4)?;
+    Ok(TriVertX {
+        v: [data[0], data[1], data[2]],
+        light_normal_index: data[3],
+    })
+}
+
+fn simple_frame(r: &mut Reader, num_verts: usize)
+    -> Result<SimpleFrame, MdlError>
+{
+    let bbox_min = trivertx(r)?;
+    let bbox_max = trivertx(r)?;
+    // Only used for display, so a name with no nul or with high-bit
+    // characters is fine.
+    let name = util::cstr_buf_to_string_lossy(r.bytes(16)?);
+    let mut verts = Vec::with_capacity(num_verts);
+    for _ in 0..num_verts {
+        verts.push(trivertx(r)?);
+    }
+    Ok(SimpleFrame { bbox_min, bbox_max, name, verts })
+}
+
+/// The ways that loading an alias model can fail.
+///
+/// Errors about a particular value in the model have `at`, the offset of that
+/// value within the file.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MdlError {
+    /// The data doesn't start with `IDPO`, so isn't an alias model.
+    BadMagic,
+    /// The model isn't the version that we can load.
+    BadVersion(i32),
+    /// Part of the model extends past the end of the data.
+    OutOfBounds {
+        /// The offset of the part.
+        at: usize,
+        /// The size of the part, in bytes.
+        len: usize,
+    },
+    /// A count, index or type is out of range.
+    BadValue {
+        /// Where the value is stored.
+        at: usize,
+        /// What the value is.
+        field: &'static str,
+        /// The value.
+        value: i64,
+    },
+    /// The interval of a skin or frame in a group isn't positive.
+    BadInterval {
+        /// Where the interval is stored.
+        at: usize,
+        /// The interval.
+        value: f32,
+    },
+}
+
+impl From<ReadError> for MdlError {
+    fn from(e: ReadError) -> Self {
+        match e {
+            ReadError::Truncated { at, len } =>
+                MdlError::OutOfBounds { at, len },
+            ReadError::BadValue { at, field, value } =>
+                MdlError::BadValue { at, field, value },
+            ReadError::BadInterval { at, value } =>
+                MdlError::BadInterval { at, value },
+        }
+    }
+}
+
+impl fmt::Display for MdlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MdlError::BadMagic =>
+                write!(f, "not an alias model (no IDPO header)"),
+            MdlError::BadVersion(version) =>
+                write!(f, "alias model has wrong version number \
+                           ({} should be {})", version, ALIAS_VERSION),
+            MdlError::OutOfBounds { at, len } =>
+                write!(f, "{} bytes at offset {} are past the end of the \
+                           model", len, at),
+            MdlError::BadValue { at, field, value } =>
+                write!(f, "bad {} {} at offset {}", field, value, at),
+            MdlError::BadInterval { at, value } =>
+                write!(f, "interval {} at offset {} is not positive",
+                       value, at),
+        }
+    }
+}
+
+impl error::Error for MdlError {}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::{ByteOrder, LittleEndian};
+    use std::io::Write;
+    use test_common::{write_floats, write_ints};
+
+    type LE = LittleEndian;
+
+    /// Where the number of skins is stored in the header.
+    const NUM_SKINS_AT: usize = 48;
+    /// Where the skins start.
+    const SKINS_AT: usize = 84;
+
+    fn write_frame(data: &mut Vec<u8>, name: &[u8], first: u8) {
+        data.write_all(&[0, 0, 0, 0, 9, 9, 9, 0]).unwrap();
+        let mut name_buf = [0; 16];
+        name_buf[..name.len()].copy_from_slice(name);
+        data.write_all(&name_buf).unwrap();
+        for i in 0..3 {
+            data.write_all(&[first + i, 1, 2, 3]).unwrap();
+        }
+    }
+
+    /// A model with 4x2 skins, 3 vertices and 1 triangle.  There's a single
+    /// skin, then a group of two, and a single frame, then a group of two.
+    fn mdl_bytes() -> Vec<u8> {
+        let mut data = b"IDPO".to_vec();
+        write_ints(&mut data, &[ALIAS_VERSION]);
+        write_floats(&mut data, &[1.0, 2.0, 3.0, -4.0, -5.0, -6.0, 30.0,
+                                  0.0, 0.0, 22.0]);
+        write_ints(&mut data, &[2, 4, 2, 3, 1, 2, 1, 8]);
+        write_floats(&mut data, &[5.5]);
+        assert_eq!(data.len(), SKINS_AT);
+
+        write_ints(&mut data, &[0]);
+        data.write_all(&[1; 8]).unwrap();
+        write_ints(&mut data, &[1, 2]);
+        write_floats(&mut data, &[0.1, 0.2]);
+        data.write_all(&[2; 8]).unwrap();
+        data.write_all(&[3; 8]).unwrap();
+
+        write_ints(&mut data, &[0, 0, 0, 32, 4, 0, 0, 0, 2]);
+        write_ints(&mut data, &[1, 0, 1, 2]);
+
+        write_ints(&mut data, &[0]);
+        write_frame(&mut data, b"stand1", 10);
+        write_ints(&mut data, &[1, 2]);
+        data.write_all(&[0, 0, 0, 0, 9, 9, 9, 0]).unwrap();
+        write_floats(&mut data, &[0.1, 0.2]);
+        write_frame(&mut data, b"run1", 20);
+        write_frame(&mut data, b"run2", 30);
+        data
+    }
+
+    fn modified(at: usize, value: i32) -> Vec<u8> {
+        let mut data = mdl_bytes();
+        LE::write_i32(&mut data[at..at+4], value);
+        data
+    }
+
+    #[test]
+    fn load() {
+        let mdl = Mdl::from_bytes(&mdl_bytes()).unwrap();
+        assert_eq!(mdl.scale, [1.0, 2.0, 3.0]);
+        assert_eq!(mdl.scale_origin, [-4.0, -5.0, -6.0]);
+        assert_eq!(mdl.bounding_radius, 30.0);
+        assert_eq!(mdl.eye_position, [0.0, 0.0, 22.0]);
+        assert_eq!((mdl.skin_width, mdl.skin_height), (4, 2));
+        assert_eq!(mdl.sync_type, SyncType::Rand);
+        assert_eq!(mdl.flags, 8);
+        assert_eq!(mdl.size, 5.5);
+
+        assert_eq!(mdl.skins, &[
+            Skin::Single(vec![1; 8]),
+            Skin::Group {
+                intervals: vec![0.1, 0.2],
+                skins: vec![vec![2; 8], vec![3; 8]],
+            },
+        ]);
+        assert_eq!(mdl.st_verts, &[
+            StVert { on_seam: false, s: 0, t: 0 },
+            StVert { on_seam: true, s: 4, t: 0 },
+            StVert { on_seam: false, s: 0, t: 2 },
+        ]);
+        assert_eq!(mdl.triangles, &[
+            Triangle { faces_front: true, vertices: [0, 1, 2] },
+        ]);
+
+        assert_eq!(mdl.frames.len(), 2);
+        match mdl.frames[0] {
+            Frame::Single(ref frame) => {
+                assert_eq!(frame.name, "stand1");
+                assert_eq!(frame.bbox_max.v, [9, 9, 9]);
+                assert_eq!(frame.verts[2],
+                           TriVertX { v: [12, 1, 2], light_normal_index: 3 });
+            },
+            ref other => panic!("expected a single frame, got {:?}", other),
+        }
+        match mdl.frames[1] {
+            Frame::Group { ref intervals, ref frames, bbox_max, .. } => {
+                assert_eq!(intervals, &[0.1, 0.2]);
+                assert_eq!(bbox_max.v, [9, 9, 9]);
+                assert_eq!(frames[0].name, "run1");
+                assert_eq!(frames[1].name, "run2");
+                assert_eq!(frames[1].verts[0].v, [30, 1, 2]);
+            },
+            ref other => panic!("expected a frame group, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let data = mdl_bytes();
+        let load = |data: &[u8]| Mdl::from_bytes(data).unwrap_err();
+
+        assert_eq!(load(b"IDSP"), MdlError::BadMagic);
+        assert_eq!(load(&modified(4, 7)), MdlError::BadVersion(7));
+        assert_eq!(load(&data[..40]),
+                   MdlError::OutOfBounds { at: 40, len: 4 });
+        assert_eq!(load(&data[..data.len() - 1]),
+                   MdlError::OutOfBounds { at: data.len() - 4, len: 4 });
+
+        assert_eq!(load(&modified(NUM_SKINS_AT, 0)),
+                   MdlError::BadValue {
+                       at: NUM_SKINS_AT, field: "number of skins", value: 0 });
+        assert_eq!(load(&modified(NUM_SKINS_AT, 33)),
+                   MdlError::BadValue {
+                       at: NUM_SKINS_AT, field: "number of skins",
+                       value: 33 });
+        assert_eq!(load(&modified(52, 6)),
+                   MdlError::BadValue { at: 52, field: "skin width",
+                                        value: 6 });
+        assert_eq!(load(&modified(56, 481)),
+                   MdlError::BadValue { at: 56, field: "skin height",
+                                        value: 481 });
+        assert_eq!(load(&modified(60, -1)),
+                   MdlError::BadValue { at: 60, field: "number of vertices",
+                                        value: -1 });
+        assert_eq!(load(&modified(72, 2)),
+                   MdlError::BadValue { at: 72, field: "sync type",
+                                        value: 2 });
+        assert_eq!(load(&modified(SKINS_AT, 2)),
+                   MdlError::BadValue { at: SKINS_AT, field: "skin type",
+                                        value: 2 });
+
+        // The group of skins.
+        let group_at = SKINS_AT + 4 + 8;
+        assert_eq!(load(&modified(group_at + 4, 1_000_000)),
+                   MdlError::OutOfBounds {
+                       at: group_at + 8, len: 4_000_000 });
+        let mut bad = data.clone();
+        LE::write_f32(&mut bad[group_at+12..group_at+16], 0.0);
+        assert_eq!(load(&bad),
+                   MdlError::BadInterval { at: group_at + 12, value: 0.0 });
+
+        // The triangle.
+        let tri_at = group_at + 16 + 16 + 3*12;
+        assert_eq!(load(&modified(tri_at + 12, 3)),
+                   MdlError::BadValue { at: tri_at + 12,
+                                        field: "vertex index", value: 3 });
+
+        // The single frame.
+        let frame_at = tri_at + 16;
+        assert_eq!(load(&modified(frame_at, -1)),
+                   MdlError::BadValue { at: frame_at, field: "frame type",
+                                        value: -1 });
+    }
+
+    /// Frame names don't need a nul or to be UTF-8.
+    #[test]
+    fn frame_names() {
+        let mut data = mdl_bytes();
+        let name_at = data.len() - 3*4 - 16;
+        data[name_at..name_at+16].copy_from_slice(b"sixteen\xff chars!!");
+        let mdl = Mdl::from_bytes(&data).unwrap();
+        match mdl.frames[1] {
+            Frame::Group { ref frames, .. } =>
+                assert_eq!(frames[1].name, "sixteen\u{fffd} chars!!"),
+            ref other => panic!("expected a frame group, got {:?}", other),
+        }
+    }
+
+    /// Load the player model from a full version of standard Quake 1.
+    #[cfg(feature = "retail-data")]
+    #[test]
+    fn retail_load_player() {
+        let fs = ::test_common::retail_fs();
+        let mdl = Mdl::load(&fs, "progs/player.mdl").unwrap().unwrap();
+        assert!(!mdl.skins.is_empty());
+        assert!(!mdl.triangles.is_empty());
+        assert!(mdl.frames.len() > 1);
+    }
+}
@@ -0,0 +1,132 @@
+// Copyright (C) 1996-1997 Id Software, Inc.
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+//
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 59 Temple Place - Suite 330, Boston, MA  02111-1307, USA.
+
+// Modified by Adrian Chan, March 2018
+// Based on the alias model and sprite parts of model.c
+
+//! Loading the models used by entities: alias models (`.mdl`) and sprites
+//! (`.spr`).
+//!
+//! Maps, which are also models in Quake, are loaded by the `bsp` module.
+
+use byteorder::{ByteOrder, LittleEndian};
+
+pub mod mdl;
+pub use self::mdl::Mdl;
+pub mod spr;
+pub use self::spr::Spr;
+
+
+/// Whether a model's animations all run in step, or start at random times.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncType {
+    /// Every instance of the model animates in step.
+    Sync,
+    /// Each instance of the model starts its animations at a random time.
+    Rand,
+}
+
+
+/// The problems that the shared parts of the model loaders can find.  Each
+/// loader converts these into its own error type.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ReadError {
+    /// Part of the model extends past the end of the data.
+    Truncated { at: usize, len: usize },
+    /// A count or index is out of range.
+    BadValue { at: usize, field: &'static str, value: i64 },
+    /// An interval in a group isn't positive.
+    BadInterval { at: usize, value: f32 },
+}
+
+/// Reads little-endian values from the start of a model onwards, keeping track
+/// of the offset for error messages.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    /// The offset of the next value.
+    fn pos(&self) -> usize {
+        self.pos
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], ReadError> {
+        let at = self.pos;
+        match at.checked_add(len) {
+            Some(end) if end <= self.data.len() => {
+                self.pos = end;
+                Ok(&self.data[at..end])
+            },
+            _ => Err(ReadError::Truncated { at, len }),
+        }
+    }
+
+    fn i32(&mut self) -> Result<i32, ReadError> {
+        self.bytes(4).map(LittleEndian::read_i32)
+    }
+
+    fn f32(&mut self) -> Result<f32, ReadError> {
+        self.bytes(4).map(LittleEndian::read_f32)
+    }
+
+    fn vec3(&mut self) -> Result<[f32; 3], ReadError> {
+        Ok([self.f32()?, self.f32()?, self.f32()?])
+    }
+
+    /// Read a count or index, and check that it's within `min..=max`.
+    fn count(&mut self, field: &'static str, min: usize, max: usize)
+        -> Result<usize, ReadError>
+    {
+        let at = self.pos;
+        let value = self.i32()?;
+        if value < 0 || (value as usize) < min || value as usize > max {
+            return Err(ReadError::BadValue { at, field, value: value.into() });
+        }
+        Ok(value as usize)
+    }
+
+    fn sync_type(&mut self) -> Result<SyncType, ReadError> {
+        let at = self.pos;
+        match self.i32()? {
+            0 => Ok(SyncType::Sync),
+            1 => Ok(SyncType::Rand),
+            value => Err(ReadError::BadValue {
+                at, field: "sync type", value: value.into() }),
+        }
+    }
+
+    /// Read the `n` intervals of a group, which must all be positive.
+    fn intervals(&mut self, n: usize) -> Result<Vec<f32>, ReadError> {
+        let at = self.pos;
+        let data = self.bytes(n.saturating_mul(4))?;
+        data.chunks(4).enumerate()
+            .map(|(i, bytes)| {
+                let value = LittleEndian::read_f32(bytes);
+                if value.is_nan() || value <= 0.0 {
+                    return Err(ReadError::BadInterval { at: at + i*4, value });
+                }
+                Ok(value)
+            })
+            .collect()
+    }
+}
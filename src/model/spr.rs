@@ -0,0 +1,445 @@
+// Copyright (C) 1996-1997 Id Software, Inc.
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+//
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 59 Temple Place - Suite 330, Boston, MA  02111-1307, USA.
+
+// Modified by Adrian Chan, March 2018
+// Based on spritegn.h and Mod_LoadSpriteModel from model.c
+
+//! Sprites (`.spr`), used for explosions, bubbles and lights.
+//!
+//! A sprite is a set of flat 8-bit pictures that always face the viewer.  The
+//! pixels are indices into the game palette, which is the same `Palette` that
+//! is decoded from `gfx.wad`.  Index 255 is transparent.
+
+use failure::Error;
+
+use std::error;
+use std::fmt;
+
+use fs::FileSys;
+use wad::Palette;
+use super::{ReadError, Reader, SyncType};
+
+/// The version of sprite used by Quake.
+pub const SPRITE_VERSION: i32 = 1;
+/// The number of frames in an angled frame group, one for each 45 degrees.
+pub const NUM_ANGLES: usize = 8;
+/// The palette index that is drawn as transparent.
+pub const TRANSPARENT_INDEX: u8 = 255;
+
+
+/// A sprite.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Spr {
+    /// How the sprite is oriented relative to the viewer.
+    pub kind: SpriteType,
+    /// The radius of a sphere around the origin that contains the sprite.
+    pub bounding_radius: f32,
+    /// The width of the widest frame, in pixels.
+    pub max_width: usize,
+    /// The height of the tallest frame, in pixels.
+    pub max_height: usize,
+    /// Unused by Quake.
+    pub beam_length: f32,
+    /// How the frames of grouped frames are timed.
+    pub sync_type: SyncType,
+    /// The animation frames.
+    pub frames: Vec<Frame>,
+}
+
+/// How a sprite is oriented relative to the viewer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpriteType {
+    /// Faces the view plane, but stays upright.
+    ViewParallelUpright,
+    /// Faces the viewer's position, but stays upright.
+    FacingUpright,
+    /// Faces the view plane.  This is the most common type.
+    ViewParallel,
+    /// Has a fixed orientation, given by the entity's angles.
+    Oriented,
+    /// Faces the view plane, but is rolled by the entity's angles.
+    ViewParallelOriented,
+}
+
+/// A frame, or a group of frames.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Frame {
+    /// A single frame.
+    Single(SimpleFrame),
+    /// Frames that are cycled through.
+    Group {
+        /// The time at which each frame ends, in seconds from the start of
+        /// the group.
+        intervals: Vec<f32>,
+        /// The frames.
+        frames: Vec<SimpleFrame>,
+    },
+    /// Eight frames, for viewing the sprite from every 45 degrees around it.
+    /// Frame 0 is seen from the front of the entity.
+    Angled(Vec<SimpleFrame>),
+}
+
+/// One picture of a sprite.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimpleFrame {
+    /// The position of the picture's top left corner relative to the
+    /// sprite's origin, with `y` going up.
+    pub origin: [i32; 2],
+    /// The width of the picture, in pixels.
+    pub width: usize,
+    /// The height of the picture, in pixels.
+    pub height: usize,
+    /// `width * height` palette indices, one row after another.
+    pub pixels: Vec<u8>,
+}
+
+impl SimpleFrame {
+    /// Convert the picture to RGBA, using the given palette.
+    ///
+    /// `TRANSPARENT_INDEX` pixels get an alpha of 0, and all others are
+    /// opaque.
+    pub fn to_rgba(&self, palette: &Palette) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(self.pixels.len() * 4);
+        for &index in &self.pixels {
+            rgba.extend_from_slice(&palette.rgb(index));
+            rgba.push(if index == TRANSPARENT_INDEX { 0 } else { 255 });
+        }
+        rgba
+    }
+}
+
+impl Spr {
+    /// Load a sprite, such as `progs/s_explod.spr`, from the file system.
+    ///
+    /// Returns `None` if the file doesn't exist.
+    pub fn load(fs: &FileSys, name: &str) -> Result<Option<Self>, Error> {
+        fs.load_parsed(name, Self::from_bytes)
+    }
+
+    /// Parse and validate a sprite from the contents of a `.spr` file.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, SprError> {
+        let mut r = Reader::new(data);
+        if r.bytes(4)? != b"IDSP" {
+            return Err(SprError::BadMagic);
+        }
+        let version = r.i32()?;
+        if version != SPRITE_VERSION {
+            return Err(SprError::BadVersion(version));
+        }
+
+        let at = r.pos();
+        let kind = match r.i32()? {
+            0 => SpriteType::ViewParallelUpright,
+            1 => SpriteType::FacingUpright,
+            2 => SpriteType::ViewParallel,
+            3 => SpriteType::Oriented,
+            4 => SpriteType::ViewParallelOriented,
+            value => return Err(SprError::BadValue {
+                at, field: "sprite type", value: value.into() }),
+        };
+        let bounding_radius = r.f32()?;
+        let max_width = r.count("width", 0, usize::MAX)?;
+        let max_height = r.count("height", 0, usize::MAX)?;
+        let num_frames = r.count("number of frames", 1, usize::MAX)?;
+        let beam_length = r.f32()?;
+        let sync_type = r.sync_type()?;
+
+        // Every frame is at least 20 bytes, so a bad count can't make a huge
+        // Vec.
+        let mut frames = Vec::with_capacity(num_frames.min(data.len() / 20));
+        for _ in 0..num_frames {
+            let at = r.pos();
+            frames.push(match r.i32()? {
+                0 => Frame::Single(simple_frame(&mut r)?),
+                1 => {
+                    let n = r.count("frames in group", 1, usize::MAX)?;
+                    let intervals = r.intervals(n)?;
+                    Frame::Group { intervals, frames: frames_in(&mut r, n)? }
+                },
+                2 => {
+                    // Stored like a group, but the intervals aren't used.
+                    let n = r.count("frames in angled group", NUM_ANGLES,
+                                    NUM_ANGLES)?;
+                    r.bytes(n * 4)?;
+                    Frame::Angled(frames_in(&mut r, n)?)
+                },
+                value => return Err(SprError::BadValue {
+                    at, field: "frame type", value: value.into() }),
+            });
+        }
+
+        Ok(Self {
+            kind,
+            bounding_radius,
+            max_width,
+            max_height,
+            beam_length,
+            sync_type,
+            frames,
+        })
+    }
+}
+
+fn simple_frame(r: &mut Reader) -> Result<SimpleFrame, SprError> {
+    let origin = [r.i32()?, r.i32()?];
+    let width = r.count("frame width", 0, usize::MAX)?;
+    let height_at = r.pos();
+    let height = r.count("frame height", 0, usize::MAX)?;
+    let num_pixels = width.checked_mul(height)
+        .ok_or(SprError::BadValue {
+            at: height_at, field: "frame height", value: height as i64 })?;
+    let pixels = r.bytes(num_pixels)?.to_vec();
+    Ok(SimpleFrame { origin, width, height, pixels })
+}
+
+fn frames_in(r: &mut Reader, n: usize) -> Result<Vec<SimpleFrame>, SprError> {
+    (0..n).map(|_| simple_frame(r)).collect()
+}
+
+/// The ways that loading a sprite can fail.
+///
+/// Errors about a particular value in the sprite have `at`, the offset of
+/// that value within the file.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SprError {
+    /// The data doesn't start with `IDSP`, so isn't a sprite.
+    BadMagic,
+    /// The sprite isn't the version that we can load.
+    BadVersion(i32),
+    /// Part of the sprite extends past the end of the data.
+    OutOfBounds {
+        /// The offset of the part.
+        at: usize,
+        /// The size of the part, in bytes.
+        len: usize,
+    },
+    /// A count, size or type is out of range.
+    BadValue {
+        /// Where the value is stored.
+        at: usize,
+        /// What the value is.
+        field: &'static str,
+        /// The value.
+        value: i64,
+    },
+    /// The interval of a frame in a group isn't positive.
+    BadInterval {
+        /// Where the interval is stored.
+        at: usize,
+        /// The interval.
+        value: f32,
+    },
+}
+
+impl From<ReadError> for SprError {
+    fn from(e: ReadError) -> Self {
+        match e {
+            ReadError::Truncated { at, len } =>
+                SprError::OutOfBounds { at, len },
+            ReadError::BadValue { at, field, value } =>
+                SprError::BadValue { at, field, value },
+            ReadError::BadInterval { at, value } =>
+                SprError::BadInterval { at, value },
+        }
+    }
+}
+
+impl fmt::Display for SprError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SprError::BadMagic =>
+                write!(f, "not a sprite (no IDSP header)"),
+            SprError::BadVersion(version) =>
+                write!(f, "sprite has wrong version number \
+                           ({} should be {})", version, SPRITE_VERSION),
+            SprError::OutOfBounds { at, len } =>
+                write!(f, "{} bytes at offset {} are past the end of the \
+                           sprite", len, at),
+            SprError::BadValue { at, field, value } =>
+                write!(f, "bad {} {} at offset {}", field, value, at),
+            SprError::BadInterval { at, value } =>
+                write!(f, "interval {} at offset {} is not positive",
+                       value, at),
+        }
+    }
+}
+
+impl error::Error for SprError {}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+    use std::io::Write;
+    use test_common::write_ints;
+
+    type LE = LittleEndian;
+
+    /// Where the frames start.
+    const FRAMES_AT: usize = 36;
+
+    fn write_frame(data: &mut Vec<u8>, width: i32, height: i32, pixel: u8) {
+        write_ints(data, &[-width / 2, height / 2, width, height]);
+        data.write_all(&vec![pixel; (width * height) as usize]).unwrap();
+    }
+
+    /// A sprite with a single 2x3 frame, a group of two frames, and an angled
+    /// group.
+    fn spr_bytes() -> Vec<u8> {
+        let mut data = b"IDSP".to_vec();
+        write_ints(&mut data, &[SPRITE_VERSION, 2]);
+        data.write_f32::<LE>(12.5).unwrap();
+        write_ints(&mut data, &[4, 3, 3]);
+        data.write_f32::<LE>(0.0).unwrap();
+        write_ints(&mut data, &[0]);
+        assert_eq!(data.len(), FRAMES_AT);
+
+        write_ints(&mut data, &[0]);
+        write_frame(&mut data, 2, 3, 1);
+
+        write_ints(&mut data, &[1, 2]);
+        data.write_f32::<LE>(0.1).unwrap();
+        data.write_f32::<LE>(0.2).unwrap();
+        write_frame(&mut data, 4, 1, 2);
+        write_frame(&mut data, 1, 1, TRANSPARENT_INDEX);
+
+        write_ints(&mut data, &[2, NUM_ANGLES as i32]);
+        for _ in 0..NUM_ANGLES {
+            data.write_f32::<LE>(1.0).unwrap();
+        }
+        for angle in 0..NUM_ANGLES {
+            write_frame(&mut data, 1, 1, angle as u8);
+        }
+        data
+    }
+
+    #[test]
+    fn load() {
+        let spr = Spr::from_bytes(&spr_bytes()).unwrap();
+        assert_eq!(spr.kind, SpriteType::ViewParallel);
+        assert_eq!(spr.bounding_radius, 12.5);
+        assert_eq!((spr.max_width, spr.max_height), (4, 3));
+        assert_eq!(spr.sync_type, SyncType::Sync);
+        assert_eq!(spr.frames.len(), 3);
+
+        assert_eq!(spr.frames[0], Frame::Single(SimpleFrame {
+            origin: [-1, 1],
+            width: 2,
+            height: 3,
+            pixels: vec![1; 6],
+        }));
+        match spr.frames[1] {
+            Frame::Group { ref intervals, ref frames } => {
+                assert_eq!(intervals, &[0.1, 0.2]);
+                assert_eq!((frames[0].width, frames[0].height), (4, 1));
+                assert_eq!(frames[1].pixels, &[TRANSPARENT_INDEX]);
+            },
+            ref other => panic!("expected a frame group, got {:?}", other),
+        }
+        match spr.frames[2] {
+            Frame::Angled(ref frames) => {
+                assert_eq!(frames.len(), NUM_ANGLES);
+                assert_eq!(frames[7].pixels, &[7]);
+            },
+            ref other => panic!("expected angled frames, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rgba() {
+        let colours: Vec<u8> = (0..768).map(|i| (i / 3) as u8).collect();
+        let palette = Palette::from_bytes(&colours).unwrap();
+        let frame = SimpleFrame {
+            origin: [0, 0],
+            width: 2,
+            height: 1,
+            pixels: vec![7, TRANSPARENT_INDEX],
+        };
+        assert_eq!(frame.to_rgba(&palette),
+                   &[7, 7, 7, 255, 255, 255, 255, 0]);
+    }
+
+    #[test]
+    fn errors() {
+        let data = spr_bytes();
+        let load = |data: &[u8]| Spr::from_bytes(data).unwrap_err();
+        let modified = |at: usize, value: i32| {
+            let mut data = spr_bytes();
+            LE::write_i32(&mut data[at..at+4], value);
+            Spr::from_bytes(&data).unwrap_err()
+        };
+
+        assert_eq!(load(b"IDPO"), SprError::BadMagic);
+        assert_eq!(modified(4, 2), SprError::BadVersion(2));
+        assert_eq!(modified(8, 5),
+                   SprError::BadValue { at: 8, field: "sprite type",
+                                        value: 5 });
+        assert_eq!(modified(24, 0),
+                   SprError::BadValue { at: 24, field: "number of frames",
+                                        value: 0 });
+        assert_eq!(modified(32, 2),
+                   SprError::BadValue { at: 32, field: "sync type",
+                                        value: 2 });
+        assert_eq!(load(&data[..data.len() - 1]),
+                   SprError::OutOfBounds { at: data.len() - 1, len: 1 });
+
+        assert_eq!(modified(FRAMES_AT, 3),
+                   SprError::BadValue { at: FRAMES_AT, field: "frame type",
+                                        value: 3 });
+        assert_eq!(modified(FRAMES_AT + 12, -2),
+                   SprError::BadValue { at: FRAMES_AT + 12,
+                                        field: "frame width", value: -2 });
+
+        // Huge frames are an error on any size of usize.
+        let mut huge = data.clone();
+        LE::write_i32(&mut huge[FRAMES_AT+12..FRAMES_AT+16], i32::MAX);
+        LE::write_i32(&mut huge[FRAMES_AT+16..FRAMES_AT+20], i32::MAX);
+        let max = i32::MAX as usize;
+        match max.checked_mul(max) {
+            Some(len) => assert_eq!(load(&huge),
+                                    SprError::OutOfBounds {
+                                        at: FRAMES_AT + 20, len }),
+            None => assert_eq!(load(&huge),
+                               SprError::BadValue {
+                                   at: FRAMES_AT + 16, field: "frame height",
+                                   value: i32::MAX.into() }),
+        }
+
+        let group_at = FRAMES_AT + 4 + 16 + 6;
+        assert_eq!(modified(group_at + 4, 0),
+                   SprError::BadValue { at: group_at + 4,
+                                        field: "frames in group", value: 0 });
+        assert_eq!(modified(group_at + 8, -1082130432),
+                   SprError::BadInterval { at: group_at + 8, value: -1.0 });
+
+        let angled_at = group_at + 16 + 20 + 17;
+        assert_eq!(modified(angled_at + 4, 4),
+                   SprError::BadValue { at: angled_at + 4,
+                                        field: "frames in angled group",
+                                        value: 4 });
+    }
+
+    #[cfg(feature = "retail-data")]
+    #[test]
+    fn retail_load_explosion() {
+        let fs = ::test_common::retail_fs();
+        let spr = Spr::load(&fs, "progs/s_explod.spr").unwrap().unwrap();
+        assert!(spr.frames.len() > 1);
+        assert!(spr.max_width > 0 && spr.max_height > 0);
+    }
+}
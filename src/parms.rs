@@ -21,10 +21,24 @@
 //! Command line parameter handling.
 
 use std;
+use std::env;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 
+use failure::Error;
+
+use cmd::Cbuf;
 use defs;
+use util;
+
+/// Extra command line arguments can be given in this environment variable.
+/// They are added after the real arguments.
+pub const ARGS_ENV_VAR: &str = "RQS_ARGS";
 
-const SAFE_ARGVS: [&'static str; 7] =
+const SAFE_ARGVS: [&str; 7] =
     ["-stdvid", "-nolan", "-nosound", "-nocdaudio", "-nojoy", "-nomouse",
      "-dibonly"];
 
@@ -38,14 +52,17 @@ const SAFE_ARGVS: [&'static str; 7] =
 /// The convention for command line parameters is as follows:
 ///
 /// * Commands starts with a `+`
-/// * TODO: what comes after a command?
+/// * Commands can be followed by zero or more arguments
 /// * Parameters start with a `-`
 /// * Parameters can be followed by zero or more values
-/// * Parameter values cannot start with `-` or `+`
-
+/// * Parameter values and command arguments cannot start with `-` or `+`
+///
+/// `Parms::from_args()` also expands `@file` response files and adds the
+/// arguments from the `RQS_ARGS` environment variable.
 // Don't intend to support cachedir.
 pub struct Parms {
     argv: Vec<String>,
+    dropped: Vec<String>,
     cwd: String,
     cmdline: String,
     is_dedicated: bool,
@@ -56,12 +73,52 @@ pub struct Parms {
 
 impl Parms {
     /// Construct a new `Parms`.
+    ///
+    /// Only the first `defs::MAX_NUM_ARGVS` arguments are used; the rest are
+    /// available from `dropped()`.
     pub fn new(mut argv: Vec<String>, cwd: String) -> Self {
-        argv.truncate(defs::MAX_NUM_ARGVS);
+        let dropped = if argv.len() > defs::MAX_NUM_ARGVS {
+            argv.split_off(defs::MAX_NUM_ARGVS)
+        } else {
+            Vec::new()
+        };
+        Self::with_dropped(argv, dropped, cwd)
+    }
+
+    /// Construct a new `Parms` from the arguments that Quake was started
+    /// with, after expanding them with `expand_args()`.
+    ///
+    /// The arguments from the `RQS_ARGS` environment variable are added to
+    /// the end.  Unlike `new()`, there is no limit on the number of
+    /// arguments, so long launch lines can be moved into response files.
+    pub fn from_args(argv: Vec<String>, cwd: String)
+        -> Result<Self, ParmsError>
+    {
+        let env_args = match env::var(ARGS_ENV_VAR) {
+            Ok(args) => Some(args),
+            Err(env::VarError::NotPresent) => None,
+            Err(env::VarError::NotUnicode(_)) =>
+                return Err(ParmsError::EnvNotUnicode),
+        };
+        Self::from_args_and_env(argv, cwd, env_args.as_deref())
+    }
+
+    /// `from_args()`, with the value of `RQS_ARGS` passed in.
+    fn from_args_and_env(argv: Vec<String>, cwd: String,
+                         env_args: Option<&str>)
+        -> Result<Self, ParmsError>
+    {
+        let argv = expand_args(argv, Path::new(&cwd), env_args)?;
+        Ok(Self::with_dropped(argv, Vec::new(), cwd))
+    }
+
+    fn with_dropped(mut argv: Vec<String>, dropped: Vec<String>, cwd: String)
+        -> Self
+    {
         // Reconstitute the command line for the cmdline externally visible
         // cvar.
         let mut cmdline: String = argv[..].join(" ");
-        cmdline.truncate(defs::CMDLINE_LENGTH);
+        util::truncate_str(&mut cmdline, defs::CMDLINE_LENGTH);
 
         let safe = argv.contains(&"-safe".to_string());
         if safe {
@@ -73,8 +130,9 @@ impl Parms {
 
         let mut parms = Parms {
             argv,
+            dropped,
             cwd,
-            cmdline: String::new(),
+            cmdline,
             is_dedicated: false,
             is_standard_quake: true,
             is_rogue: false,
@@ -84,11 +142,25 @@ impl Parms {
         parms
     }
 
+    /// The arguments that `new()` ignored because there were more than
+    /// `defs::MAX_NUM_ARGVS`.
+    pub fn dropped(&self) -> &[String] {
+        &self.dropped
+    }
+
     /// The current working directory (when Quake was started).
     pub fn cwd(&self) -> &str {
         &self.cwd
     }
 
+    /// The command line arguments, joined with spaces and truncated to
+    /// `defs::CMDLINE_LENGTH` bytes.
+    ///
+    /// This is the value of the `cmdline` cvar.
+    pub fn cmdline(&self) -> &str {
+        &self.cmdline
+    }
+
     /// Is Quake running as a dedicated server?
     pub fn is_dedicated(&self) -> bool {
         self.is_dedicated
@@ -109,6 +181,110 @@ impl Parms {
         self.is_hipnotic
     }
 
+    /// The size of the hunk, in bytes.
+    ///
+    /// Set with `-heapsize <kb>`, otherwise `defs::DEFAULT_HEAP_SIZE`.  It is
+    /// an error if the size in bytes doesn't fit in a `usize`.
+    pub fn heap_size(&self) -> Result<usize, Error> {
+        self.kb_value("-heapsize", defs::DEFAULT_HEAP_SIZE)
+    }
+
+    /// The size of the zone, in bytes.
+    ///
+    /// Set with `-zone <kb>`, otherwise `defs::DEFAULT_ZONE_SIZE`.  It is an
+    /// error if the size in bytes doesn't fit in a `usize`.
+    pub fn zone_size(&self) -> Result<usize, Error> {
+        self.kb_value("-zone", defs::DEFAULT_ZONE_SIZE)
+    }
+
+    /// The value of a parameter given in kilobytes, converted to bytes.
+    fn kb_value(&self, parm: &str, default: usize) -> Result<usize, Error> {
+        match self.parse_value::<usize>(parm) {
+            Some(kb) => kb.checked_mul(1024)
+                .ok_or_else(|| format_err!("{} {} is too large", parm, kb)),
+            None => Ok(default),
+        }
+    }
+
+    /// The commands given on the command line, one per line.
+    ///
+    /// Arguments with spaces or `;` in them are quoted.  The command parser
+    /// has no way to escape a `"` or a line break, so a command with an
+    /// argument containing one is left out, and reported by
+    /// `bad_commands()`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rqs::Parms;
+    ///
+    /// let p = Parms::new(
+    ///     vec!("-game".into(), "mymod".into(), "+map".into(), "e1m1".into(),
+    ///          "+skill".into(), "3".into()),
+    ///     "cwd".into());
+    /// assert_eq!(p.commands(), "map e1m1\nskill 3\n");
+    /// ```
+    pub fn commands(&self) -> String {
+        let mut text = String::new();
+        for line in self.command_lines().into_iter().filter_map(Result::ok) {
+            text.push_str(&line);
+            text.push('\n');
+        }
+        text
+    }
+
+    /// The commands that `commands()` left out, because an argument couldn't
+    /// be quoted.
+    pub fn bad_commands(&self) -> Vec<ParmsError> {
+        self.command_lines().into_iter().filter_map(Result::err).collect()
+    }
+
+    /// Each command given on the command line, as a line for the command
+    /// buffer.
+    fn command_lines(&self) -> Vec<Result<String, ParmsError>> {
+        let mut lines = Vec::new();
+        let mut line: Option<Result<String, ParmsError>> = None;
+        for arg in &self.argv {
+            if let Some(command) = arg.strip_prefix('+') {
+                lines.extend(line.take());
+                line = Some(Ok(command.to_string()));
+            } else if arg.starts_with('-') {
+                lines.extend(line.take());
+            } else if let Some(Ok(ref mut text)) = line {
+                if arg.contains(&['"', '\n', '\r'][..]) {
+                    let command = text.split(' ').next().unwrap_or("");
+                    line = Some(Err(ParmsError::BadCommandArgument {
+                        command: command.to_string(),
+                        arg: arg.clone(),
+                    }));
+                    continue;
+                }
+                // Keep empty arguments, and arguments with spaces in them,
+                // as a single argument.
+                text.push(' ');
+                let quote = arg.is_empty()
+                    || arg.contains(|c: char| c <= ' ' || c == ';');
+                if quote {
+                    text.push('"');
+                    text.push_str(arg);
+                    text.push('"');
+                } else {
+                    text.push_str(arg);
+                }
+            }
+        }
+        lines.extend(line);
+        lines
+    }
+
+    /// Add the commands given on the command line to the command buffer.
+    ///
+    /// Quake does this at startup, after the config files have been
+    /// executed.
+    pub fn stuff_cmds(&self, cbuf: &mut Cbuf) {
+        cbuf.add(&self.commands());
+    }
+
     fn detect_features(&mut self) {
         if self.has("-rogue") {
             self.is_rogue = true;
@@ -133,7 +309,7 @@ impl Parms {
 
     /// The index of the command line parameter, if present.
     pub fn index(&self, parm: &str) -> Option<usize> {
-        self.argv.iter().position(|ref p| p.as_str() == parm)
+        self.argv.iter().position(|p| p.as_str() == parm)
     }
 
     /// Parse the value following the command line parameter, if present.
@@ -186,7 +362,7 @@ impl Parms {
                 match self.argv.get(value_idx) {
                     None => None,
                     Some(val) => {
-                        Some(&val)
+                        Some(val)
                     }
                 }
             }
@@ -231,6 +407,100 @@ impl Parms {
     }
 }
 
+/// Expand response files in `argv`, and add the arguments from `env_args`.
+///
+/// An argument of the form `@file` is replaced by the arguments in `file`,
+/// which is relative to `cwd`.  The arguments in a response file or in
+/// `env_args` are separated by whitespace, and can be quoted with `"` to
+/// include whitespace.  `@file` arguments inside a response file or
+/// `env_args` are not expanded.
+pub fn expand_args(argv: Vec<String>, cwd: &Path, env_args: Option<&str>)
+    -> Result<Vec<String>, ParmsError>
+{
+    let mut expanded = Vec::with_capacity(argv.len());
+    for arg in argv {
+        match arg.strip_prefix('@') {
+            Some(name) if !name.is_empty() => {
+                let path = cwd.join(name);
+                let text = fs::read_to_string(&path)
+                    .map_err(|error| ParmsError::ResponseFile { path, error })?;
+                expanded.extend(split_args(&text));
+            },
+            _ => expanded.push(arg),
+        }
+    }
+    if let Some(env_args) = env_args {
+        expanded.extend(split_args(env_args));
+    }
+    Ok(expanded)
+}
+
+/// Split text into arguments at whitespace, except inside `"` quotes.
+fn split_args(text: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut arg: Option<String> = None;
+    let mut in_quotes = false;
+    for c in text.chars() {
+        if c == '"' {
+            in_quotes = !in_quotes;
+            arg.get_or_insert_with(String::new);
+        } else if c.is_whitespace() && !in_quotes {
+            args.extend(arg.take());
+        } else {
+            arg.get_or_insert_with(String::new).push(c);
+        }
+    }
+    args.extend(arg);
+    args
+}
+
+/// The error type for building `Parms` from the command line.
+#[derive(Debug)]
+pub enum ParmsError {
+    /// A response file couldn't be read.
+    ResponseFile {
+        /// The path of the response file.
+        path: PathBuf,
+        /// What went wrong.
+        error: io::Error,
+    },
+    /// The `RQS_ARGS` environment variable isn't valid unicode.
+    EnvNotUnicode,
+    /// An argument to a `+command` contains a `"` or a line break, which
+    /// can't be quoted.
+    BadCommandArgument {
+        /// The name of the command.
+        command: String,
+        /// The argument.
+        arg: String,
+    },
+}
+
+impl fmt::Display for ParmsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParmsError::ResponseFile { ref path, ref error } =>
+                write!(f, "can't read response file {}: {}",
+                       path.display(), error),
+            ParmsError::EnvNotUnicode =>
+                write!(f, "{} is not valid unicode", ARGS_ENV_VAR),
+            ParmsError::BadCommandArgument { ref command, ref arg } =>
+                write!(f, "argument {:?} to +{} can't be quoted",
+                       arg, command),
+        }
+    }
+}
+
+impl error::Error for ParmsError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            ParmsError::ResponseFile { ref error, .. } => Some(error),
+            ParmsError::EnvNotUnicode
+            | ParmsError::BadCommandArgument { .. } => None,
+        }
+    }
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -243,10 +513,10 @@ mod tests {
             let p = Parms::new(
                 vec!(),
                 "cwd".into());
-            assert_eq!(p.is_dedicated(), false);
-            assert_eq!(p.is_standard_quake(), true);
-            assert_eq!(p.is_rogue(), false);
-            assert_eq!(p.is_hipnotic(), false);
+            assert!(!p.is_dedicated());
+            assert!(p.is_standard_quake());
+            assert!(!p.is_rogue());
+            assert!(!p.is_hipnotic());
         }
 
         // All.
@@ -256,10 +526,39 @@ mod tests {
                      "-rogue".into(),
                      "-hipnotic".into()),
                 "cwd".into());
-            assert_eq!(p.is_dedicated(), true);
-            assert_eq!(p.is_standard_quake(), false);
-            assert_eq!(p.is_rogue(), true);
-            assert_eq!(p.is_hipnotic(), true);
+            assert!(p.is_dedicated());
+            assert!(!p.is_standard_quake());
+            assert!(p.is_rogue());
+            assert!(p.is_hipnotic());
+        }
+    }
+
+    #[test]
+    fn memory_sizes() {
+        {
+            let p = Parms::new(vec!(), "cwd".into());
+            assert_eq!(p.heap_size().unwrap(), defs::DEFAULT_HEAP_SIZE);
+            assert_eq!(p.zone_size().unwrap(), defs::DEFAULT_ZONE_SIZE);
+        }
+
+        {
+            let p = Parms::new(
+                vec!("-heapsize".into(), "16384".into(),
+                     "-zone".into(), "512".into()),
+                "cwd".into());
+            assert_eq!(p.heap_size().unwrap(), 16 * 1024 * 1024);
+            assert_eq!(p.zone_size().unwrap(), 512 * 1024);
+        }
+
+        {
+            let huge = (usize::MAX / 1024 + 1).to_string();
+            let p = Parms::new(
+                vec!("-heapsize".into(), huge.clone(),
+                     "-zone".into(), huge.clone()),
+                "cwd".into());
+            assert_eq!(p.heap_size().unwrap_err().to_string(),
+                       format!("-heapsize {} is too large", huge));
+            assert!(p.zone_size().is_err());
         }
     }
 
@@ -270,11 +569,11 @@ mod tests {
                 vec!("zero".into(),
                      "-one".into()),
                 "cwd".into());
-            assert_eq!(p.has("zero"), true);
+            assert!(p.has("zero"));
             assert_eq!(p.index("zero"), Some(0));
-            assert_eq!(p.has("-one"), true);
+            assert!(p.has("-one"));
             assert_eq!(p.index("-one"), Some(1));
-            assert_eq!(p.has("nope"), false);
+            assert!(!p.has("nope"));
             assert_eq!(p.index("nope"), None);
         }
     }
@@ -367,4 +666,143 @@ mod tests {
             assert_eq!(values, &["a", "b"]);
         }
     }
+
+    #[test]
+    fn commands() {
+        let p = Parms::new(vec!(), "cwd".into());
+        assert_eq!(p.commands(), "");
+
+        let p = Parms::new(
+            vec!("-dedicated".into(),
+                 "+map".into(),
+                 "e1m1".into(),
+                 "+skill".into(),
+                 "3".into(),
+                 "-nosound".into(),
+                 "ignored".into(),
+                 "+hostname".into(),
+                 "my server".into(),
+                 "+listen".into()),
+            "cwd".into());
+        assert_eq!(p.commands(),
+                   "map e1m1\nskill 3\nhostname \"my server\"\nlisten\n");
+
+        let mut cbuf = Cbuf::new();
+        p.stuff_cmds(&mut cbuf);
+        assert_eq!(cbuf.next_line().unwrap(), "map e1m1");
+        assert_eq!(cbuf.next_line().unwrap(), "skill 3");
+    }
+
+    #[test]
+    fn bad_command_arguments() {
+        let p = Parms::new(
+            vec!("+say".into(), "a \"quoted\" word".into(),
+                 "+map".into(), "e1m1".into(),
+                 "+say".into(), "a\nquit x".into(),
+                 "+echo".into(), "a\rb".into(),
+                 "+skill".into(), "3".into()),
+            "cwd".into());
+        assert_eq!(p.commands(), "map e1m1\nskill 3\n");
+        let errors = p.bad_commands();
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[1].to_string(),
+                   r#"argument "a\nquit x" to +say can't be quoted"#);
+        match errors[2] {
+            ParmsError::BadCommandArgument { ref command, ref arg } => {
+                assert_eq!(command, "echo");
+                assert_eq!(arg, "a\rb");
+            },
+            ref other =>
+                panic!("expected a BadCommandArgument error, got {:?}", other),
+        }
+
+        // A line break can't sneak a command into the buffer.
+        let mut cbuf = Cbuf::new();
+        p.stuff_cmds(&mut cbuf);
+        assert_eq!(cbuf.next_line().unwrap(), "map e1m1");
+        assert_eq!(cbuf.next_line().unwrap(), "skill 3");
+        assert!(cbuf.is_empty());
+
+        // Parameters that aren't part of a command don't matter.
+        let p = Parms::new(
+            vec!("-name".into(), "\"\n".into(), "+map".into(), "e1m1".into()),
+            "cwd".into());
+        assert_eq!(p.commands(), "map e1m1\n");
+        assert!(p.bad_commands().is_empty());
+    }
+
+    #[test]
+    fn truncation() {
+        let argv: Vec<String> = (0..defs::MAX_NUM_ARGVS + 2)
+            .map(|i| i.to_string())
+            .collect();
+        let p = Parms::new(argv, "cwd".into());
+        assert!(p.has("49"));
+        assert!(!p.has("50"));
+        assert_eq!(p.dropped(), &["50", "51"]);
+
+        let p = Parms::new(vec!("-foo".into()), "cwd".into());
+        assert!(p.dropped().is_empty());
+    }
+
+    #[test]
+    fn response_files() {
+        let dir = ::tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("server.rsp"),
+                  "-dedicated 16\n  +map \"e1m1\"\r\n\
+                   \t+hostname \"my server\" \"\"\n")
+            .unwrap();
+
+        let argv = vec!("quake".to_string(), "@server.rsp".into(),
+                        "-port".into(), "26001".into());
+        let expanded = expand_args(argv, dir.path(),
+                                   Some(" -nosound  \"@not a file\""))
+            .unwrap();
+        assert_eq!(expanded,
+                   &["quake", "-dedicated", "16", "+map", "e1m1",
+                     "+hostname", "my server", "", "-port", "26001",
+                     "-nosound", "@not a file"]);
+
+        // A lone @ is left alone.
+        let expanded = expand_args(vec!("@".into()), dir.path(), None)
+            .unwrap();
+        assert_eq!(expanded, &["@"]);
+
+        match expand_args(vec!("@missing.rsp".into()), dir.path(), None) {
+            Err(ParmsError::ResponseFile { path, error }) => {
+                assert_eq!(path, dir.path().join("missing.rsp"));
+                assert_eq!(error.kind(), io::ErrorKind::NotFound);
+            },
+            other => panic!("expected a ResponseFile error, got {:?}", other),
+        }
+
+        let p = Parms::from_args_and_env(
+            vec!("@server.rsp".into()),
+            dir.path().to_string_lossy().to_string(),
+            Some("+skill 3"))
+            .unwrap();
+        assert!(p.is_dedicated());
+        assert_eq!(p.commands(),
+                   "map e1m1\nhostname \"my server\" \"\"\nskill 3\n");
+    }
+
+    #[test]
+    fn long_response_file() {
+        let dir = ::tempfile::tempdir().unwrap();
+        let args: Vec<String> = (0..defs::MAX_NUM_ARGVS * 2)
+            .map(|i| format!("-parm{}", i))
+            .collect();
+        fs::write(dir.path().join("long.rsp"), args.join("\n")).unwrap();
+
+        let p = Parms::from_args_and_env(
+            vec!("quake".into(), "@long.rsp".into(), "+map".into(),
+                 "e1m1".into()),
+            dir.path().to_string_lossy().to_string(),
+            None)
+            .unwrap();
+        assert!(p.dropped().is_empty());
+        assert!(args.iter().all(|arg| p.has(arg)));
+        assert_eq!(p.index("-parm99"), Some(100));
+        assert_eq!(p.commands(), "map e1m1\n");
+    }
 }
@@ -1,9 +1,20 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use tempfile::{self, TempDir};
 
 use defs;
+#[cfg(feature = "retail-data")]
+use fs::FileSys;
+use fs::pack::PackWriter;
+use parms::Parms;
 
 
 /// Get the base Quake directory (contains the quake executable).
+///
+/// Only available with the `retail-data` feature, and requires `QUAKE_DIR`
+/// to point at a full version of standard Quake 1.
+#[cfg(feature = "retail-data")]
 pub fn base_dir() -> PathBuf {
     use std::env;
     use std::env::VarError;
@@ -20,6 +31,7 @@ pub fn base_dir() -> PathBuf {
 }
 
 /// Get the game directory (basedir/GAMENAME).
+#[cfg(feature = "retail-data")]
 pub fn game_dir() -> PathBuf {
     let mut dir = base_dir();
     dir.push(defs::GAMENAME);
@@ -27,8 +39,273 @@ pub fn game_dir() -> PathBuf {
 }
 
 /// Get the path of the game's pak0.pak file.
+#[cfg(feature = "retail-data")]
 pub fn pak0_path() -> PathBuf {
     let mut p = game_dir();
     p.push("PAK0.PAK");
     p
 }
+
+/// A `FileSys` for the full version of standard Quake 1 at `base_dir()`.
+#[cfg(feature = "retail-data")]
+pub fn retail_fs() -> FileSys {
+    FileSys::new(&parms_for(&base_dir())).unwrap()
+}
+
+/// Make `Parms` that use the given base directory.
+pub fn parms_for(base_dir: &Path) -> Parms {
+    Parms::new(
+        vec!["-basedir".into(), base_dir.to_string_lossy().to_string()],
+        "cwd".into())
+}
+
+/// A synthetic Quake install in a temporary directory.
+///
+/// The directory is deleted when the `Fixture` is dropped.
+pub struct Fixture {
+    dir: TempDir,
+}
+
+impl Fixture {
+    /// The base Quake directory.
+    pub fn base_dir(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// The game directory (basedir/GAMENAME).
+    pub fn game_dir(&self) -> PathBuf {
+        self.dir.path().join(defs::GAMENAME)
+    }
+
+    /// The path of the game's pak0.pak file.
+    pub fn pak0_path(&self) -> PathBuf {
+        self.game_dir().join("pak0.pak")
+    }
+
+    /// `Parms` that use this fixture as the base directory.
+    pub fn parms(&self) -> Parms {
+        parms_for(self.base_dir())
+    }
+}
+
+/// Builds a `Fixture`.
+///
+/// Each call to `pak()` adds the next numbered .pak file to the game
+/// directory, and each call to `file()` adds a loose file.
+#[derive(Default)]
+pub struct FixtureBuilder {
+    paks: Vec<Vec<(String, Vec<u8>)>>,
+    files: Vec<(String, Vec<u8>)>,
+}
+
+impl FixtureBuilder {
+    /// Start with an empty game directory.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add a .pak file that contains the given files.
+    pub fn pak(mut self, files: Vec<(&str, Vec<u8>)>) -> Self {
+        self.paks.push(
+            files.into_iter().map(|(n, d)| (n.to_string(), d)).collect());
+        self
+    }
+
+    /// Add a loose file to the game directory.
+    pub fn file(mut self, name: &str, data: Vec<u8>) -> Self {
+        self.files.push((name.to_string(), data));
+        self
+    }
+
+    /// Write everything to a new temporary directory.
+    pub fn build(self) -> Fixture {
+        let fixture = Fixture { dir: tempfile::tempdir().unwrap() };
+        let game_dir = fixture.game_dir();
+        ::std::fs::create_dir_all(&game_dir).unwrap();
+
+        for (i, files) in self.paks.into_iter().enumerate() {
+            let mut writer = PackWriter::new();
+            for (name, data) in files {
+                writer.add_bytes(&name, data).unwrap();
+            }
+            writer.write(&game_dir.join(format!("pak{}.pak", i))).unwrap();
+        }
+
+        for (name, data) in self.files {
+            let path = game_dir.join(name);
+            ::std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            ::std::fs::write(path, data).unwrap();
+        }
+
+        fixture
+    }
+}
+
+/// A stand-in for retail Quake: `id1` containing `pak0.pak` and `pak1.pak`.
+///
+/// The first file in pak0 is `sound/items/r_item1.wav`, which is 6822 bytes
+/// and starts with `RIFF`.  pak0 also contains a `gfx.wad` with a `disc`
+/// qpic, a `palette` and the `conchars`.
+pub fn fixture() -> Fixture {
+    let mut wav = vec![0; 6822];
+    wav[0..4].copy_from_slice(b"RIFF");
+
+    let mut disc = vec![0; 8 + 24*24];
+    LittleEndian::write_i32(&mut disc[0..4], 24);
+    LittleEndian::write_i32(&mut disc[4..8], 24);
+    let palette: Vec<u8> = (0..768).map(|i| i as u8).collect();
+    let conchars = vec![0; 128*128];
+    let gfx_wad = wad_bytes(&[
+        ("disc", 66, 0, disc.len(), &disc),
+        ("palette", 64, 0, palette.len(), &palette),
+        ("conchars", 68, 0, conchars.len(), &conchars),
+    ]);
+
+    FixtureBuilder::new()
+        .pak(vec![
+            ("sound/items/r_item1.wav", wav),
+            ("gfx.wad", gfx_wad),
+            ("gfx/palette.lmp", palette.clone()),
+        ])
+        .pak(vec![
+            ("maps/e1m1.bsp", b"not really a map".to_vec()),
+        ])
+        .build()
+}
+
+/// Build a wad in memory, from (name, type, compression, size, data) lumps.
+///
+/// `size` is the uncompressed size of the lump; `data` is written as-is.
+pub fn wad_bytes(lumps: &[(&str, u8, u8, usize, &[u8])]) -> Vec<u8> {
+    const LUMP_INFO_SIZE: usize = 32;
+
+    let mut data = vec![0; 12];
+    data[0..4].copy_from_slice(b"WAD2");
+    LittleEndian::write_i32(&mut data[4..8], lumps.len() as i32);
+
+    let mut positions = Vec::new();
+    for &(_, _, _, _, lump_data) in lumps {
+        positions.push(data.len());
+        data.extend_from_slice(lump_data);
+    }
+
+    let table_offset = data.len();
+    LittleEndian::write_i32(&mut data[8..12], table_offset as i32);
+    for (&(name, lump_type, compression, size, lump_data), pos) in
+        lumps.iter().zip(positions)
+    {
+        let mut info = [0; LUMP_INFO_SIZE];
+        LittleEndian::write_i32(&mut info[0..4], pos as i32);
+        LittleEndian::write_i32(&mut info[4..8], lump_data.len() as i32);
+        LittleEndian::write_i32(&mut info[8..12], size as i32);
+        info[12] = lump_type;
+        info[13] = compression;
+        info[16..16+name.len()].copy_from_slice(name.as_bytes());
+        data.extend_from_slice(&info);
+    }
+    data
+}
+
+/// Build a zip (.pk3) in memory, from (name, data, deflate) entries.
+///
+/// Names that end in `/` are directories.  Entries are deflated if `deflate`
+/// is true, and stored otherwise.
+pub fn zip_bytes(entries: &[(&str, Vec<u8>, bool)]) -> Vec<u8> {
+    use std::io::Write;
+    use byteorder::WriteBytesExt;
+    use flate2::{Compression, Crc};
+    use flate2::write::DeflateEncoder;
+
+    type LE = LittleEndian;
+
+    let mut data = Vec::new();
+    let mut central_dir = Vec::new();
+    for &(name, ref contents, deflate) in entries {
+        let (method, stored) = match deflate {
+            true => {
+                let mut encoder =
+                    DeflateEncoder::new(Vec::new(), Compression::best());
+                encoder.write_all(contents).unwrap();
+                (8, encoder.finish().unwrap())
+            },
+            false => (0, contents.clone()),
+        };
+        let mut crc = Crc::new();
+        crc.update(contents);
+
+        // The fields that the local header and central directory share.
+        let mut common = Vec::new();
+        common.write_u16::<LE>(20).unwrap();  // Version needed to extract.
+        common.write_u16::<LE>(0).unwrap();  // Flags.
+        common.write_u16::<LE>(method).unwrap();
+        common.write_u32::<LE>(0).unwrap();  // Modification time and date.
+        common.write_u32::<LE>(crc.sum()).unwrap();
+        common.write_u32::<LE>(stored.len() as u32).unwrap();
+        common.write_u32::<LE>(contents.len() as u32).unwrap();
+        common.write_u16::<LE>(name.len() as u16).unwrap();
+        common.write_u16::<LE>(0).unwrap();  // Extra field length.
+
+        central_dir.extend_from_slice(b"PK\x01\x02");
+        central_dir.write_u16::<LE>(20).unwrap();  // Version made by.
+        central_dir.extend_from_slice(&common);
+        central_dir.write_u16::<LE>(0).unwrap();  // Comment length.
+        central_dir.write_u16::<LE>(0).unwrap();  // Disk number.
+        central_dir.write_u16::<LE>(0).unwrap();  // Internal attributes.
+        central_dir.write_u32::<LE>(0).unwrap();  // External attributes.
+        central_dir.write_u32::<LE>(data.len() as u32).unwrap();
+        central_dir.extend_from_slice(name.as_bytes());
+
+        data.extend_from_slice(b"PK\x03\x04");
+        data.extend_from_slice(&common);
+        data.extend_from_slice(name.as_bytes());
+        data.extend_from_slice(&stored);
+    }
+
+    let central_dir_offset = data.len();
+    data.extend_from_slice(&central_dir);
+    data.extend_from_slice(b"PK\x05\x06");
+    data.write_u16::<LE>(0).unwrap();  // Disk number.
+    data.write_u16::<LE>(0).unwrap();  // Disk with the central directory.
+    data.write_u16::<LE>(entries.len() as u16).unwrap();
+    data.write_u16::<LE>(entries.len() as u16).unwrap();
+    data.write_u32::<LE>(central_dir.len() as u32).unwrap();
+    data.write_u32::<LE>(central_dir_offset as u32).unwrap();
+    data.write_u16::<LE>(0).unwrap();  // Comment length.
+    data
+}
+
+/// Build a BSP in memory, from the data of each lump in header order.
+pub fn bsp_bytes(version: i32, lumps: &[Vec<u8>]) -> Vec<u8> {
+    let header_size = 4 + lumps.len() * 8;
+    let mut data = vec![0; header_size];
+    LittleEndian::write_i32(&mut data[0..4], version);
+    for (i, lump) in lumps.iter().enumerate() {
+        let at = 4 + i*8;
+        let offset = data.len() as i32;
+        LittleEndian::write_i32(&mut data[at..at+4], offset);
+        LittleEndian::write_i32(&mut data[at+4..at+8], lump.len() as i32);
+        data.extend_from_slice(lump);
+    }
+    data
+}
+
+/// Append little-endian `i16`s to `data`.
+pub fn write_shorts(data: &mut Vec<u8>, v: &[i16]) {
+    for &x in v {
+        data.write_i16::<LittleEndian>(x).unwrap();
+    }
+}
+
+/// Append little-endian `i32`s to `data`.
+pub fn write_ints(data: &mut Vec<u8>, v: &[i32]) {
+    for &x in v {
+        data.write_i32::<LittleEndian>(x).unwrap();
+    }
+}
+
+/// Append little-endian `f32`s to `data`.
+pub fn write_floats(data: &mut Vec<u8>, v: &[f32]) {
+    for &x in v {
+        data.write_f32::<LittleEndian>(x).unwrap();
+    }
+}
@@ -1,8 +1,7 @@
 //! Misc utility functions.
 
-use std::ffi::CStr;
-
-use failure::Error;
+use std::error::Error;
+use std::fmt;
 
 
 /// Try to read a `String` from a `u8` buffer that contains a C-style string.
@@ -15,16 +14,164 @@ use failure::Error;
 ///
 /// This function will attempt the conversion, but ignore all data after the
 /// first nul byte.
-pub fn cstr_buf_to_string(buf: &[u8]) -> Result<String, Error> {
-    // Attempt to find the subset of the buffer that contains a single nul byte.
-    // If there is no nul byte, use the entire buffer and let the error be
-    // handled by `CStr::from_bytes_with_nul()`.
-    let cstr_slice = match buf.iter().position(|c| c == &b'\0') {
-        Some(i) => 0..(i+1),
-        None => 0..buf.len()
+pub fn cstr_buf_to_string(buf: &[u8]) -> Result<String, CStrError> {
+    let len = buf.iter().position(|c| c == &b'\0')
+        .ok_or(CStrError::NoNul)?;
+    ::std::str::from_utf8(&buf[..len])
+        .map(|s| s.to_string())
+        .map_err(|e| CStrError::NotUtf8 { valid_up_to: e.valid_up_to() })
+}
+
+/// The error type returned by `cstr_buf_to_string()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CStrError {
+    /// The buffer doesn't contain a nul terminator.
+    NoNul,
+    /// The string isn't valid UTF-8.
+    NotUtf8 {
+        /// The length of the valid prefix of the string.
+        valid_up_to: usize,
+    },
+}
+
+impl fmt::Display for CStrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CStrError::NoNul =>
+                write!(f, "string has no nul terminator"),
+            CStrError::NotUtf8 { valid_up_to } =>
+                write!(f, "string is not valid UTF-8 after byte {}",
+                       valid_up_to),
+        }
+    }
+}
+
+impl Error for CStrError {}
+
+/// Read a `String` from a `u8` buffer that contains a C-style string, for
+/// fields where a bad string shouldn't stop anything from loading.
+///
+/// Unlike `cstr_buf_to_string()`, the string may fill the whole buffer with
+/// no nul terminator, and bytes that aren't valid UTF-8 are replaced with
+/// U+FFFD.
+pub fn cstr_buf_to_string_lossy(buf: &[u8]) -> String {
+    let len = buf.iter().position(|c| c == &b'\0').unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+/// Does `name` match the glob `pattern`?
+///
+/// `*` matches any number of characters, and `?` matches exactly one
+/// character.  Neither will match a `/`, so that a pattern only matches names
+/// within a single directory.  All other characters must match exactly.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    // Since nothing but a `/` matches a `/`, each directory can be matched on
+    // its own.
+    let mut patterns = pattern.split('/');
+    let mut names = name.split('/');
+    loop {
+        match (patterns.next(), names.next()) {
+            (None, None) => return true,
+            (Some(p), Some(n)) if glob_match_part(p, n) => (),
+            _ => return false,
+        }
+    }
+}
+
+/// Match part of a glob with no `/` in it.
+///
+/// When a match fails after a `*`, we only need to retry from the most
+/// recent `*`, letting it take one more character.  Earlier `*`s never need
+/// to change, since the most recent one can already take anything they
+/// could.  This keeps the time to at most the length of the pattern times
+/// the length of the name, rather than exponential in the number of `*`s.
+fn glob_match_part(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // The pattern index after the last `*`, and the name index it's retried
+    // from.
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(&'*') => {
+                star = Some((p + 1, n));
+                p += 1;
+            },
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            },
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p;
+                    n = star_n + 1;
+                },
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Parse a number the way Quake's `Q_atof` does.
+///
+/// Accepts an optional `-`, followed by a hex number starting with `0x`, a
+/// character in single quotes (giving its code), or a decimal number with an
+/// optional `.`.  Parsing stops at the first unexpected character, and
+/// anything unparseable is 0.
+pub fn atof(s: &str) -> f32 {
+    let (sign, s) = match s.strip_prefix('-') {
+        Some(rest) => (-1.0, rest),
+        None => (1.0, s),
     };
-    let cstr = CStr::from_bytes_with_nul(&buf[cstr_slice])?;
-    Ok(cstr.to_str()?.to_string())
+    let bytes = s.as_bytes();
+
+    if bytes.starts_with(b"0x") || bytes.starts_with(b"0X") {
+        let mut val = 0.0f64;
+        for &c in &bytes[2..] {
+            match (c as char).to_digit(16) {
+                Some(d) => val = val * 16.0 + f64::from(d),
+                None => break,
+            }
+        }
+        return (sign * val) as f32;
+    }
+
+    if bytes.first() == Some(&b'\'') {
+        let val = s[1..].chars().next().map_or(0, |c| c as u32);
+        return (sign * f64::from(val)) as f32;
+    }
+
+    let mut val = 0.0f64;
+    let mut scale = 1.0f64;
+    let mut seen_point = false;
+    for &c in bytes {
+        if c == b'.' {
+            seen_point = true;
+        } else if c.is_ascii_digit() {
+            val = val * 10.0 + f64::from(c - b'0');
+            if seen_point {
+                scale *= 10.0;
+            }
+        } else {
+            break;
+        }
+    }
+    (sign * val / scale) as f32
+}
+
+/// Truncate `s` to at most `max_len` bytes, without splitting a character.
+pub fn truncate_str(s: &mut String, max_len: usize) {
+    if s.len() > max_len {
+        let mut len = max_len;
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        s.truncate(len);
+    }
 }
 
 
@@ -79,5 +226,79 @@ mod tests {
                 Ok(s) => panic!("expected an error, got '{}'", s),
             }
         }
+
+        assert_eq!(cstr_buf_to_string(b"abc"), Err(CStrError::NoNul));
+    }
+
+    #[test]
+    fn cstr_buf_not_utf8() {
+        assert_eq!(cstr_buf_to_string(b"ab\xff\0"),
+                   Err(CStrError::NotUtf8 { valid_up_to: 2 }));
+        // Junk after the terminator doesn't matter.
+        assert_eq!(cstr_buf_to_string(b"ab\0\xff").unwrap(), "ab");
+    }
+
+    #[test]
+    fn cstr_buf_lossy() {
+        assert_eq!(cstr_buf_to_string_lossy(b"abc\0\xff"), "abc");
+        assert_eq!(cstr_buf_to_string_lossy(b"abc"), "abc");
+        assert_eq!(cstr_buf_to_string_lossy(b"a\xffb\0"), "a\u{fffd}b");
+        assert_eq!(cstr_buf_to_string_lossy(b""), "");
+    }
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("", ""));
+        assert!(glob_match("abc", "abc"));
+        assert!(!glob_match("abc", "abcd"));
+        assert!(glob_match("*", "abc"));
+        assert!(glob_match("a*c", "ac"));
+        assert!(glob_match("a*c", "abbbc"));
+        assert!(!glob_match("a*c", "abbb"));
+        assert!(glob_match("a?c", "abc"));
+        assert!(!glob_match("a?c", "ac"));
+        assert!(glob_match("maps/*.bsp", "maps/e1m1.bsp"));
+        assert!(!glob_match("*.bsp", "maps/e1m1.bsp"));
+        assert!(!glob_match("maps?e1m1.bsp", "maps/e1m1.bsp"));
+        assert!(glob_match("*/*", "maps/e1m1.bsp"));
+        assert!(!glob_match("*", "maps/e1m1.bsp"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(glob_match("**", ""));
+        assert!(glob_match("*?", "a"));
+        assert!(!glob_match("*?", ""));
+    }
+
+    /// Patterns with many `*`s don't take exponential time.
+    #[test]
+    fn glob_many_stars() {
+        let name = "a".repeat(100);
+        assert!(!glob_match("*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*b", &name));
+        assert!(glob_match("*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*", &name));
+    }
+
+    #[test]
+    fn atof_values() {
+        assert_eq!(atof("0"), 0.0);
+        assert_eq!(atof("800"), 800.0);
+        assert_eq!(atof("-3.5"), -3.5);
+        assert_eq!(atof("0.25"), 0.25);
+        assert_eq!(atof(".5"), 0.5);
+        assert_eq!(atof("0x1F"), 31.0);
+        assert_eq!(atof("-0x10"), -16.0);
+        assert_eq!(atof("'A"), 65.0);
+        assert_eq!(atof("12abc"), 12.0);
+        assert_eq!(atof("abc"), 0.0);
+        assert_eq!(atof(""), 0.0);
+    }
+
+    #[test]
+    fn truncate() {
+        let mut s = "héllo".to_string();
+        truncate_str(&mut s, 10);
+        assert_eq!(s, "héllo");
+        truncate_str(&mut s, 2);
+        assert_eq!(s, "h");
+        truncate_str(&mut s, 0);
+        assert_eq!(s, "");
     }
 }
@@ -0,0 +1,261 @@
+// Copyright (C) 2018 Adrian Chan
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+//
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 59 Temple Place - Suite 330, Boston, MA  02111-1307, USA.
+
+//! Typed versions of the data stored in wad lumps.
+
+use std::fmt;
+use std::ops::Range;
+
+use byteorder::{ByteOrder, LittleEndian};
+use failure::Error;
+
+use try_from_temp::TryFromTemp;
+use util;
+
+
+/// A picture, made up of 8-bit palette indices.
+///
+/// This is the `LumpType::Qpic` layout: a width and height, followed by the
+/// pixels in row order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QPic {
+    /// Width of the picture, in pixels.
+    pub width: usize,
+    /// Height of the picture, in pixels.
+    pub height: usize,
+    /// `width * height` palette indices, one row after another.
+    pub pixels: Vec<u8>,
+}
+
+/// Width and height of the `conchars` picture.
+pub const CONCHARS_SIZE: usize = 128;
+
+impl QPic {
+    /// Parse a `QPic` from the raw lump data.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Error> {
+        const HEADER_SIZE: usize = 4+4;
+        const WIDTH_SLICE: Range<usize> = 0..4;
+        const HEIGHT_SLICE: Range<usize> = 4..8;
+
+        if data.len() < HEADER_SIZE {
+            bail!("Invalid qpic: too short for header");
+        }
+        let width = usize::try_from_temp(
+            LittleEndian::read_i32(&data[WIDTH_SLICE]))?;
+        let height = usize::try_from_temp(
+            LittleEndian::read_i32(&data[HEIGHT_SLICE]))?;
+        let num_pixels = width.checked_mul(height)
+            .ok_or_else(|| format_err!("Invalid qpic: {}x{} is too large",
+                                       width, height))?;
+        if data.len() - HEADER_SIZE != num_pixels {
+            bail!("Invalid qpic: {}x{} needs {} bytes of pixels, got {}",
+                  width, height, num_pixels, data.len() - HEADER_SIZE);
+        }
+
+        Ok(Self {
+            width,
+            height,
+            pixels: data[HEADER_SIZE..].to_vec(),
+        })
+    }
+
+    /// Make a `QPic` from the `conchars` lump.
+    ///
+    /// The console characters are a special case: the lump has no header,
+    /// and is just a 128x128 block of pixels holding 16x16 characters of 8x8
+    /// pixels each.  Colour 0 is used for transparent pixels.
+    pub fn from_conchars(data: &[u8]) -> Result<Self, Error> {
+        if data.len() != CONCHARS_SIZE * CONCHARS_SIZE {
+            bail!("Invalid conchars: expected {} bytes, got {}",
+                  CONCHARS_SIZE * CONCHARS_SIZE, data.len());
+        }
+        Ok(Self {
+            width: CONCHARS_SIZE,
+            height: CONCHARS_SIZE,
+            pixels: data.to_vec(),
+        })
+    }
+}
+
+/// A 256 colour palette.
+#[derive(Clone)]
+pub struct Palette {
+    rgb: [[u8; 3]; 256],
+}
+
+/// Size of a `Palette` on disk, in bytes.
+pub const PALETTE_SIZE: usize = 256*3;
+
+impl Palette {
+    /// Parse a `Palette` from 768 bytes of RGB triplets.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Error> {
+        if data.len() != PALETTE_SIZE {
+            bail!("Invalid palette: expected {} bytes, got {}",
+                  PALETTE_SIZE, data.len());
+        }
+        let mut rgb = [[0; 3]; 256];
+        for (colour, bytes) in rgb.iter_mut().zip(data.chunks(3)) {
+            colour.copy_from_slice(bytes);
+        }
+        Ok(Self { rgb })
+    }
+
+    /// The RGB value of the given palette index.
+    pub fn rgb(&self, index: u8) -> [u8; 3] {
+        self.rgb[index as usize]
+    }
+
+    /// All 256 colours, in palette order.
+    pub fn colours(&self) -> &[[u8; 3]; 256] {
+        &self.rgb
+    }
+}
+
+impl fmt::Debug for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Palette {{ .. }}")
+    }
+}
+
+/// A texture, with four mip levels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MipTex {
+    /// Name of the texture.
+    pub name: String,
+    /// Width of the full size texture, in pixels.
+    pub width: usize,
+    /// Height of the full size texture, in pixels.
+    pub height: usize,
+    /// The pixels of each mip level.  Level 0 is full size, and each level
+    /// after that is half the width and height of the previous one.
+    pub mips: [Vec<u8>; MIP_LEVELS],
+}
+
+/// The number of mip levels stored in a `MipTex`.
+pub const MIP_LEVELS: usize = 4;
+
+impl MipTex {
+    /// Parse a `MipTex` from the raw lump data.
+    ///
+    /// The mip level offsets are relative to the start of `data`.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Error> {
+        const HEADER_SIZE: usize = 16+4+4+4*MIP_LEVELS;
+        const NAME_SLICE: Range<usize> = 0..16;
+        const WIDTH_SLICE: Range<usize> = 16..20;
+        const HEIGHT_SLICE: Range<usize> = 20..24;
+        const OFFSETS_START: usize = 24;
+
+        if data.len() < HEADER_SIZE {
+            bail!("Invalid miptex: too short for header");
+        }
+        let name = util::cstr_buf_to_string(&data[NAME_SLICE])?;
+        let width = usize::try_from_temp(
+            LittleEndian::read_i32(&data[WIDTH_SLICE]))?;
+        let height = usize::try_from_temp(
+            LittleEndian::read_i32(&data[HEIGHT_SLICE]))?;
+        if width == 0 || height == 0
+            || !width.is_multiple_of(8)
+            || !height.is_multiple_of(8) {
+            bail!("Invalid miptex {}: size {}x{} is not a multiple of 8",
+                  name, width, height);
+        }
+        // The mip levels are smaller than this, so their sizes can't
+        // overflow either.
+        width.checked_mul(height)
+            .ok_or_else(|| format_err!("Invalid miptex {}: {}x{} is too large",
+                                       name, width, height))?;
+
+        let mut mips: [Vec<u8>; MIP_LEVELS] = Default::default();
+        for (level, mip) in mips.iter_mut().enumerate() {
+            let start = OFFSETS_START + level*4;
+            let offset = LittleEndian::read_u32(&data[start..start+4]) as usize;
+            let size = (width >> level) * (height >> level);
+            if offset.checked_add(size).is_none_or(|end| end > data.len()) {
+                bail!("Invalid miptex {}: mip level {} is out of bounds",
+                      name, level);
+            }
+            *mip = data[offset..offset+size].to_vec();
+        }
+
+        Ok(Self {
+            name,
+            width,
+            height,
+            mips,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn qpic() {
+        let data = [2, 0, 0, 0, 3, 0, 0, 0, 1, 2, 3, 4, 5, 6];
+        let pic = QPic::from_bytes(&data).unwrap();
+        assert_eq!(pic.width, 2);
+        assert_eq!(pic.height, 3);
+        assert_eq!(pic.pixels, &[1, 2, 3, 4, 5, 6]);
+
+        assert!(QPic::from_bytes(&data[..13]).is_err());
+        assert!(QPic::from_bytes(&data[..4]).is_err());
+    }
+
+    #[test]
+    fn palette() {
+        let data: Vec<u8> = (0..PALETTE_SIZE).map(|i| i as u8).collect();
+        let palette = Palette::from_bytes(&data).unwrap();
+        assert_eq!(palette.rgb(0), [0, 1, 2]);
+        assert_eq!(palette.rgb(255), [253, 254, 255]);
+
+        assert!(Palette::from_bytes(&data[..767]).is_err());
+    }
+
+    #[test]
+    fn miptex() {
+        let mut data = vec![0; 40];
+        data[0..4].copy_from_slice(b"wall");
+        LittleEndian::write_u32(&mut data[16..20], 16);
+        LittleEndian::write_u32(&mut data[20..24], 8);
+        let mut offset = data.len();
+        for level in 0..MIP_LEVELS {
+            let start = 24 + level*4;
+            LittleEndian::write_u32(&mut data[start..start+4], offset as u32);
+            let size = (16 >> level) * (8 >> level);
+            data.extend(vec![level as u8; size]);
+            offset += size;
+        }
+
+        let tex = MipTex::from_bytes(&data).unwrap();
+        assert_eq!(tex.name, "wall");
+        assert_eq!((tex.width, tex.height), (16, 8));
+        assert_eq!(tex.mips[0], vec![0; 128]);
+        assert_eq!(tex.mips[3], vec![3; 2]);
+
+        assert!(MipTex::from_bytes(&data[..data.len()-1]).is_err());
+
+        // Sizes are signed, and must fit in the lump.
+        let mut bad = data.clone();
+        LittleEndian::write_i32(&mut bad[16..20], -8);
+        assert!(MipTex::from_bytes(&bad).is_err());
+        LittleEndian::write_i32(&mut bad[16..20], 0x7fff_fff8);
+        LittleEndian::write_i32(&mut bad[20..24], 0x7fff_fff8);
+        assert!(MipTex::from_bytes(&bad).is_err());
+    }
+}
@@ -0,0 +1,144 @@
+// Copyright (C) 2018 Adrian Chan
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+//
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 59 Temple Place - Suite 330, Boston, MA  02111-1307, USA.
+
+//! LZSS decompression, for wad lumps that use `Compression::Lzss`.
+//!
+//! Quake itself never decompresses wad lumps, but the wad format allows for
+//! it and some third-party tools produce compressed lumps.  These use the
+//! classic Okumura LZSS scheme:
+//!
+//! * A 4096 byte ring buffer, initially filled with spaces.  Writing starts at
+//!   position `RING_SIZE - MAX_MATCH`.
+//! * A flag byte precedes every group of 8 items, and is consumed LSB first.
+//!   A set bit means the item is a single literal byte.  A clear bit means the
+//!   item is a 2 byte reference into the ring buffer.
+//! * A reference holds a 12 bit ring position and a 4 bit length.  The real
+//!   length is the stored length plus `THRESHOLD + 1`.
+
+use failure::Error;
+
+
+/// Size of the ring buffer.
+const RING_SIZE: usize = 4096;
+/// The longest match that a reference can encode.
+const MAX_MATCH: usize = 18;
+/// Matches of this length or shorter are stored as literals.
+const THRESHOLD: usize = 2;
+
+/// Decompress LZSS `data`, which should expand to exactly `size` bytes.
+///
+/// It is an error if the compressed data ends before `size` bytes have been
+/// produced, or if it would produce more than `size` bytes.
+pub fn decompress(data: &[u8], size: usize) -> Result<Vec<u8>, Error> {
+    // Don't trust `size` too far: each input byte can expand to at most
+    // `MAX_MATCH` output bytes.
+    let mut out = Vec::with_capacity(size.min(data.len() * MAX_MATCH));
+    let mut ring = [b' '; RING_SIZE];
+    let mut r = RING_SIZE - MAX_MATCH;
+    let mut input = data.iter();
+
+    // Holds the current flag byte in the low 8 bits.  The high bits count how
+    // many flags remain; when they run out we need a new flag byte.
+    let mut flags: u32 = 0;
+
+    while out.len() < size {
+        flags >>= 1;
+        if flags & 0x100 == 0 {
+            match input.next() {
+                Some(&b) => flags = u32::from(b) | 0xff00,
+                None => break,
+            }
+        }
+
+        if flags & 1 == 1 {
+            // Literal byte.
+            let c = match input.next() {
+                Some(&c) => c,
+                None => break,
+            };
+            out.push(c);
+            ring[r] = c;
+            r = (r + 1) & (RING_SIZE - 1);
+        } else {
+            // Reference to earlier data in the ring buffer.
+            let (lo, hi) = match (input.next(), input.next()) {
+                (Some(&lo), Some(&hi)) => (lo as usize, hi as usize),
+                _ => bail!("LZSS data truncated in the middle of a reference"),
+            };
+            let pos = lo | ((hi & 0xf0) << 4);
+            let len = (hi & 0x0f) + THRESHOLD + 1;
+            if out.len() + len > size {
+                bail!("LZSS data expands past the expected size of {} bytes",
+                      size);
+            }
+            for k in 0..len {
+                let c = ring[(pos + k) & (RING_SIZE - 1)];
+                out.push(c);
+                ring[r] = c;
+                r = (r + 1) & (RING_SIZE - 1);
+            }
+        }
+    }
+
+    if out.len() != size {
+        bail!("LZSS data expands to {} bytes, expected {}", out.len(), size);
+    }
+    Ok(out)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literals_only() {
+        let data = [0xff, b'a', b'b', b'c', b'd', b'e', b'f', b'g', b'h',
+                    0x01, b'i'];
+        let out = decompress(&data, 9).unwrap();
+        assert_eq!(out, b"abcdefghi");
+    }
+
+    #[test]
+    fn overlapping_reference() {
+        // Three literals, then a reference to the first literal (ring
+        // position 4078) with a length of 6.
+        let data = [0x07, b'a', b'b', b'c', 0xee, 0xf3];
+        let out = decompress(&data, 9).unwrap();
+        assert_eq!(out, b"abcabcabc");
+    }
+
+    #[test]
+    fn reference_into_initial_spaces() {
+        let data = [0x00, 0x00, 0x00];
+        let out = decompress(&data, 3).unwrap();
+        assert_eq!(out, b"   ");
+    }
+
+    #[test]
+    fn wrong_size() {
+        let data = [0x07, b'a', b'b', b'c', 0xee, 0xf3];
+        assert!(decompress(&data, 10).is_err());
+        assert!(decompress(&data, 8).is_err());
+    }
+
+    #[test]
+    fn truncated_reference() {
+        let data = [0x07, b'a', b'b', b'c', 0xee];
+        assert!(decompress(&data, 9).is_err());
+    }
+}
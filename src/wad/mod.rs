@@ -26,14 +26,20 @@
 //! This metadata includes a lump's name, what kind of data it contains (see
 //! `LumpType`), whether the data is compressed, etc.
 
+pub mod lumps;
+pub mod lzss;
+pub use self::lumps::{MipTex, Palette, QPic};
+
+use std::borrow::Cow;
+use std::error;
+use std::fmt;
 use std::ops::Range;
 
 use byteorder::{ByteOrder, LittleEndian};
 use failure::Error;
 
 use fs::FileSys;
-use try_from_temp::TryFromTemp;
-use util;
+use util::{self, CStrError};
 
 
 /// A wad file loaded into memory.
@@ -46,14 +52,40 @@ pub struct Wad {
 
 impl Wad {
     /// Load a wad file from the filesystem.
-    pub fn load_from_file(file_name: &str, fs: &mut FileSys)
+    ///
+    /// A missing file gives `WadError::NotFound`, and a corrupt one gives
+    /// `WadError::Invalid`, both with the file name.
+    pub fn load_from_file(file_name: &str, fs: &FileSys)
         -> Result<Self, Error>
     {
-        let mut data =
+        let data =
             fs.load_file(file_name)?
             .ok_or_else(
-                || format_err!("no such file {}", file_name))?;
+                || WadError::NotFound { path: file_name.to_string() })?;
+        Self::from_bytes(data)
+            .map_err(|error| WadError::Invalid {
+                path: file_name.to_string(),
+                error: Box::new(error),
+            }.into())
+    }
+
+    /// Parse a wad file that has already been loaded into memory.
+    ///
+    /// The lump table and the data of every lump are checked against the
+    /// size of the wad, so a truncated or corrupt wad gives an error rather
+    /// than a panic later on.
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, WadError> {
         let info = WadInfo::from(&data)?;
+
+        let table_len = info.num_lumps.checked_mul(LUMP_INFO_SIZE);
+        let table_end = table_len
+            .and_then(|len| info.lump_table_offset.checked_add(len));
+        if table_end.is_none_or(|end| end > data.len()) {
+            return Err(WadError::OutOfBounds {
+                at: info.lump_table_offset,
+                len: table_len.unwrap_or(usize::MAX),
+            });
+        }
         let mut lumps = Vec::with_capacity(info.num_lumps);
 
         let make_lump_info_slice = |i| {
@@ -63,8 +95,16 @@ impl Wad {
         };
 
         for i in 0..info.num_lumps {
-            let mut lump_info_buf = &mut data[make_lump_info_slice(i)];
-            let lump_info = LumpInfo::from(lump_info_buf)?;
+            let lump_info_slice = make_lump_info_slice(i);
+            let at = lump_info_slice.start;
+            let lump_info = LumpInfo::from(&data[lump_info_slice], at)?;
+            let lump_end = lump_info.file_pos.checked_add(lump_info.disk_size);
+            if lump_end.is_none_or(|end| end > data.len()) {
+                return Err(WadError::OutOfBounds {
+                    at: lump_info.file_pos,
+                    len: lump_info.disk_size,
+                });
+            }
             // We won't bother to lowercase the names, since we'll just use
             // `eq_ignore_ascii_case()` when making the comparisons.
             // And we won't implement `SwapPic` either -- we'll interpret the
@@ -80,32 +120,105 @@ impl Wad {
         })
     }
 
+    /// Information about each lump in the wad, in the order they are
+    /// stored.
+    pub fn lump_infos(&self) -> &[LumpInfo] {
+        &self.lumps
+    }
+
     /// Try to get information about the lump with the given name.
-    pub fn lump_info(&self, name: &str) -> Result<&LumpInfo, Error> {
+    pub fn lump_info(&self, name: &str) -> Result<&LumpInfo, WadError> {
         self.lumps.iter()
             .find(|l| l.name.eq_ignore_ascii_case(name))
             .ok_or_else(
-                || format_err!("No lump named {}", name))
+                || WadError::NoSuchLump(name.to_string()))
     }
 
     /// Try to get the data from the lump with the given name.
     ///
-    /// **Warning:** this does not uncompress the data.
-    pub fn data_for_lump_named(&self, name: &str) -> Result<&[u8], Error> {
+    /// **Warning:** this does not uncompress the data.  Use
+    /// `decompressed_lump()` if the lump may be compressed.
+    pub fn data_for_lump_named(&self, name: &str) -> Result<&[u8], WadError> {
         let lump = self.lump_info(name)?;
         Ok(&self.data[lump.file_pos..lump.file_pos+lump.disk_size])
     }
 
     /// Try to get the data from the lump with the given number.
     ///
-    /// **Warning:** this does not uncompress the data.
-    pub fn data_for_lump_num(&self, n: usize) -> Result<&[u8], Error> {
-        if n > self.info.num_lumps {
-            bail!("Bad lump number: {}", n);
+    /// **Warning:** this does not uncompress the data.  Use
+    /// `decompressed_lump()` if the lump may be compressed.
+    pub fn data_for_lump_num(&self, n: usize) -> Result<&[u8], WadError> {
+        if n >= self.info.num_lumps {
+            return Err(WadError::BadLumpNumber(n));
         }
         let lump = &self.lumps[n];
         Ok(&self.data[lump.file_pos..lump.file_pos+lump.disk_size])
     }
+
+    /// Try to get the data from the lump with the given name, decompressing
+    /// it if necessary.
+    ///
+    /// The result is always `LumpInfo::size()` bytes long.  Uncompressed lumps
+    /// are borrowed from the wad, compressed lumps are decompressed into a new
+    /// buffer.
+    pub fn decompressed_lump(&self, name: &str) -> Result<Cow<'_, [u8]>, Error> {
+        let lump = self.lump_info(name)?;
+        let data = self.data_for_lump_named(name)?;
+        match lump.compression {
+            Compression::None => {
+                if lump.disk_size != lump.size {
+                    return Err(WadError::SizeMismatch {
+                        name: lump.name.clone(),
+                        disk_size: lump.disk_size,
+                        size: lump.size,
+                    }.into());
+                }
+                Ok(Cow::Borrowed(data))
+            },
+            Compression::Lzss => {
+                Ok(Cow::Owned(lzss::decompress(data, lump.size)?))
+            },
+        }
+    }
+
+    /// Get the decompressed data for the named lump, after checking that it
+    /// has the expected type.
+    fn typed_lump(&self, name: &str, expected: LumpType)
+        -> Result<Cow<'_, [u8]>, Error>
+    {
+        let lump = self.lump_info(name)?;
+        if lump.lump_type != expected {
+            return Err(WadError::WrongLumpType {
+                name: lump.name.clone(),
+                expected,
+                found: lump.lump_type,
+            }.into());
+        }
+        self.decompressed_lump(name)
+    }
+
+    /// Decode the named `LumpType::Qpic` lump.
+    pub fn qpic(&self, name: &str) -> Result<QPic, Error> {
+        QPic::from_bytes(&self.typed_lump(name, LumpType::Qpic)?)
+    }
+
+    /// Decode the named `LumpType::Palette` lump.
+    pub fn palette(&self, name: &str) -> Result<Palette, Error> {
+        Palette::from_bytes(&self.typed_lump(name, LumpType::Palette)?)
+    }
+
+    /// Decode the named `LumpType::Miptex` lump.
+    pub fn miptex(&self, name: &str) -> Result<MipTex, Error> {
+        MipTex::from_bytes(&self.typed_lump(name, LumpType::Miptex)?)
+    }
+
+    /// Decode the headerless `conchars` lump (the console font).
+    ///
+    /// In `gfx.wad` this is marked as `LumpType::Miptex`, even though it is
+    /// just a 128x128 block of pixels.
+    pub fn conchars(&self) -> Result<QPic, Error> {
+        QPic::from_conchars(&self.typed_lump("conchars", LumpType::Miptex)?)
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -115,23 +228,35 @@ struct WadInfo {
 }
 
 impl WadInfo {
-    fn from(data: &[u8]) -> Result<Self, Error> {
+    fn from(data: &[u8]) -> Result<Self, WadError> {
         const HEADER_SIZE: usize = 4+4+4;
         const HEADER_SLICE: Range<usize> = 0..4;
         const NUM_LUMPS_SLICE: Range<usize> = 4..8;
         const OFFSET_SLICE: Range<usize> = 8..12;
 
         if data.len() < HEADER_SIZE {
-            bail!("Invalid wad header: too short");
+            return Err(WadError::OutOfBounds { at: 0, len: HEADER_SIZE });
         }
         if &data[HEADER_SLICE] != b"WAD2" {
-            bail!("Invalid wad header: no WAD2 id");
+            return Err(WadError::BadMagic);
         }
 
-        let num_lumps = usize::try_from_temp(
-            LittleEndian::read_i32(&data[NUM_LUMPS_SLICE]))?;
-        let lump_table_offset = usize::try_from_temp(
-            LittleEndian::read_i32(&data[OFFSET_SLICE]))?;
+        let num_lumps = LittleEndian::read_i32(&data[NUM_LUMPS_SLICE]);
+        if num_lumps < 0 {
+            return Err(WadError::NegativeCount {
+                at: NUM_LUMPS_SLICE.start,
+                value: num_lumps,
+            });
+        }
+        let lump_table_offset = LittleEndian::read_i32(&data[OFFSET_SLICE]);
+        if lump_table_offset < 0 {
+            return Err(WadError::NegativeOffset {
+                at: OFFSET_SLICE.start,
+                value: lump_table_offset,
+            });
+        }
+        let num_lumps = num_lumps as usize;
+        let lump_table_offset = lump_table_offset as usize;
         Ok(Self {
             num_lumps,
             lump_table_offset,
@@ -157,10 +282,13 @@ pub struct LumpInfo {
 const LUMP_INFO_SIZE: usize = 4+4+4+1+1+1+1+16;
 
 impl LumpInfo {
-    fn from(data: &[u8]) -> Result<Self, Error> {
+    /// Parse a `LumpInfo` from the lump table.
+    ///
+    /// `at` is the offset of `data` within the wad; it is only used to
+    /// describe errors.
+    fn from(data: &[u8], at: usize) -> Result<Self, WadError> {
         if data.len() != LUMP_INFO_SIZE {
-            bail!("Invalid lump info size: expected {} got {}",
-                  LUMP_INFO_SIZE, data.len());
+            return Err(WadError::OutOfBounds { at, len: LUMP_INFO_SIZE });
         }
 
         const FILE_POS_SLICE: Range<usize> = 0..4;
@@ -171,22 +299,41 @@ impl LumpInfo {
         // Skip 2 padding bytes.
         const NAME_SLICE: Range<usize> = 16..32;
 
-        let file_pos = usize::try_from_temp(
-            LittleEndian::read_i32(&data[FILE_POS_SLICE]))?;
-        let disk_size = usize::try_from_temp(
-            LittleEndian::read_i32(&data[DISK_SIZE_SLICE]))?;
-        let size = usize::try_from_temp(
-            LittleEndian::read_i32(&data[UNCOMPRESSED_SIZE_SLICE]))?;
+        let file_pos = LittleEndian::read_i32(&data[FILE_POS_SLICE]);
+        if file_pos < 0 {
+            return Err(WadError::NegativeOffset {
+                at: at + FILE_POS_SLICE.start,
+                value: file_pos,
+            });
+        }
+        let disk_size = LittleEndian::read_i32(&data[DISK_SIZE_SLICE]);
+        if disk_size < 0 {
+            return Err(WadError::NegativeSize {
+                at: at + DISK_SIZE_SLICE.start,
+                value: disk_size,
+            });
+        }
+        let size = LittleEndian::read_i32(&data[UNCOMPRESSED_SIZE_SLICE]);
+        if size < 0 {
+            return Err(WadError::NegativeSize {
+                at: at + UNCOMPRESSED_SIZE_SLICE.start,
+                value: size,
+            });
+        }
         let lump_type = LumpType::try_from(
-            data[TYPE_INDEX])?;
+            data[TYPE_INDEX], at + TYPE_INDEX)?;
         let compression = Compression::try_from(
-            data[COMPRESSION_INDEX])?;
-        let name = util::cstr_buf_to_string(&data[NAME_SLICE])?;
+            data[COMPRESSION_INDEX], at + COMPRESSION_INDEX)?;
+        let name = util::cstr_buf_to_string(&data[NAME_SLICE])
+            .map_err(|error| WadError::BadLumpName {
+                at: at + NAME_SLICE.start,
+                error,
+            })?;
 
         Ok(Self {
-            file_pos,
-            disk_size,
-            size,
+            file_pos: file_pos as usize,
+            disk_size: disk_size as usize,
+            size: size as usize,
             lump_type,
             compression,
             name,
@@ -230,11 +377,11 @@ pub enum Compression {
 }
 
 impl Compression {
-    fn try_from(n: u8) -> Result<Self, Error> {
+    fn try_from(n: u8, at: usize) -> Result<Self, WadError> {
         match n {
             0 => Ok(Compression::None),
             1 => Ok(Compression::Lzss),
-            _ => Err(format_err!("Invalid compression type: {}", n)),
+            _ => Err(WadError::BadCompression { at, value: n }),
         }
     }
 }
@@ -258,7 +405,7 @@ pub enum LumpType {
 }
 
 impl LumpType {
-    fn try_from(n: u8) -> Result<Self, Error> {
+    fn try_from(n: u8, at: usize) -> Result<Self, WadError> {
         match n {
             0 => Ok(LumpType::None),
             1 => Ok(LumpType::Label),
@@ -267,7 +414,152 @@ impl LumpType {
             66 => Ok(LumpType::Qpic),
             67 => Ok(LumpType::Sound),
             68 => Ok(LumpType::Miptex),
-            _ => Err(format_err!("Invalid lump type: {}", n))
+            _ => Err(WadError::BadLumpType { at, value: n })
+        }
+    }
+}
+
+/// The ways that parsing a wad, or looking up a lump within it, can fail.
+///
+/// Errors about a particular value in the wad have `at`, the offset of that
+/// value within the wad.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WadError {
+    /// The wad file doesn't exist.
+    NotFound {
+        /// The name of the file within the search path.
+        path: String,
+    },
+    /// The wad file exists, but isn't a valid wad.
+    Invalid {
+        /// The name of the file within the search path.
+        path: String,
+        /// What is wrong with it.
+        error: Box<WadError>,
+    },
+    /// The data doesn't start with `WAD2`, so isn't a wad.
+    BadMagic,
+    /// Part of the wad extends past the end of the data.
+    OutOfBounds {
+        /// The offset of the part.
+        at: usize,
+        /// The size of the part, in bytes.
+        len: usize,
+    },
+    /// The number of lumps is negative.
+    NegativeCount {
+        /// Where the count is stored.
+        at: usize,
+        /// The count.
+        value: i32,
+    },
+    /// An offset is negative.
+    NegativeOffset {
+        /// Where the offset is stored.
+        at: usize,
+        /// The offset.
+        value: i32,
+    },
+    /// A size is negative.
+    NegativeSize {
+        /// Where the size is stored.
+        at: usize,
+        /// The size.
+        value: i32,
+    },
+    /// A lump has a type that isn't one of the `LumpType`s.
+    BadLumpType {
+        /// Where the type is stored.
+        at: usize,
+        /// The type.
+        value: u8,
+    },
+    /// A lump has a compression that isn't one of the `Compression`s.
+    BadCompression {
+        /// Where the compression is stored.
+        at: usize,
+        /// The compression.
+        value: u8,
+    },
+    /// A lump name isn't a valid string.
+    BadLumpName {
+        /// Where the name is stored.
+        at: usize,
+        /// Why the name isn't valid.
+        error: CStrError,
+    },
+    /// There's no lump with the given name.
+    NoSuchLump(String),
+    /// There's no lump with the given number.
+    BadLumpNumber(usize),
+    /// The lump doesn't have the type that was asked for.
+    WrongLumpType {
+        /// The name of the lump.
+        name: String,
+        /// The type that was asked for.
+        expected: LumpType,
+        /// The lump's type.
+        found: LumpType,
+    },
+    /// An uncompressed lump has different sizes on disk and in memory.
+    SizeMismatch {
+        /// The name of the lump.
+        name: String,
+        /// The lump's size on disk.
+        disk_size: usize,
+        /// The lump's size in memory.
+        size: usize,
+    },
+}
+
+impl fmt::Display for WadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WadError::NotFound { ref path } =>
+                write!(f, "No such wad file {}", path),
+            WadError::Invalid { ref path, ref error } =>
+                write!(f, "{}: {}", path, error),
+            WadError::BadMagic =>
+                write!(f, "Invalid wad header: no WAD2 id"),
+            WadError::OutOfBounds { at, len } =>
+                write!(f, "Invalid wad: {} bytes at byte {} are past the end",
+                       len, at),
+            WadError::NegativeCount { at, value } =>
+                write!(f, "Invalid wad: negative count {} at byte {}",
+                       value, at),
+            WadError::NegativeOffset { at, value } =>
+                write!(f, "Invalid wad: negative offset {} at byte {}",
+                       value, at),
+            WadError::NegativeSize { at, value } =>
+                write!(f, "Invalid wad: negative size {} at byte {}",
+                       value, at),
+            WadError::BadLumpType { at, value } =>
+                write!(f, "Invalid lump type {} at byte {}", value, at),
+            WadError::BadCompression { at, value } =>
+                write!(f, "Invalid compression type {} at byte {}",
+                       value, at),
+            WadError::BadLumpName { at, ref error } =>
+                write!(f, "Invalid lump name at byte {}: {}", at, error),
+            WadError::NoSuchLump(ref name) =>
+                write!(f, "No lump named {}", name),
+            WadError::BadLumpNumber(n) =>
+                write!(f, "Bad lump number: {}", n),
+            WadError::WrongLumpType { ref name, expected, found } =>
+                write!(f, "Lump {} has type {:?}, expected {:?}",
+                       name, found, expected),
+            WadError::SizeMismatch { ref name, disk_size, size } =>
+                write!(f, "Uncompressed lump {} has disk size {} but size {}",
+                       name, disk_size, size),
+        }
+    }
+}
+
+impl error::Error for WadError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            WadError::Invalid { ref error, .. } => Some(&**error),
+            WadError::BadLumpName { ref error, .. } => Some(error),
+            _ => None,
         }
     }
 }
@@ -276,20 +568,55 @@ impl LumpType {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use parms::Parms;
     use test_common as common;
 
-    // These tests make use of a FileSys of a full version of standard Quake 1.
-
     /// Just see if we can load something from a wad.
     #[test]
     fn wad_load() {
-        let parms = Parms::new(
-            vec!["-basedir".into(), common::base_dir().to_string_lossy().to_string()],
-            "cwd".into());
-        let mut fs = FileSys::new(&parms).unwrap();
+        let fixture = common::fixture();
+        let fs = FileSys::new(&fixture.parms()).unwrap();
+
+        let wad = Wad::load_from_file("gfx.wad", &fs).unwrap();
+
+        // This is the "loading" icon.
+        let info = wad.lump_info("disc").unwrap();
+        assert_eq!(info.lump_type(), LumpType::Qpic);
+        assert_eq!(info.compression(), Compression::None);
+
+        let data = wad.data_for_lump_named("disc").unwrap();
+        assert_eq!(data.len(), info.size());
+    }
+
+    /// A missing or corrupt wad can be told apart without looking at the
+    /// message.
+    #[test]
+    fn load_errors() {
+        let fixture = common::FixtureBuilder::new()
+            .pak(vec![("bad.wad", b"WAD3".to_vec())])
+            .build();
+        let fs = FileSys::new(&fixture.parms()).unwrap();
+        let load = |name| {
+            Wad::load_from_file(name, &fs).unwrap_err()
+                .downcast::<WadError>().unwrap()
+        };
+
+        assert_eq!(load("nope.wad"),
+                   WadError::NotFound { path: "nope.wad".into() });
+        assert_eq!(load("bad.wad"), WadError::Invalid {
+            path: "bad.wad".into(),
+            error: Box::new(WadError::OutOfBounds { at: 0, len: 12 }),
+        });
+    }
+
+    /// As `wad_load`, but using a FileSys of a full version of standard
+    /// Quake 1.
+    #[cfg(feature = "retail-data")]
+    #[test]
+    fn retail_wad_load() {
+        let parms = common::parms_for(&common::base_dir());
+        let fs = FileSys::new(&parms).unwrap();
 
-        let wad = Wad::load_from_file("gfx.wad", &mut fs).unwrap();
+        let wad = Wad::load_from_file("gfx.wad", &fs).unwrap();
 
         // This is the "loading" icon.
         let info = wad.lump_info("disc").unwrap();
@@ -299,4 +626,140 @@ mod tests {
         let data = wad.data_for_lump_named("disc").unwrap();
         assert_eq!(data.len(), info.size());
     }
+
+    #[test]
+    fn decompressed_lump() {
+        let wad = Wad::from_bytes(common::wad_bytes(&[
+            ("plain", 66, 0, 3, b"abc"),
+            ("packed", 66, 1, 9, &[0x07, b'a', b'b', b'c', 0xee, 0xf3]),
+        ])).unwrap();
+
+        let plain = wad.decompressed_lump("plain").unwrap();
+        assert_eq!(&plain[..], b"abc");
+
+        assert_eq!(wad.lump_info("packed").unwrap().compression(),
+                   Compression::Lzss);
+        let packed = wad.decompressed_lump("packed").unwrap();
+        assert_eq!(&packed[..], b"abcabcabc");
+        assert_eq!(packed.len(), wad.lump_info("packed").unwrap().size());
+    }
+
+    #[test]
+    fn typed_lumps() {
+        let pic = [1, 0, 0, 0, 2, 0, 0, 0, 7, 8];
+        let palette = [3; lumps::PALETTE_SIZE];
+        let conchars = [4; lumps::CONCHARS_SIZE * lumps::CONCHARS_SIZE];
+        let wad = Wad::from_bytes(common::wad_bytes(&[
+            ("pic", 66, 0, pic.len(), &pic),
+            ("palette", 64, 0, palette.len(), &palette),
+            ("conchars", 68, 0, conchars.len(), &conchars),
+        ])).unwrap();
+
+        let qpic = wad.qpic("pic").unwrap();
+        assert_eq!((qpic.width, qpic.height), (1, 2));
+        assert_eq!(qpic.pixels, &[7, 8]);
+        assert_eq!(wad.palette("palette").unwrap().rgb(9), [3, 3, 3]);
+        let chars = wad.conchars().unwrap();
+        assert_eq!((chars.width, chars.height), (128, 128));
+
+        // Wrong lump types.
+        assert!(wad.qpic("palette").is_err());
+        assert!(wad.palette("pic").is_err());
+        assert!(wad.miptex("pic").is_err());
+        assert_eq!(wad.qpic("palette").unwrap_err().downcast::<WadError>()
+                   .unwrap(),
+                   WadError::WrongLumpType {
+                       name: "palette".into(),
+                       expected: LumpType::Qpic,
+                       found: LumpType::Palette,
+                   });
+    }
+
+    #[test]
+    fn parse_errors() {
+        let good = common::wad_bytes(&[("pic", 66, 0, 3, b"abc")]);
+        // The lump table is after the header and the lump data.
+        let info_at = 12 + 3;
+        let parse = |data: &[u8]| Wad::from_bytes(data.to_vec()).unwrap_err();
+
+        assert_eq!(parse(&good[..11]), WadError::OutOfBounds { at: 0, len: 12 });
+
+        let mut data = good.clone();
+        data[0..4].copy_from_slice(b"PACK");
+        assert_eq!(parse(&data), WadError::BadMagic);
+
+        let mut data = good.clone();
+        LittleEndian::write_i32(&mut data[4..8], -1);
+        assert_eq!(parse(&data), WadError::NegativeCount { at: 4, value: -1 });
+
+        let mut data = good.clone();
+        LittleEndian::write_i32(&mut data[info_at+4..info_at+8], -3);
+        assert_eq!(parse(&data),
+                   WadError::NegativeSize { at: info_at + 4, value: -3 });
+
+        let mut data = good.clone();
+        data[info_at+12] = 99;
+        assert_eq!(parse(&data),
+                   WadError::BadLumpType { at: info_at + 12, value: 99 });
+
+        let mut data = good.clone();
+        data[info_at+13] = 2;
+        assert_eq!(parse(&data),
+                   WadError::BadCompression { at: info_at + 13, value: 2 });
+
+        let mut data = good.clone();
+        for b in &mut data[info_at+16..info_at+32] {
+            *b = b'x';
+        }
+        assert_eq!(parse(&data), WadError::BadLumpName {
+            at: info_at + 16,
+            error: CStrError::NoNul,
+        });
+
+        let wad = Wad::from_bytes(good).unwrap();
+        assert_eq!(wad.lump_info("nope").unwrap_err(),
+                   WadError::NoSuchLump("nope".into()));
+    }
+
+    /// Tables and lumps that don't fit in the wad are errors, not panics.
+    #[test]
+    fn bounds() {
+        let good = common::wad_bytes(&[
+            ("a", 66, 0, 3, b"abc"),
+            ("b", 66, 0, 2, b"de"),
+        ]);
+        let info_at = 12 + 5;
+        let parse = |data: &[u8]| Wad::from_bytes(data.to_vec()).unwrap_err();
+
+        // Truncated lump table.
+        assert_eq!(parse(&good[..good.len()-1]),
+                   WadError::OutOfBounds { at: info_at, len: 64 });
+
+        // Huge lump table.
+        let mut data = good.clone();
+        LittleEndian::write_i32(&mut data[4..8], i32::MAX);
+        assert_eq!(parse(&data), WadError::OutOfBounds {
+            at: info_at,
+            len: i32::MAX as usize * LUMP_INFO_SIZE,
+        });
+        let mut data = good.clone();
+        LittleEndian::write_i32(&mut data[8..12], i32::MAX);
+        assert_eq!(parse(&data),
+                   WadError::OutOfBounds { at: i32::MAX as usize, len: 64 });
+
+        // Lump data past the end.
+        let mut data = good.clone();
+        LittleEndian::write_i32(&mut data[info_at+4..info_at+8], 1000);
+        assert_eq!(parse(&data), WadError::OutOfBounds { at: 12, len: 1000 });
+        let mut data = good.clone();
+        LittleEndian::write_i32(&mut data[info_at+32..info_at+36], i32::MAX);
+        assert_eq!(parse(&data),
+                   WadError::OutOfBounds { at: i32::MAX as usize, len: 2 });
+
+        // Lump numbers are checked too.
+        let wad = Wad::from_bytes(good).unwrap();
+        assert_eq!(wad.data_for_lump_num(1).unwrap(), b"de");
+        assert_eq!(wad.data_for_lump_num(2).unwrap_err(),
+                   WadError::BadLumpNumber(2));
+    }
 }
@@ -0,0 +1,469 @@
+// Copyright (C) 1996-1997 Id Software, Inc.
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+//
+// See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software
+// Foundation, Inc., 59 Temple Place - Suite 330, Boston, MA  02111-1307, USA.
+
+// Modified by Adrian Chan, March 2018
+// The hunk and cache parts of zone.c
+
+//! Memory budgeting, in the style of Quake's hunk and cache.
+//!
+//! Quake grabs one fixed-size block of memory at startup, and never asks for
+//! more.  This is the hunk.  Memory is allocated from either end of the hunk,
+//! and is freed by going back to an earlier "mark" -- everything allocated
+//! after the mark is freed at once.  By convention, the low end is used for
+//! things that last for the whole game or level, and the high end for
+//! temporary things.
+//!
+//! Whatever space is left between the low and high ends is used for the
+//! cache.  Cache entries are purgeable: they are thrown away, least recently
+//! used first, whenever the hunk or the cache needs the space.
+//!
+//! Here, the hunk is a real block of memory, and allocations are `HunkBlock`
+//! handles into it.  Cache entries are stored separately, but they still
+//! count against the size of the hunk, so the total memory used is capped in
+//! the same way.
+
+use failure::Error;
+
+use defs;
+use parms::Parms;
+
+
+/// A fixed-size block of memory, with allocations made from either end and an
+/// LRU cache in the space between.
+pub struct Hunk {
+    memory: Vec<u8>,
+    /// Bytes allocated from the low end.
+    low_used: usize,
+    /// Bytes allocated from the high end.
+    high_used: usize,
+    /// The zone, if one was reserved by `for_parms()`.
+    zone: Option<HunkBlock>,
+    cache: Cache,
+    /// The id to give the next allocation.
+    next_id: u64,
+    /// The ids of the live allocations from each end, oldest first.
+    low_ids: Vec<u64>,
+    high_ids: Vec<u64>,
+}
+
+/// A handle to memory allocated from the `Hunk`.
+///
+/// The handle stops being valid once the hunk is freed back to a mark that
+/// was taken before the allocation was made.  Each allocation has its own id,
+/// so a stale handle is still caught after its memory is allocated again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HunkBlock {
+    start: usize,
+    len: usize,
+    end: HunkEnd,
+    id: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum HunkEnd {
+    Low,
+    High,
+}
+
+/// A position to free the low end of the hunk back to, and the id of the
+/// first allocation made after it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LowMark(usize, u64);
+
+/// A position to free the high end of the hunk back to, and the id of the
+/// first allocation made after it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HighMark(usize, u64);
+
+/// Purgeable entries, stored in the free space of the `Hunk`.
+struct Cache {
+    /// Total bytes used by all entries.
+    used: usize,
+    entries: Vec<CacheEntry>,
+    /// Incremented on each use, so that entries can be ordered by last use.
+    clock: u64,
+}
+
+struct CacheEntry {
+    name: String,
+    data: Vec<u8>,
+    last_used: u64,
+}
+
+impl HunkBlock {
+    /// The size of the block, in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Is the block zero bytes long?
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Hunk {
+    /// Create a hunk of exactly `size` bytes, which are all free.
+    pub fn new(size: usize) -> Self {
+        Self {
+            memory: vec![0; size],
+            low_used: 0,
+            high_used: 0,
+            zone: None,
+            cache: Cache {
+                used: 0,
+                entries: Vec::new(),
+                clock: 0,
+            },
+            next_id: 0,
+            low_ids: Vec::new(),
+            high_ids: Vec::new(),
+        }
+    }
+
+    /// Create a hunk using the `-heapsize` and `-zone` parameters, and reserve
+    /// the zone from the low end of the hunk.
+    pub fn for_parms(parms: &Parms) -> Result<Self, Error> {
+        let size = parms.heap_size()?;
+        if size < defs::MINIMUM_HEAP_SIZE {
+            bail!("Only {} bytes of heap, need at least {}",
+                  size, defs::MINIMUM_HEAP_SIZE);
+        }
+        let mut hunk = Self::new(size);
+        let zone = hunk.alloc_low(parms.zone_size()?)?;
+        hunk.zone = Some(zone);
+        Ok(hunk)
+    }
+
+    /// The total size of the hunk, in bytes.
+    pub fn size(&self) -> usize {
+        self.memory.len()
+    }
+
+    /// The number of bytes not used by the low end, high end or cache.
+    pub fn free_space(&self) -> usize {
+        self.size() - self.low_used - self.high_used - self.cache.used
+    }
+
+    /// The memory reserved for the zone by `for_parms()`, if any.
+    pub fn zone(&self) -> Option<HunkBlock> {
+        self.zone
+    }
+
+    /// Allocate `size` zeroed bytes from the low end of the hunk.
+    ///
+    /// Cache entries are purged if that's needed to make space.
+    pub fn alloc_low(&mut self, size: usize) -> Result<HunkBlock, Error> {
+        self.make_space(size)
+            .map_err(|_| format_err!("Hunk_AllocName: failed on {} bytes",
+                                     size))?;
+        let start = self.low_used;
+        self.low_used += size;
+        self.zero(start, size);
+        let id = self.new_id();
+        self.low_ids.push(id);
+        Ok(HunkBlock { start, len: size, end: HunkEnd::Low, id })
+    }
+
+    /// Allocate `size` zeroed bytes from the high end of the hunk.
+    ///
+    /// Cache entries are purged if that's needed to make space.
+    pub fn alloc_high(&mut self, size: usize) -> Result<HunkBlock, Error> {
+        self.make_space(size)
+            .map_err(|_| format_err!("Hunk_HighAlloc: failed on {} bytes",
+                                     size))?;
+        self.high_used += size;
+        let start = self.size() - self.high_used;
+        self.zero(start, size);
+        let id = self.new_id();
+        self.high_ids.push(id);
+        Ok(HunkBlock { start, len: size, end: HunkEnd::High, id })
+    }
+
+    /// The current position of the low end.
+    pub fn low_mark(&self) -> LowMark {
+        LowMark(self.low_used, self.next_id)
+    }
+
+    /// Free everything allocated from the low end since `mark` was taken.
+    pub fn free_to_low_mark(&mut self, mark: LowMark) -> Result<(), Error> {
+        if mark.0 > self.low_used {
+            bail!("Hunk_FreeToLowMark: bad mark {}", mark.0);
+        }
+        self.low_used = mark.0;
+        free_ids(&mut self.low_ids, mark.1);
+        Ok(())
+    }
+
+    /// The current position of the high end.
+    pub fn high_mark(&self) -> HighMark {
+        HighMark(self.high_used, self.next_id)
+    }
+
+    /// Free everything allocated from the high end since `mark` was taken.
+    pub fn free_to_high_mark(&mut self, mark: HighMark) -> Result<(), Error> {
+        if mark.0 > self.high_used {
+            bail!("Hunk_FreeToHighMark: bad mark {}", mark.0);
+        }
+        self.high_used = mark.0;
+        free_ids(&mut self.high_ids, mark.1);
+        Ok(())
+    }
+
+    /// The contents of an allocated block.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the block has been freed.
+    pub fn block(&self, block: &HunkBlock) -> &[u8] {
+        self.check_block(block);
+        &self.memory[block.start..block.start+block.len]
+    }
+
+    /// The contents of an allocated block, for writing.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the block has been freed.
+    pub fn block_mut(&mut self, block: &HunkBlock) -> &mut [u8] {
+        self.check_block(block);
+        &mut self.memory[block.start..block.start+block.len]
+    }
+
+    /// Get the named cache entry, if it hasn't been purged.
+    pub fn cache_check(&mut self, name: &str) -> Option<&[u8]> {
+        self.cache.clock += 1;
+        let clock = self.cache.clock;
+        self.cache.entries.iter_mut()
+            .find(|e| e.name == name)
+            .map(|e| {
+                e.last_used = clock;
+                &e.data[..]
+            })
+    }
+
+    /// Add an entry to the cache, replacing any entry with the same name.
+    ///
+    /// Less recently used entries are purged if that's needed to make space.
+    pub fn cache_alloc(&mut self, name: &str, data: Vec<u8>)
+        -> Result<&[u8], Error>
+    {
+        self.cache_free(name);
+        let size = data.len();
+        self.make_space(size)
+            .map_err(|_| format_err!("Cache_Alloc: failed on {} bytes for {}",
+                                     size, name))?;
+
+        self.cache.clock += 1;
+        self.cache.used += size;
+        self.cache.entries.push(CacheEntry {
+            name: name.to_string(),
+            data,
+            last_used: self.cache.clock,
+        });
+        Ok(&self.cache.entries.last().unwrap().data[..])
+    }
+
+    /// Remove the named entry from the cache, if it exists.
+    pub fn cache_free(&mut self, name: &str) {
+        if let Some(i) = self.cache.entries.iter().position(|e| e.name == name) {
+            let entry = self.cache.entries.swap_remove(i);
+            self.cache.used -= entry.data.len();
+        }
+    }
+
+    /// Remove every entry from the cache.
+    pub fn cache_flush(&mut self) {
+        self.cache.entries.clear();
+        self.cache.used = 0;
+    }
+
+    /// Purge cache entries, least recently used first, until there are at
+    /// least `size` bytes free.
+    fn make_space(&mut self, size: usize) -> Result<(), ()> {
+        if self.size() - self.low_used - self.high_used < size {
+            // Not enough space even if the cache was empty.
+            return Err(());
+        }
+        while self.free_space() < size {
+            let oldest = self.cache.entries.iter()
+                .enumerate()
+                .min_by_key(|&(_, e)| e.last_used)
+                .map(|(i, _)| i)
+                .expect("cache is using space but has no entries");
+            let entry = self.cache.entries.swap_remove(oldest);
+            self.cache.used -= entry.data.len();
+        }
+        Ok(())
+    }
+
+    fn zero(&mut self, start: usize, len: usize) {
+        for b in &mut self.memory[start..start+len] {
+            *b = 0;
+        }
+    }
+
+    fn new_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    fn check_block(&self, block: &HunkBlock) {
+        let ids = match block.end {
+            HunkEnd::Low => &self.low_ids,
+            HunkEnd::High => &self.high_ids,
+        };
+        let valid = ids.binary_search(&block.id).is_ok();
+        assert!(valid, "use of freed hunk block {:?}", block);
+    }
+}
+
+/// Forget the ids of the allocations made since a mark was taken.
+fn free_ids(ids: &mut Vec<u64>, first_freed: u64) {
+    let kept = ids.iter().take_while(|&&id| id < first_freed).count();
+    ids.truncate(kept);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic;
+
+    #[test]
+    fn low_and_high() {
+        let mut hunk = Hunk::new(100);
+        let low = hunk.alloc_low(30).unwrap();
+        let high = hunk.alloc_high(40).unwrap();
+        assert_eq!(hunk.free_space(), 30);
+        assert!(hunk.alloc_low(31).is_err());
+        assert!(hunk.alloc_high(31).is_err());
+
+        hunk.block_mut(&low).copy_from_slice(&[1; 30]);
+        hunk.block_mut(&high).copy_from_slice(&[2; 40]);
+        assert_eq!(hunk.block(&low), &[1; 30][..]);
+        assert_eq!(hunk.block(&high), &[2; 40][..]);
+    }
+
+    #[test]
+    fn marks() {
+        let mut hunk = Hunk::new(100);
+        hunk.alloc_low(10).unwrap();
+        let low_mark = hunk.low_mark();
+        let high_mark = hunk.high_mark();
+        hunk.alloc_low(50).unwrap();
+        hunk.alloc_high(40).unwrap();
+        assert_eq!(hunk.free_space(), 0);
+
+        hunk.free_to_low_mark(low_mark).unwrap();
+        hunk.free_to_high_mark(high_mark).unwrap();
+        assert_eq!(hunk.free_space(), 90);
+
+        // Memory is zeroed when it is reused.
+        let block = hunk.alloc_low(5).unwrap();
+        assert_eq!(hunk.block(&block), &[0; 5][..]);
+
+        assert!(hunk.free_to_low_mark(LowMark(50, 0)).is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn freed_block() {
+        let mut hunk = Hunk::new(100);
+        let mark = hunk.low_mark();
+        let block = hunk.alloc_low(10).unwrap();
+        hunk.free_to_low_mark(mark).unwrap();
+        hunk.block(&block);
+    }
+
+    /// A stale handle is caught even once its memory is in use again.
+    #[test]
+    fn reallocated_block() {
+        let mut hunk = Hunk::new(100);
+        let kept = hunk.alloc_low(5).unwrap();
+        let low_mark = hunk.low_mark();
+        let high_mark = hunk.high_mark();
+        let stale_low = hunk.alloc_low(10).unwrap();
+        let stale_high = hunk.alloc_high(10).unwrap();
+        hunk.free_to_low_mark(low_mark).unwrap();
+        hunk.free_to_high_mark(high_mark).unwrap();
+        let new_low = hunk.alloc_low(10).unwrap();
+        let new_high = hunk.alloc_high(10).unwrap();
+
+        hunk.block(&kept);
+        hunk.block(&new_low);
+        hunk.block(&new_high);
+        for stale in &[stale_low, stale_high] {
+            let result = panic::catch_unwind(
+                panic::AssertUnwindSafe(|| { hunk.block(stale); }));
+            assert!(result.is_err(), "{:?} wasn't caught", stale);
+        }
+    }
+
+    #[test]
+    fn cache_lru() {
+        let mut hunk = Hunk::new(100);
+        hunk.cache_alloc("a", vec![1; 30]).unwrap();
+        hunk.cache_alloc("b", vec![2; 30]).unwrap();
+        hunk.cache_alloc("c", vec![3; 30]).unwrap();
+        assert_eq!(hunk.free_space(), 10);
+
+        // Use "a", so that "b" is the least recently used.
+        assert_eq!(hunk.cache_check("a"), Some(&[1; 30][..]));
+        hunk.cache_alloc("d", vec![4; 30]).unwrap();
+        assert_eq!(hunk.cache_check("b"), None);
+        assert!(hunk.cache_check("a").is_some());
+        assert!(hunk.cache_check("c").is_some());
+        assert!(hunk.cache_check("d").is_some());
+
+        // Too big, even if the cache was empty.
+        assert!(hunk.cache_alloc("e", vec![5; 101]).is_err());
+    }
+
+    #[test]
+    fn hunk_purges_cache() {
+        let mut hunk = Hunk::new(100);
+        hunk.cache_alloc("a", vec![1; 40]).unwrap();
+        hunk.cache_alloc("b", vec![2; 40]).unwrap();
+        hunk.alloc_low(50).unwrap();
+        assert_eq!(hunk.cache_check("a"), None);
+        assert!(hunk.cache_check("b").is_some());
+
+        // Never purges more than it needs to, and fails if the cache can't
+        // help.
+        assert!(hunk.alloc_high(61).is_err());
+        assert!(hunk.cache_check("b").is_some());
+    }
+
+    #[test]
+    fn for_parms() {
+        let parms = Parms::new(
+            vec!["-heapsize".into(), "6000".into(),
+                 "-zone".into(), "64".into()],
+            "cwd".into());
+        let hunk = Hunk::for_parms(&parms).unwrap();
+        assert_eq!(hunk.size(), 6000 * 1024);
+        assert_eq!(hunk.zone().unwrap().len(), 64 * 1024);
+        assert_eq!(hunk.free_space(), (6000 - 64) * 1024);
+
+        let parms = Parms::new(
+            vec!["-heapsize".into(), "1024".into()],
+            "cwd".into());
+        assert!(Hunk::for_parms(&parms).is_err());
+    }
+}